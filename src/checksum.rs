@@ -0,0 +1,278 @@
+use crate::verify::sha256_hex;
+use clap::ValueEnum;
+
+/// Hash used for `checksums.txt`, named on its `# algorithm:` header line.
+/// SHA-1, MD5 and BLAKE3 are only offered to match existing verification
+/// tooling; they are implemented here rather than pulled in as three more
+/// dependencies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Sha1,
+    Md5,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::from_str(name.trim(), true).ok()
+    }
+
+    /// Guesses the algorithm of a file without a header from the length of
+    /// its hex digests. BLAKE3 digests are as long as SHA-256 ones, so they
+    /// are only recognized through the header.
+    pub fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            64 => Some(ChecksumAlgorithm::Sha256),
+            40 => Some(ChecksumAlgorithm::Sha1),
+            32 => Some(ChecksumAlgorithm::Md5),
+            _ => None,
+        }
+    }
+
+    /// Lowercase hex digest of `bytes`.
+    pub fn hex(self, bytes: &[u8]) -> String {
+        let digest = match self {
+            ChecksumAlgorithm::Sha256 => return sha256_hex(bytes),
+            ChecksumAlgorithm::Sha1 => sha1(bytes).to_vec(),
+            ChecksumAlgorithm::Md5 => md5(bytes).to_vec(),
+            ChecksumAlgorithm::Blake3 => blake3(bytes).to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Pads `bytes` into 64-byte blocks the MD5/SHA-1 way: a 1 bit, zeros, and
+/// the bit length, little- or big-endian.
+fn md_padded(bytes: &[u8], big_endian: bool) -> Vec<u8> {
+    let bits = (bytes.len() as u64).wrapping_mul(8);
+    let mut data = bytes.to_vec();
+    data.push(0x80);
+    while data.len() % 64 != 56 {
+        data.push(0);
+    }
+    data.extend_from_slice(&if big_endian { bits.to_be_bytes() } else { bits.to_le_bytes() });
+    data
+}
+
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    for block in md_padded(bytes, true).chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..40 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(wi);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, t);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut out = [0u8; 20];
+    for (chunk, word) in out.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+fn md5(bytes: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let k: Vec<u32> = (0..64).map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32).collect();
+    let mut h: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+    for block in md_padded(bytes, false).chunks(64) {
+        let m: Vec<u32> = block.chunks(4).map(|w| u32::from_le_bytes(w.try_into().unwrap())).collect();
+        let [mut a, mut b, mut c, mut d] = h;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(k[i])
+                .wrapping_add(m[g])
+                .rotate_left(SHIFTS[(i / 16) * 4 + i % 4]);
+            (a, d, c, b) = (d, c, b, b.wrapping_add(rotated));
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut out = [0u8; 16];
+    for (chunk, word) in out.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+const BLAKE3_IV: [u32; 8] = [
+    0x6A09_E667, 0xBB67_AE85, 0x3C6E_F372, 0xA54F_F53A, 0x510E_527F, 0x9B05_688C, 0x1F83_D9AB, 0x5BE0_CD19,
+];
+const BLAKE3_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+const BLAKE3_CHUNK_LEN: usize = 1024;
+const CHUNK_START: u32 = 1;
+const CHUNK_END: u32 = 2;
+const PARENT: u32 = 4;
+const ROOT: u32 = 8;
+
+fn blake3_compress(cv: &[u32; 8], block: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 16] {
+    fn g(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+        s[a] = s[a].wrapping_add(s[b]).wrapping_add(x);
+        s[d] = (s[d] ^ s[a]).rotate_right(16);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_right(12);
+        s[a] = s[a].wrapping_add(s[b]).wrapping_add(y);
+        s[d] = (s[d] ^ s[a]).rotate_right(8);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_right(7);
+    }
+    let mut s = [0u32; 16];
+    s[..8].copy_from_slice(cv);
+    s[8..12].copy_from_slice(&BLAKE3_IV[..4]);
+    s[12] = counter as u32;
+    s[13] = (counter >> 32) as u32;
+    s[14] = block_len;
+    s[15] = flags;
+    let mut m = *block;
+    for _ in 0..7 {
+        g(&mut s, 0, 4, 8, 12, m[0], m[1]);
+        g(&mut s, 1, 5, 9, 13, m[2], m[3]);
+        g(&mut s, 2, 6, 10, 14, m[4], m[5]);
+        g(&mut s, 3, 7, 11, 15, m[6], m[7]);
+        g(&mut s, 0, 5, 10, 15, m[8], m[9]);
+        g(&mut s, 1, 6, 11, 12, m[10], m[11]);
+        g(&mut s, 2, 7, 8, 13, m[12], m[13]);
+        g(&mut s, 3, 4, 9, 14, m[14], m[15]);
+        m = BLAKE3_PERMUTATION.map(|i| m[i]);
+    }
+    for i in 0..8 {
+        s[i] ^= s[i + 8];
+        s[i + 8] ^= cv[i];
+    }
+    s
+}
+
+/// The last compression of a BLAKE3 node, kept back until it is known
+/// whether the node is the root.
+struct Blake3Node {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Blake3Node {
+    fn chaining_value(&self) -> [u32; 8] {
+        let out = blake3_compress(&self.cv, &self.block, self.counter, self.block_len, self.flags);
+        out[..8].try_into().unwrap()
+    }
+
+    /// Hashes `input`, which starts at chunk `counter`: one chunk, or a
+    /// parent over a left subtree of the largest power of two chunks that
+    /// leaves something for the right.
+    fn subtree(input: &[u8], counter: u64) -> Self {
+        if input.len() <= BLAKE3_CHUNK_LEN {
+            return Self::chunk(input, counter);
+        }
+        let chunks = input.len().div_ceil(BLAKE3_CHUNK_LEN);
+        let left_chunks = 1usize << (usize::BITS - 1 - (chunks - 1).leading_zeros());
+        let split = left_chunks * BLAKE3_CHUNK_LEN;
+        let left = Self::subtree(&input[..split], counter).chaining_value();
+        let right = Self::subtree(&input[split..], counter + left_chunks as u64).chaining_value();
+        let mut block = [0u32; 16];
+        block[..8].copy_from_slice(&left);
+        block[8..].copy_from_slice(&right);
+        Self { cv: BLAKE3_IV, block, counter: 0, block_len: 64, flags: PARENT }
+    }
+
+    fn chunk(chunk: &[u8], counter: u64) -> Self {
+        let blocks: Vec<&[u8]> = if chunk.is_empty() { vec![&[]] } else { chunk.chunks(64).collect() };
+        let mut cv = BLAKE3_IV;
+        for (i, bytes) in blocks.iter().enumerate() {
+            let mut padded = [0u8; 64];
+            padded[..bytes.len()].copy_from_slice(bytes);
+            let mut block = [0u32; 16];
+            for (word, b) in block.iter_mut().zip(padded.chunks(4)) {
+                *word = u32::from_le_bytes(b.try_into().unwrap());
+            }
+            let mut flags = if i == 0 { CHUNK_START } else { 0 };
+            if i == blocks.len() - 1 {
+                flags |= CHUNK_END;
+                return Self { cv, block, counter, block_len: bytes.len() as u32, flags };
+            }
+            cv = blake3_compress(&cv, &block, counter, 64, flags)[..8].try_into().unwrap();
+        }
+        unreachable!("a chunk has at least one block")
+    }
+}
+
+fn blake3(bytes: &[u8]) -> [u8; 32] {
+    let root = Blake3Node::subtree(bytes, 0);
+    let words = blake3_compress(&root.cv, &root.block, root.counter, root.block_len, root.flags | ROOT);
+    let mut out = [0u8; 32];
+    for (chunk, word) in out.chunks_mut(4).zip(&words[..8]) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_match_known_answers() {
+        let cases = [
+            (ChecksumAlgorithm::Sha256, "", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (ChecksumAlgorithm::Sha256, "abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (ChecksumAlgorithm::Sha1, "", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            (ChecksumAlgorithm::Sha1, "abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (ChecksumAlgorithm::Md5, "", "d41d8cd98f00b204e9800998ecf8427e"),
+            (ChecksumAlgorithm::Md5, "abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (ChecksumAlgorithm::Blake3, "", "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+            (ChecksumAlgorithm::Blake3, "abc", "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+        ];
+        for (algorithm, input, expected) in cases {
+            assert_eq!(algorithm.hex(input.as_bytes()), expected, "{} of {:?}", algorithm.as_str(), input);
+        }
+    }
+
+    #[test]
+    fn blake3_matches_the_official_vectors_around_one_chunk() {
+        // From the BLAKE3 test_vectors.json, whose input is bytes 0..=250 repeated.
+        let input = |len: usize| -> Vec<u8> { (0..len).map(|i| (i % 251) as u8).collect() };
+        assert_eq!(
+            ChecksumAlgorithm::Blake3.hex(&input(1024)),
+            "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"
+        );
+        assert_eq!(
+            ChecksumAlgorithm::Blake3.hex(&input(1025)),
+            "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"
+        );
+    }
+}
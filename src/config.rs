@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command, Parser};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+/// Config file name looked up in the working directory, then in
+/// `$XDG_CONFIG_HOME/bing/` (default `~/.config/bing/`).
+pub const CONFIG_FILE: &str = "bing.toml";
+
+/// Prefix of the environment variables that provide option defaults, e.g.
+/// `BING_DOWNLOAD_CONCURRENCY`.
+const ENV_PREFIX: &str = "BING";
+
+/// `[subcommand]` tables of option defaults, keyed by long option name.
+type Config = BTreeMap<String, BTreeMap<String, toml::Value>>;
+
+/// Parses the command line into `T`, filling options that were not given
+/// with, in order of precedence, `BING_<SUBCOMMAND>_<OPTION>` environment
+/// variables, the matching `[subcommand]` table of the config file, and the
+/// built-in defaults.
+///
+/// The config file is the one passed with the top-level `--config` option, or
+/// the first [`CONFIG_FILE`] found in the working directory or the user's
+/// config directory. Clap errors and `--help` exit the process as usual.
+pub fn parse_with_config<T: Parser>() -> Result<T> {
+    let command = with_env(T::command());
+    let mut argv: Vec<OsString> = env::args_os().collect();
+    let matches = command.clone().get_matches_from(&argv);
+
+    let path = match matches.try_get_one::<PathBuf>("config").ok().flatten() {
+        Some(path) => Some(path.clone()),
+        None => default_config_path(),
+    };
+    if let (Some(path), Some((name, sub_matches))) = (path, matches.subcommand()) {
+        let text = fs::read_to_string(&path).with_context(|| format!("Reading config {:?}", path))?;
+        let config: Config =
+            toml::from_str(&text).with_context(|| format!("Parsing config {:?}", path))?;
+        for section in config.keys() {
+            if command.find_subcommand(section).is_none() {
+                return Err(anyhow!("Unknown section [{}] in config {:?}", section, path));
+            }
+        }
+        if let (Some(table), Some(sub)) = (config.get(name), command.find_subcommand(name)) {
+            let extra = config_args(table, sub, sub_matches)
+                .with_context(|| format!("Config {:?}, section [{}]", path, name))?;
+            if !extra.is_empty() {
+                // Before a `--` so the options are not taken as positional values.
+                let at = argv.iter().position(|a| a == "--").unwrap_or(argv.len());
+                argv.splice(at..at, extra);
+                return Ok(T::from_arg_matches(&command.get_matches_from(&argv))
+                    .unwrap_or_else(|e| e.exit()));
+            }
+        }
+    }
+    Ok(T::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()))
+}
+
+/// Gives every long option of every subcommand a hidden
+/// `BING_<SUBCOMMAND>_<OPTION>` environment variable.
+fn with_env(command: Command) -> Command {
+    command.mut_subcommands(|sub| {
+        let prefix = format!("{}_{}", ENV_PREFIX, sub.get_name().to_uppercase());
+        sub.mut_args(|arg| match arg.get_long() {
+            Some(long) if arg.get_id() != "help" && arg.get_id() != "version" && !arg.is_global_set() => {
+                let name = format!("{}_{}", prefix, long.to_uppercase().replace('-', "_"));
+                arg.env(name).hide_env(true)
+            }
+            _ => arg,
+        })
+    })
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    let local = PathBuf::from(CONFIG_FILE);
+    if local.is_file() {
+        return Some(local);
+    }
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    let user = config_home.join("bing").join(CONFIG_FILE);
+    user.is_file().then_some(user)
+}
+
+/// `--option=value` arguments for the config entries whose option was not set
+/// on the command line or through the environment, and does not conflict with
+/// one that was.
+fn config_args(
+    table: &BTreeMap<String, toml::Value>,
+    sub: &Command,
+    matches: &ArgMatches,
+) -> Result<Vec<OsString>> {
+    let explicit = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+    let given: Vec<_> = sub.get_arguments().filter(|a| explicit(a.get_id().as_str())).collect();
+
+    let mut out = Vec::new();
+    for (key, value) in table {
+        let wanted = key.replace('_', "-");
+        let arg = sub
+            .get_arguments()
+            .find(|a| {
+                a.get_long() == Some(wanted.as_str())
+                    || a.get_all_aliases().is_some_and(|aliases| aliases.contains(&wanted.as_str()))
+            })
+            .ok_or_else(|| anyhow!("Unknown option `{}`", key))?;
+        let long = arg.get_long().unwrap_or_default();
+        if given.iter().any(|g| {
+            g.get_id() == arg.get_id()
+                || sub.get_arg_conflicts_with(arg).contains(g)
+                || sub.get_arg_conflicts_with(g).contains(&arg)
+        }) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(items) => items.iter().collect(),
+            other => vec![other],
+        };
+        for value in values {
+            let text = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(f) => f.to_string(),
+                toml::Value::Boolean(b) if !arg.get_action().takes_values() => {
+                    if *b {
+                        out.push(format!("--{}", long).into());
+                    }
+                    continue;
+                }
+                toml::Value::Boolean(b) => b.to_string(),
+                _ => return Err(anyhow!("`{}` must be a string, number, boolean or array", key)),
+            };
+            out.push(format!("--{}={}", long, text).into());
+        }
+    }
+    Ok(out)
+}
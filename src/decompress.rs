@@ -1,11 +1,29 @@
+use crate::glb::Glb;
+use crate::download::decode_body;
+use crate::layout::{flip_row, tile_stem};
+use crate::manifest::Manifest;
+use crate::mask::Mask;
+use crate::mbtiles::{MbTile, MbtilesReader, MbtilesWriter};
+use crate::progress::{self, ColorMode, PlainProgress, PLAIN_INTERVAL};
+use crate::stats::{format_bytes, format_duration, parse_tile_path};
+use crate::tile::tile_bounds;
+use crate::checksum::ChecksumAlgorithm;
+use crate::verify::{read_checksums, relative_key, CHECKSUMS_FILE};
 use anyhow::{anyhow, Context, Result};
-use clap::{ArgAction, Parser};
-use indicatif::{ProgressBar, ProgressStyle};
+use clap::{ArgAction, Parser, ValueEnum};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use rayon::prelude::*;
-use std::ffi::OsStr;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Instant;
 use walkdir::WalkDir;
 use which::which;
 
@@ -15,7 +33,8 @@ pub struct Args {
     #[arg(default_value = ".")]
     pub input_dir: PathBuf,
 
-    /// Output directory (defaults to <input_dir>/processed)
+    /// Output directory (defaults to <input_dir>/processed; with --mbtiles-in, processed/ next
+    /// to the file, or a temporary directory when only --mbtiles-out is wanted)
     #[arg(short, long)]
     pub out: Option<PathBuf>,
 
@@ -23,6 +42,21 @@ pub struct Args {
     #[arg(short, long, action = ArgAction::SetTrue)]
     pub recursive: bool,
 
+    /// Process exactly the files listed in this file (one path per line, relative to the
+    /// input directory) or in a download --manifest JSON, instead of scanning
+    #[arg(long = "files-from", conflicts_with = "recursive")]
+    pub files_from: Option<PathBuf>,
+
+    /// Process the tiles of this MBTiles file instead of scanning a directory. They are
+    /// extracted to temporary z_x_y.glb files (rows flipped to XYZ), which --out receives
+    #[arg(long = "mbtiles-in", conflicts_with_all = ["input_dir", "files_from", "recursive"])]
+    pub mbtiles_in: Option<PathBuf>,
+
+    /// With --mbtiles-in, write the decompressed tiles to this new MBTiles file under their
+    /// original zoom_level/tile_column/tile_row keys, with the input's metadata
+    #[arg(long = "mbtiles-out", requires = "mbtiles_in")]
+    pub mbtiles_out: Option<PathBuf>,
+
     /// Overwrite outputs if they already exist
     #[arg(short = 'f', long, action = ArgAction::SetTrue)]
     pub force: bool,
@@ -35,6 +69,10 @@ pub struct Args {
     #[arg(long, action = ArgAction::SetTrue)]
     pub use_npx: bool,
 
+    /// Require a globally installed gltf-transform; error instead of falling back to npx
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "use_npx")]
+    pub prefer_global: bool,
+
     /// Dry run: list what would be processed without executing
     #[arg(long, action = ArgAction::SetTrue)]
     pub dry_run: bool,
@@ -42,6 +80,121 @@ pub struct Args {
     /// Split tiles into a grid of subdirectories (must be a perfect square: 1, 4, 9, 16, 25, etc.)
     #[arg(long = "split", default_value_t = 1)]
     pub split: usize,
+
+    /// Write every output directly into the output directory instead of mirroring subfolders
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub flatten: bool,
+
+    /// What to do when --flatten maps two inputs to the same output name
+    #[arg(long = "on-conflict", value_enum, default_value_t = OnConflict::Error, requires = "flatten")]
+    pub on_conflict: OnConflict,
+
+    /// Process one subdirectory at a time, in sorted order, reporting each as it finishes
+    #[arg(long = "by-dir", action = ArgAction::SetTrue)]
+    pub by_dir: bool,
+
+    /// Keep one gltf-transform Node process per worker thread and feed it files, instead of
+    /// spawning the CLI for every file (needs a global gltf-transform install)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub persistent: bool,
+
+    /// Hide the progress bar and print only the final summary
+    #[arg(short, long, action = ArgAction::SetTrue)]
+    pub quiet: bool,
+
+    /// Print the final summary as JSON on stdout (implies --quiet)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub json: bool,
+
+    /// Re-encode the decompressed textures to this format (requires gltf-transform 3.0+)
+    #[arg(long = "texture-format", value_enum)]
+    pub texture_format: Option<TextureFormat>,
+
+    /// After ktxdecompress, remove every texture and image from the output, keeping only geometry and material factors
+    #[arg(long = "strip-textures", action = ArgAction::SetTrue, conflicts_with = "texture_format")]
+    pub strip_textures: bool,
+
+    /// Only process tiles whose z_x_y bounds intersect a polygon in this GeoJSON file
+    #[arg(long = "mask-geojson")]
+    pub mask_geojson: Option<PathBuf>,
+
+    /// With --mask-geojson, what to do with files whose names don't parse as tiles
+    #[arg(long = "mask-unparsed", value_enum, default_value_t = Unparsed::Process, requires = "mask_geojson")]
+    pub mask_unparsed: Unparsed,
+
+    /// Record a checksum of every output in <out>/checksums.txt (SHA-256 unless --checksum-algorithm says otherwise)
+    #[arg(long = "checksum-outputs", action = ArgAction::SetTrue)]
+    pub checksum_outputs: bool,
+
+    /// Hash for --checksum-outputs, named on the file's header line
+    #[arg(long = "checksum-algorithm", value_enum, default_value_t = ChecksumAlgorithm::Sha256, requires = "checksum_outputs")]
+    pub checksum_algorithm: ChecksumAlgorithm,
+
+    /// Compare output checksums against this sha256sum-style file and fail on any mismatch
+    #[arg(long = "compare-checksums", requires = "checksum_outputs")]
+    pub compare_checksums: Option<PathBuf>,
+
+    /// After decompressing, merge every output into this single GLB with gltf-transform merge
+    #[arg(long = "merge-output")]
+    pub merge_output: Option<PathBuf>,
+
+    /// Redraw the progress bar at most every this many milliseconds; without a terminal, print a progress line this often (default: 5000)
+    #[arg(long = "progress-interval")]
+    pub progress_interval: Option<u64>,
+
+    /// Set from the top-level `--color`
+    #[arg(skip)]
+    pub color: ColorMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TextureFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl TextureFormat {
+    /// gltf-transform subcommand that re-encodes textures to this format.
+    fn command(self) -> &'static str {
+        match self {
+            TextureFormat::Png => "png",
+            TextureFormat::Jpeg => "jpeg",
+            TextureFormat::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Abort before processing and list the collisions
+    Error,
+    /// Keep the first input (in sorted order) and skip the rest
+    Skip,
+    /// Append the parent folder name, then a counter if still taken
+    Rename,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Unparsed {
+    /// Process them as if they were inside the mask
+    Process,
+    /// Leave them out
+    Skip,
+}
+
+/// Result of the `--merge-output` pass.
+struct MergeReport {
+    inputs: usize,
+    /// Why the merge failed or was skipped; `None` when it succeeded.
+    error: Option<String>,
+    elapsed: f64,
+}
+
+enum FileOutcome {
+    Processed,
+    Skipped,
+    DryRun,
 }
 
 enum CliKind {
@@ -49,7 +202,19 @@ enum CliKind {
     Npx { package: String }, // e.g., @gltf-transform/cli
 }
 
+impl CliKind {
+    /// What was resolved, for the start-up line.
+    fn describe(&self) -> String {
+        match self {
+            CliKind::Global(path) => format!("global {}", path.display()),
+            CliKind::Npx { package } => format!("npx {}", package),
+        }
+    }
+}
+
 pub fn run_decompress(args: Args) -> Result<()> {
+    args.color.apply();
+    let progress_interval = progress::interval(args.progress_interval)?;
 
     if let Some(n) = args.jobs {
         rayon::ThreadPoolBuilder::new()
@@ -58,147 +223,563 @@ pub fn run_decompress(args: Args) -> Result<()> {
             .ok(); // harmless if already set
     }
 
+    // Tiles of --mbtiles-in are extracted and then processed like any
+    // other input directory.
+    let staged = match &args.mbtiles_in {
+        Some(path) => Some(StagedMbtiles::extract(path)?),
+        None => None,
+    };
+    let input_dir = staged.as_ref().map_or(args.input_dir.clone(), |s| s.dir.clone());
+
     // Decide output dir.
-    let out_dir = args
-        .out
-        .clone()
-        .unwrap_or_else(|| args.input_dir.join("processed"));
+    let out_dir = match (&args.out, &args.mbtiles_in, &args.mbtiles_out) {
+        (Some(out), _, _) => out.clone(),
+        (None, Some(_), Some(_)) => input_dir.join("processed"),
+        (None, Some(path), None) => path.parent().unwrap_or(Path::new(".")).join("processed"),
+        (None, None, _) => input_dir.join("processed"),
+    };
     fs::create_dir_all(&out_dir)
         .with_context(|| format!("Failed to create output directory {:?}", out_dir))?;
 
+    // Read the reference first: it may be the checksums.txt this run rewrites.
+    let reference = match &args.compare_checksums {
+        Some(path) => {
+            let (algorithm, reference) = read_checksums(path)?;
+            if algorithm != args.checksum_algorithm {
+                return Err(anyhow!(
+                    "{} holds {} checksums; pass --checksum-algorithm {} to compare against it",
+                    path.display(),
+                    algorithm.as_str(),
+                    algorithm.as_str()
+                ));
+            }
+            Some(reference)
+        }
+        None => None,
+    };
+
     // Discover CLI.
-    let cli = detect_cli(args.use_npx)?;
+    let cli = detect_cli(args.use_npx, args.prefer_global)?;
+    let quiet = args.quiet || args.json;
+    if !quiet {
+        println!("gltf-transform: {}", cli.describe());
+    }
+    let runner = if args.persistent {
+        Runner::Persistent(HelperPool::new(&cli, rayon::current_num_threads())?)
+    } else {
+        Runner::Spawn(cli)
+    };
 
     // Gather .glb files.
-    let files = collect_glb_files(&args.input_dir, args.recursive)?;
-    println!(
-        "GLB files: {}\nOutput: {:?}",
-        files.len(),
-        out_dir
-    );
-    if files.is_empty() {
+    let files = match (&staged, &args.files_from) {
+        (Some(staged), _) => {
+            let mut files: Vec<PathBuf> = staged.keys.keys().cloned().collect();
+            files.sort();
+            files
+        }
+        (None, Some(list)) => read_file_list(list, &input_dir)?,
+        (None, None) => collect_glb_files(&input_dir, args.recursive)?,
+    };
+    let files = match &args.mask_geojson {
+        Some(path) => {
+            let mask = Mask::read_geojson(path)?;
+            let total = files.len();
+            let (files, unparsed) = apply_mask(files, &input_dir, &mask, args.mask_unparsed);
+            if !quiet {
+                println!(
+                    "Mask: {} of {} files intersect {} ({} name(s) not parsed as tiles{})",
+                    files.len(),
+                    total,
+                    path.display(),
+                    unparsed,
+                    if args.mask_unparsed == Unparsed::Skip { ", skipped" } else { "" }
+                );
+            }
+            files
+        }
+        None => files,
+    };
+    if !quiet {
+        println!(
+            "GLB files: {}\nOutput: {:?}",
+            files.len(),
+            out_dir
+        );
+    }
+    if files.is_empty() && !args.json {
         return Ok(());
     }
 
     let pb = ProgressBar::new(files.len() as u64);
-    pb.set_style(
-        ProgressStyle::with_template(
-            "[{elapsed_precise}] [{bar:30.cyan/blue}] {pos}/{len} - ETA {eta}",
-        )
-        .unwrap()
-    );
+    let mut plain = None;
+    if quiet {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    } else if !progress::interactive() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+        let interval = progress_interval.unwrap_or(PLAIN_INTERVAL);
+        plain = Some(PlainProgress::start("Processed", vec![pb.clone()], interval));
+    } else {
+        pb.set_draw_target(progress::draw_target(progress_interval));
+    }
+    let template = if args.by_dir {
+        "[{elapsed_precise}] [{bar:30.cyan/blue}] {pos}/{len} - ETA {eta} {prefix} {wide_msg}"
+    } else {
+        "[{elapsed_precise}] [{bar:30.cyan/blue}] {pos}/{len} - ETA {eta} {wide_msg}"
+    };
+    pb.set_style(ProgressStyle::with_template(template).unwrap());
 
+    let started = Instant::now();
     let force = args.force;
     let dry_run = args.dry_run;
+    let texture_format = args.texture_format;
+    let strip_textures = args.strip_textures;
+    // Output sizes before and after --strip-textures, over all processed files.
+    let unstripped = AtomicU64::new(0);
+    let stripped = AtomicU64::new(0);
 
-    // Process in parallel.
-    let results: Vec<Result<()>> = files
-        .par_iter()
-        .map(|in_path| {
-            let file_name = in_path
-                .file_name()
-                .ok_or_else(|| anyhow!("Bad filename"))?;
-            
-            // Calculate relative path from input directory to maintain directory structure
-            let relative_path = in_path
-                .strip_prefix(&args.input_dir)
-                .map_err(|_| anyhow!("Failed to calculate relative path"))?;
-            
-            let out_path = out_dir.join(relative_path);
-
-            if out_path.exists() && !force {
-                pb.inc(1);
-                pb.set_message(format!(
-                    "{} (skipped, exists)",
-                    file_name.to_string_lossy()
-                ));
-                return Ok(());
-            }
+    // Every output path is decided up front so --flatten collisions are
+    // caught before anything is written.
+    let out_paths = plan_outputs(&files, &input_dir, &out_dir, args.flatten, args.on_conflict)?;
 
-            if dry_run {
-                pb.inc(1);
-                pb.set_message(format!("{} (dry-run)", file_name.to_string_lossy()));
-                return Ok(());
-            }
+    let process = |in_path: &PathBuf| -> Result<FileOutcome> {
+        let Some(out_path) = out_paths[in_path].clone() else {
+            return Ok(FileOutcome::Skipped);
+        };
 
-            // Ensure the parent directory exists
-            if let Some(parent) = out_path.parent() {
-                fs::create_dir_all(parent)
-                    .with_context(|| format!("Creating parent for {:?}", out_path))?;
-            }
+        if out_path.exists() && !force {
+            return Ok(FileOutcome::Skipped);
+        }
 
-            // Build command
-            let status = match &cli {
-                CliKind::Global(bin) => {
-                    Command::new(bin)
-                        .arg("ktxdecompress")
-                        .arg(in_path)
-                        .arg(&out_path)
-                        .stdin(Stdio::null())
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::piped())
-                        .status()
-                        .with_context(|| format!("Failed to spawn gltf-transform for {:?}", in_path))?
-                }
-                CliKind::Npx { package } => {
-                    Command::new("npx")
-                        .arg("-y")
-                        .arg(package)
-                        .arg("ktxdecompress")
-                        .arg(in_path)
-                        .arg(&out_path)
-                        .stdin(Stdio::null())
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::piped())
-                        .status()
-                        .with_context(|| format!("Failed to spawn npx for {:?}", in_path))?
-                }
-            };
+        if dry_run {
+            return Ok(FileOutcome::DryRun);
+        }
 
-            if !status.success() {
-                return Err(anyhow!(
-                    "ktxdecompress failed for {:?} (exit status {:?})",
-                    in_path,
-                    status.code()
-                ));
-            }
+        // Ensure the parent directory exists
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Creating parent for {:?}", out_path))?;
+        }
 
-            pb.inc(1);
-            pb.set_message(format!("{} (ok)", file_name.to_string_lossy()));
-            Ok(())
-        })
-        .collect();
+        runner.run("ktxdecompress", in_path, &out_path)?;
 
+        // Re-encode in place; ktxdecompress has already written out_path.
+        if let Some(format) = texture_format {
+            runner.run(format.command(), &out_path, &out_path)?;
+        }
+        if strip_textures {
+            let bytes = fs::read(&out_path).with_context(|| format!("Reading {:?}", out_path))?;
+            let mut glb = Glb::parse(&bytes).with_context(|| format!("Parsing {:?}", out_path))?;
+            glb.strip_textures()
+                .with_context(|| format!("Stripping textures from {:?}", out_path))?;
+            let out = glb.to_bytes()?;
+            fs::write(&out_path, &out).with_context(|| format!("Writing {:?}", out_path))?;
+            unstripped.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            stripped.fetch_add(out.len() as u64, Ordering::Relaxed);
+        }
+
+        Ok(FileOutcome::Processed)
+    };
+
+    // Process in parallel. With --by-dir, directories are handled one after
+    // another in sorted order, each with its files spread across workers.
+    let in_flight = InFlight::new(pb.clone());
+    let mut tally = Tally::default();
+    if args.by_dir {
+        let groups = group_by_dir(&files);
+        let count = groups.len();
+        for (i, (dir, group)) in groups.into_iter().enumerate() {
+            let label = dir
+                .strip_prefix(&input_dir)
+                .ok()
+                .filter(|rel| !rel.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+                .display()
+                .to_string();
+            pb.set_prefix(format!("{} ({}/{})", label, i + 1, count));
+            let before = tally.failures.len();
+            run_parallel(&group, &process, &in_flight, &mut tally);
+            let failed = tally.failures.len() - before;
+            pb.println(format!("Finished {}: {} file(s), {} failed", label, group.len(), failed));
+        }
+    } else {
+        let all: Vec<&PathBuf> = files.iter().collect();
+        run_parallel(&all, &process, &in_flight, &mut tally);
+    }
+
+    if let Some(plain) = plain {
+        plain.finish();
+    }
     pb.finish_and_clear();
+    let elapsed = started.elapsed().as_secs_f64();
 
     // Summarize errors if any.
-    let mut failures = Vec::new();
-    for res in results {
-        if let Err(e) = res {
-            failures.push(e);
+    // Results arrive in completion order; report failures in file order.
+    let Tally { processed, skipped, dry_runs, mut failures } = tally;
+    failures.sort_by(|a, b| a.0.cmp(b.0));
+
+    let merge = args.merge_output.as_ref().filter(|_| !dry_run).map(|merge_path| {
+        let mut outputs: Vec<&PathBuf> = out_paths
+            .values()
+            .flatten()
+            .filter(|p| p.is_file() && p.as_path() != merge_path.as_path())
+            .collect();
+        outputs.sort();
+        let merge_started = Instant::now();
+        let error = if !failures.is_empty() {
+            Some(format!("skipped because {} file(s) failed", failures.len()))
+        } else if outputs.is_empty() {
+            Some("no outputs to merge".to_string())
+        } else {
+            if !quiet {
+                println!("Merging {} outputs into {}", outputs.len(), merge_path.display());
+            }
+            merge_path.parent().map(fs::create_dir_all);
+            runner.merge(&outputs, merge_path).err().map(|e| format!("{:#}", e))
+        };
+        MergeReport {
+            inputs: outputs.len(),
+            error,
+            elapsed: merge_started.elapsed().as_secs_f64(),
         }
-    }
+    });
+    drop(runner);
+    let strip = strip_textures.then(|| (unstripped.into_inner(), stripped.into_inner()));
 
-    if failures.is_empty() {
+    let checksums = if args.checksum_outputs && !dry_run {
+        let mut outputs: Vec<&PathBuf> = out_paths.values().flatten().filter(|p| p.is_file()).collect();
+        outputs.sort();
+        Some(record_checksums(&outputs, &out_dir, args.checksum_algorithm, reference.as_ref())?)
+    } else {
+        None
+    };
+
+    let mbtiles = match (&staged, &args.mbtiles_out) {
+        (Some(staged), Some(path)) if !dry_run => {
+            let failed: Vec<&PathBuf> = failures.iter().map(|(path, _)| *path).collect();
+            Some(write_mbtiles(path, staged, &out_paths, &failed)?)
+        }
+        _ => None,
+    };
+
+    if args.json {
+        let failed: Vec<Value> = failures
+            .iter()
+            .map(|(path, e)| json!({ "path": path, "error": format!("{:#}", e) }))
+            .collect();
+        println!(
+            "{}",
+            json!({
+                "output": out_dir,
+                "ok": processed,
+                "skipped": skipped,
+                "dry_run": dry_runs,
+                "failed": failures.len(),
+                "failed_files": failed,
+                "duration_secs": elapsed,
+                "checksum_mismatches": checksums.as_ref().map(|c| c.mismatched.len()),
+                "strip_textures": strip.map(|(before, after)| json!({
+                    "bytes_before": before,
+                    "bytes_after": after,
+                })),
+                "mbtiles_out": mbtiles.map(|(written, left_out)| json!({
+                    "output": args.mbtiles_out,
+                    "tiles": written,
+                    "left_out": left_out,
+                })),
+                "merge": merge.as_ref().map(|m| json!({
+                    "output": args.merge_output,
+                    "inputs": m.inputs,
+                    "ok": m.error.is_none(),
+                    "error": m.error,
+                    "duration_secs": m.elapsed,
+                })),
+            })
+        );
+    } else {
+        println!(
+            "Processed: {}, skipped: {}, failed: {}{}",
+            processed,
+            skipped,
+            failures.len(),
+            if dry_runs > 0 { format!(", dry-run: {}", dry_runs) } else { String::new() }
+        );
         println!(
-            "All done. Decompressed files are in: {}",
-            out_dir.display()
+            "Time: {}, {:.1} files/s",
+            format_duration(elapsed),
+            processed as f64 / elapsed.max(1e-3)
         );
+        if let Some((before, after)) = strip {
+            println!(
+                "Stripped textures: {} -> {} ({:.0}% smaller)",
+                format_bytes(before),
+                format_bytes(after),
+                100.0 * before.saturating_sub(after) as f64 / before.max(1) as f64
+            );
+        }
+        if let (Some((written, left_out)), Some(path)) = (mbtiles, &args.mbtiles_out) {
+            println!(
+                "MBTiles: {} tiles written to {}{}",
+                written,
+                path.display(),
+                if left_out > 0 { format!(" ({} without an output left out)", left_out) } else { String::new() }
+            );
+        }
+        if let (Some(m), Some(path)) = (&merge, &args.merge_output) {
+            match &m.error {
+                None => println!(
+                    "Merge: {} outputs into {} ({}, {})",
+                    m.inputs,
+                    path.display(),
+                    format_bytes(fs::metadata(path).map_or(0, |meta| meta.len())),
+                    format_duration(m.elapsed)
+                ),
+                Some(e) => println!("Merge: failed: {}", e),
+            }
+        }
+        if let Some(c) = &checksums {
+            println!("Checksums: {} outputs recorded in {}", c.recorded, out_dir.join(CHECKSUMS_FILE).display());
+            if reference.is_some() {
+                println!(
+                    "Compared: {} match, {} mismatch, {} not in reference",
+                    c.matched,
+                    c.mismatched.len(),
+                    c.unlisted
+                );
+            }
+        }
+    }
+    let mismatches = checksums.as_ref().map_or(0, |c| c.mismatched.len());
+    if let Some(c) = &checksums {
+        for (file, expected, actual) in &c.mismatched {
+            eprintln!("MISMATCH {} (expected {}, got {})", file, expected, actual);
+        }
+    }
+
+    if failures.is_empty() {
+        if mismatches > 0 {
+            return Err(anyhow!("{} output checksum(s) differ from the reference", mismatches));
+        }
+        if let Some(e) = merge.as_ref().and_then(|m| m.error.as_ref()) {
+            return Err(anyhow!("--merge-output failed: {}", e));
+        }
+        if args.json {
+            return Ok(());
+        }
+        match (&args.mbtiles_out, &args.out) {
+            (Some(path), None) => println!("All done. Decompressed tiles are in: {}", path.display()),
+            _ => println!(
+                "All done. Decompressed files are in: {}",
+                out_dir.display()
+            ),
+        }
         Ok(())
     } else {
         eprintln!("Completed with {} error(s):", failures.len());
-        for (i, e) in failures.iter().enumerate() {
+        for (i, (_, e)) in failures.iter().enumerate() {
             eprintln!("  {}. {:#}", i + 1, e);
         }
         Err(anyhow!("Some files failed. See errors above."))
     }
 }
 
-fn detect_cli(force_npx: bool) -> Result<CliKind> {
-    if !force_npx {
-        if let Ok(p) = which("gltf-transform") {
-            return Ok(CliKind::Global(p));
+/// How each `gltf-transform <subcommand> <input> <output>` call is executed.
+enum Runner {
+    /// A fresh CLI process per call
+    Spawn(CliKind),
+    /// Requests sent to long-lived Node helpers (`--persistent`)
+    Persistent(HelperPool),
+}
+
+impl Runner {
+    fn run(&self, subcommand: &str, input: &Path, output: &Path) -> Result<()> {
+        self.run_args(&[subcommand.into(), input.into(), output.into()], input)
+    }
+
+    /// `gltf-transform merge <inputs...> <output>`, combining every input's
+    /// nodes into a single scene.
+    fn merge(&self, inputs: &[&PathBuf], output: &Path) -> Result<()> {
+        let mut args: Vec<OsString> = vec!["merge".into()];
+        args.extend(inputs.iter().map(|p| p.as_os_str().to_os_string()));
+        args.push(output.into());
+        args.push("--merge-scenes".into());
+        self.run_args(&args, output)
+    }
+
+    /// Runs `gltf-transform <args...>`; `subject` names the file in errors.
+    fn run_args(&self, args: &[OsString], subject: &Path) -> Result<()> {
+        let subcommand = args[0].to_string_lossy();
+        match self {
+            Runner::Spawn(cli) => {
+                let status = run_cli(cli, args, subject)?;
+                if !status.success() {
+                    return Err(anyhow!(
+                        "{} failed for {:?} (exit status {:?})",
+                        subcommand,
+                        subject,
+                        status.code()
+                    ));
+                }
+                Ok(())
+            }
+            Runner::Persistent(pool) => pool
+                .run(args)
+                .with_context(|| format!("{} failed for {:?}", subcommand, subject)),
+        }
+    }
+}
+
+const HELPER_SCRIPT: &str = include_str!("gltf_helper.mjs");
+const HELPER_REPLY_PREFIX: &str = "\u{1}bing ";
+
+/// One helper per rayon worker, spawned on first use. A helper that dies or
+/// stops answering is dropped and respawned for the next file.
+struct HelperPool {
+    script: PathBuf,
+    module: PathBuf,
+    slots: Vec<Mutex<Option<Helper>>>,
+}
+
+struct Helper {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl HelperPool {
+    fn new(cli: &CliKind, size: usize) -> Result<Self> {
+        let CliKind::Global(bin) = cli else {
+            return Err(anyhow!(
+                "--persistent needs a globally installed gltf-transform (npm i -g @gltf-transform/cli)"
+            ));
+        };
+        // bin/cli.js is a thin launcher around dist/cli.js, which exports the program.
+        let launcher = fs::canonicalize(bin)
+            .with_context(|| format!("Resolving gltf-transform at {:?}", bin))?;
+        let module = launcher
+            .parent()
+            .and_then(Path::parent)
+            .map(|pkg| pkg.join("dist").join("cli.js"))
+            .filter(|m| m.is_file())
+            .ok_or_else(|| {
+                anyhow!("Could not find dist/cli.js next to {:?}; is it the npm package?", launcher)
+            })?;
+        which("node").context("--persistent needs `node` on PATH")?;
+
+        let script = std::env::temp_dir().join(format!("bing-gltf-helper-{}.mjs", std::process::id()));
+        fs::write(&script, HELPER_SCRIPT).with_context(|| format!("Writing {:?}", script))?;
+        Ok(Self {
+            script,
+            module,
+            slots: (0..size.max(1)).map(|_| Mutex::new(None)).collect(),
+        })
+    }
+
+    fn run(&self, args: &[OsString]) -> Result<()> {
+        let idx = rayon::current_thread_index().unwrap_or(0) % self.slots.len();
+        let mut slot = self.slots[idx].lock().unwrap();
+        if slot.is_none() {
+            *slot = Some(self.spawn()?);
+        }
+        let args: Vec<_> = args.iter().map(|a| a.to_string_lossy()).collect();
+        let request = json!({ "args": args });
+        match slot.as_mut().unwrap().call(&request) {
+            Ok(reply) => reply,
+            Err(e) => {
+                // Broken pipe or EOF: drop this helper so the next call respawns it.
+                *slot = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn spawn(&self) -> Result<Helper> {
+        let mut child = Command::new("node")
+            .arg(&self.script)
+            .env("BING_GLTF_TRANSFORM_MODULE", &self.module)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to spawn gltf-transform helper")?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(Helper { child, stdin, stdout })
+    }
+}
+
+impl Drop for HelperPool {
+    fn drop(&mut self) {
+        for slot in &self.slots {
+            slot.lock().unwrap().take();
         }
+        fs::remove_file(&self.script).ok();
+    }
+}
+
+impl Helper {
+    /// Sends one request. The outer error means the helper itself broke; the
+    /// inner one is a failed conversion reported by a healthy helper.
+    fn call(&mut self, request: &Value) -> Result<Result<()>> {
+        writeln!(self.stdin, "{}", request)?;
+        self.stdin.flush()?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(anyhow!("gltf-transform helper exited unexpectedly"));
+            }
+            if let Some(reply) = line.trim_end().strip_prefix(HELPER_REPLY_PREFIX) {
+                let reply: Value = serde_json::from_str(reply)?;
+                return Ok(if reply["ok"].as_bool() == Some(true) {
+                    Ok(())
+                } else {
+                    Err(anyhow!("{}", reply["error"].as_str().unwrap_or("unknown error")))
+                });
+            }
+        }
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        // Requests are synchronous, so nothing is in flight by now.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Runs `gltf-transform <args...>`, e.g. `ktxdecompress <input> <output>`.
+fn run_cli(cli: &CliKind, args: &[OsString], input: &Path) -> Result<ExitStatus> {
+    match cli {
+        CliKind::Global(bin) => Command::new(bin)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .status()
+            .with_context(|| format!("Failed to spawn gltf-transform for {:?}", input)),
+        CliKind::Npx { package } => Command::new("npx")
+            .arg("-y")
+            .arg(package)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .status()
+            .with_context(|| format!("Failed to spawn npx for {:?}", input)),
+    }
+}
+
+fn detect_cli(force_npx: bool, require_global: bool) -> Result<CliKind> {
+    if !force_npx
+        && let Ok(p) = which("gltf-transform")
+    {
+        return Ok(CliKind::Global(p));
+    }
+    if require_global {
+        return Err(anyhow!(
+            "--prefer-global: no gltf-transform found on PATH (install it with `npm install -g @gltf-transform/cli`)"
+        ));
     }
     // Fallback: npx @gltf-transform/cli
     Ok(CliKind::Npx {
@@ -206,6 +787,305 @@ fn detect_cli(force_npx: bool) -> Result<CliKind> {
     })
 }
 
+/// Per-outcome counts, with the error of every failed file.
+#[derive(Default)]
+struct Tally<'a> {
+    processed: usize,
+    skipped: usize,
+    dry_runs: usize,
+    failures: Vec<(&'a PathBuf, anyhow::Error)>,
+}
+
+/// Names of the files being processed right now, shown as the bar message.
+struct InFlight {
+    pb: ProgressBar,
+    names: Mutex<BTreeSet<String>>,
+}
+
+impl InFlight {
+    fn new(pb: ProgressBar) -> Self {
+        Self {
+            pb,
+            names: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    fn update(&self, path: &Path, started: bool) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let mut names = self.names.lock().unwrap();
+        if started {
+            names.insert(name);
+        } else {
+            names.remove(&name);
+        }
+        let mut message = names.iter().take(3).cloned().collect::<Vec<_>>().join(", ");
+        if names.len() > 3 {
+            message.push_str(&format!(" +{}", names.len() - 3));
+        }
+        self.pb.set_message(message);
+    }
+}
+
+/// Runs `process` over `files` on the rayon pool. Each result goes through a
+/// bounded channel to this thread, which tallies it as it arrives, so only
+/// failures are kept in memory and the bar moves as files finish.
+fn run_parallel<'a, F>(files: &[&'a PathBuf], process: &F, in_flight: &InFlight, tally: &mut Tally<'a>)
+where
+    F: Fn(&PathBuf) -> Result<FileOutcome> + Sync,
+{
+    let (tx, rx) = mpsc::sync_channel(rayon::current_num_threads() * 2);
+    thread::scope(|scope| {
+        // Driven from a plain thread so a one-thread pool can't block on
+        // this receiver.
+        scope.spawn(move || {
+            files.par_iter().for_each_with(tx, |tx, path| {
+                in_flight.update(path, true);
+                let result = process(path);
+                in_flight.update(path, false);
+                tx.send((*path, result)).ok();
+            });
+        });
+        for (path, result) in rx {
+            match result {
+                Ok(FileOutcome::Processed) => tally.processed += 1,
+                Ok(FileOutcome::Skipped) => tally.skipped += 1,
+                Ok(FileOutcome::DryRun) => tally.dry_runs += 1,
+                Err(e) => tally.failures.push((path, e)),
+            }
+            in_flight.pb.inc(1);
+        }
+    });
+}
+
+/// Keeps the files whose `z_x_y` tile intersects `mask`; names that don't
+/// parse, including out-of-range zooms and negative tiles, are kept or
+/// dropped per `unparsed`. Returns the kept files and how many did not parse.
+fn apply_mask(files: Vec<PathBuf>, input_dir: &Path, mask: &Mask, unparsed: Unparsed) -> (Vec<PathBuf>, usize) {
+    let mut not_tiles = 0;
+    let kept = files
+        .into_iter()
+        .filter(|path| {
+            let rel = path.strip_prefix(input_dir).unwrap_or(path);
+            match parse_tile_path(rel) {
+                Some((z, x, y)) => mask.intersects(tile_bounds(x, y, z)),
+                None => {
+                    not_tiles += 1;
+                    unparsed == Unparsed::Process
+                }
+            }
+        })
+        .collect();
+    (kept, not_tiles)
+}
+
+/// Outcome of `--checksum-outputs`, with the comparison when a reference
+/// was given.
+struct ChecksumReport {
+    recorded: usize,
+    matched: usize,
+    /// `(path, expected, actual)`
+    mismatched: Vec<(String, String, String)>,
+    /// Outputs the reference has no entry for
+    unlisted: usize,
+}
+
+/// Hashes `outputs`, writes them to `<out_dir>/checksums.txt` in `sha256sum`
+/// format under an `# algorithm:` header (readable by `bing verify`), and
+/// compares against `reference`.
+fn record_checksums(
+    outputs: &[&PathBuf],
+    out_dir: &Path,
+    algorithm: ChecksumAlgorithm,
+    reference: Option<&BTreeMap<String, String>>,
+) -> Result<ChecksumReport> {
+    let hashes: Vec<(String, String)> = outputs
+        .par_iter()
+        .map(|path| {
+            let bytes = fs::read(path).with_context(|| format!("Reading {:?}", path))?;
+            Ok((relative_key(path, out_dir), algorithm.hex(&bytes)))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut text = format!("# algorithm: {}\n", algorithm.as_str());
+    for (file, hash) in &hashes {
+        text.push_str(&format!("{}  {}\n", hash, file));
+    }
+    let path = out_dir.join(CHECKSUMS_FILE);
+    fs::write(&path, text).with_context(|| format!("Writing {:?}", path))?;
+
+    let mut report = ChecksumReport {
+        recorded: hashes.len(),
+        matched: 0,
+        mismatched: Vec::new(),
+        unlisted: 0,
+    };
+    if let Some(reference) = reference {
+        for (file, actual) in hashes {
+            match reference.get(&file) {
+                Some(expected) if expected.eq_ignore_ascii_case(&actual) => report.matched += 1,
+                Some(expected) => report.mismatched.push((file, expected.clone(), actual)),
+                None => report.unlisted += 1,
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Maps each input to its output path: mirrored under `out_dir`, or directly
+/// in it with `flatten`. `None` marks an input skipped by `OnConflict::Skip`.
+fn plan_outputs(
+    files: &[PathBuf],
+    input_dir: &Path,
+    out_dir: &Path,
+    flatten: bool,
+    on_conflict: OnConflict,
+) -> Result<HashMap<PathBuf, Option<PathBuf>>> {
+    let mut plan = HashMap::with_capacity(files.len());
+    let mut taken: HashMap<PathBuf, &PathBuf> = HashMap::new();
+    let mut conflicts = Vec::new();
+    for in_path in files {
+        let relative = in_path
+            .strip_prefix(input_dir)
+            .map_err(|_| anyhow!("Failed to calculate relative path for {:?}", in_path))?;
+        if !flatten {
+            plan.insert(in_path.clone(), Some(out_dir.join(relative)));
+            continue;
+        }
+        let name = in_path.file_name().ok_or_else(|| anyhow!("Bad filename"))?;
+        let mut out_path = out_dir.join(name);
+        if let Some(first) = taken.get(&out_path) {
+            match on_conflict {
+                OnConflict::Error => {
+                    conflicts.push(format!("{:?} and {:?} -> {:?}", first, in_path, out_path));
+                    continue;
+                }
+                OnConflict::Skip => {
+                    plan.insert(in_path.clone(), None);
+                    continue;
+                }
+                OnConflict::Rename => out_path = renamed_output(in_path, relative, out_dir, &taken),
+            }
+        }
+        taken.insert(out_path.clone(), in_path);
+        plan.insert(in_path.clone(), Some(out_path));
+    }
+    if !conflicts.is_empty() {
+        for c in &conflicts {
+            eprintln!("CONFLICT {}", c);
+        }
+        return Err(anyhow!(
+            "{} output name collision(s) with --flatten; use --on-conflict skip or rename",
+            conflicts.len()
+        ));
+    }
+    Ok(plan)
+}
+
+/// `stem__<parent folders>.ext`, then `stem__<parents>_2.ext`, ... until free.
+fn renamed_output(
+    in_path: &Path,
+    relative: &Path,
+    out_dir: &Path,
+    taken: &HashMap<PathBuf, &PathBuf>,
+) -> PathBuf {
+    let stem = in_path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = in_path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+    let parents: Vec<String> = relative
+        .parent()
+        .map(|p| p.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect())
+        .unwrap_or_default();
+    let base = if parents.is_empty() {
+        stem.to_string()
+    } else {
+        format!("{}__{}", stem, parents.join("_"))
+    };
+    let mut candidate = out_dir.join(format!("{}.{}", base, ext));
+    let mut n = 2;
+    while taken.contains_key(&candidate) {
+        candidate = out_dir.join(format!("{}_{}.{}", base, n, ext));
+        n += 1;
+    }
+    candidate
+}
+
+/// Groups files by parent directory, both in sorted order.
+fn group_by_dir(files: &[PathBuf]) -> BTreeMap<PathBuf, Vec<&PathBuf>> {
+    let mut groups: BTreeMap<PathBuf, Vec<&PathBuf>> = BTreeMap::new();
+    for file in files {
+        let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        groups.entry(dir).or_default().push(file);
+    }
+    groups
+}
+
+/// Tiles of an `--mbtiles-in` file extracted as `z_x_y.glb` files into a
+/// temporary directory, which is removed again on drop.
+struct StagedMbtiles {
+    dir: PathBuf,
+    /// MBTiles `(zoom_level, tile_column, tile_row)` of each extracted file
+    keys: HashMap<PathBuf, (u32, i32, i32)>,
+    metadata: Vec<(String, String)>,
+}
+
+impl StagedMbtiles {
+    /// Extracts every tile of `path`, inflating gzip- or zlib-compressed
+    /// tile data, under file names with the row flipped from TMS to XYZ.
+    fn extract(path: &Path) -> Result<Self> {
+        let mut reader = MbtilesReader::open(path)?;
+        let metadata = reader.metadata().with_context(|| format!("Reading metadata from {:?}", path))?;
+        let dir = std::env::temp_dir().join(format!("bing-mbtiles-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).with_context(|| format!("Creating {:?}", dir))?;
+        let mut staged = Self { dir, keys: HashMap::new(), metadata };
+        reader
+            .tiles(&mut |tile| {
+                let MbTile { z, column, row, data } = tile;
+                if z > 30 || column < 0 || row < 0 || column >= 1 << z || row >= 1 << z {
+                    return Err(anyhow!("Tile {}/{}/{} is outside the tile grid", z, column, row));
+                }
+                let file = staged.dir.join(format!("{}.glb", tile_stem(z, column, flip_row(row, z))));
+                let data = decode_body(&data).with_context(|| format!("Inflating tile {}/{}/{}", z, column, row))?;
+                fs::write(&file, &data).with_context(|| format!("Writing {:?}", file))?;
+                staged.keys.insert(file, (z, column, row));
+                Ok(())
+            })
+            .with_context(|| format!("Reading tiles from {:?}", path))?;
+        Ok(staged)
+    }
+}
+
+impl Drop for StagedMbtiles {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// Writes the outputs of the `staged` tiles to a new MBTiles file under
+/// their original keys. Tiles that failed or have no output are left out.
+/// Returns the number of tiles written and left out.
+fn write_mbtiles(
+    path: &Path,
+    staged: &StagedMbtiles,
+    out_paths: &HashMap<PathBuf, Option<PathBuf>>,
+    failed: &[&PathBuf],
+) -> Result<(usize, usize)> {
+    let mut tiles: Vec<(&(u32, i32, i32), &PathBuf)> = staged.keys.iter().map(|(file, key)| (key, file)).collect();
+    tiles.sort();
+    let mut writer = MbtilesWriter::create(path)?;
+    let mut left_out = 0;
+    for (&(z, column, row), file) in tiles {
+        let output = out_paths.get(file).cloned().flatten().filter(|out| out.is_file());
+        let Some(output) = output.filter(|_| !failed.contains(&file)) else {
+            left_out += 1;
+            continue;
+        };
+        let data = fs::read(&output).with_context(|| format!("Reading {:?}", output))?;
+        writer.add(&MbTile { z, column, row, data })?;
+    }
+    let written = writer.finish(&staged.metadata)?;
+    Ok((written, left_out))
+}
+
 fn collect_glb_files(root: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
     let mut out = Vec::new();
 
@@ -230,9 +1110,103 @@ fn collect_glb_files(root: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
     Ok(out)
 }
 
-fn has_glb_ext(path: &Path) -> bool {
+/// Reads the `--files-from` list: newline-separated paths resolved against
+/// `input_dir`, or a download manifest whose paths are relative to its own
+/// directory. Missing files, non-GLB/glTF paths and paths outside `input_dir`
+/// are reported and left out.
+fn read_file_list(list: &Path, input_dir: &Path) -> Result<Vec<PathBuf>> {
+    let is_manifest = list
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let listed: Vec<PathBuf> = if is_manifest {
+        let base = list.parent().unwrap_or(Path::new("."));
+        Manifest::read(list)?
+            .tiles
+            .iter()
+            .map(|t| base.join(&t.path))
+            .collect()
+    } else {
+        fs::read_to_string(list)
+            .with_context(|| format!("Reading file list {:?}", list))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| input_dir.join(line))
+            .collect()
+    };
+
+    let mut files = Vec::with_capacity(listed.len());
+    let mut rejected = 0;
+    for path in listed {
+        // A manifest at `tiles/manifest.json` yields `tiles/...` paths, which
+        // lie under the default input directory `.` without sharing its prefix.
+        let path = if input_dir == Path::new(".") && path.is_relative() && !path.starts_with(".") {
+            input_dir.join(path)
+        } else {
+            path
+        };
+        let problem = if !path.is_file() {
+            Some("missing")
+        } else if !(has_glb_ext(&path) || has_ext(&path, "gltf")) {
+            Some("not a .glb/.gltf file")
+        } else if !path.starts_with(input_dir) {
+            Some("outside the input directory")
+        } else {
+            None
+        };
+        match problem {
+            Some(problem) => {
+                eprintln!("WARNING: listed file {:?} is {}", path, problem);
+                rejected += 1;
+            }
+            None => files.push(path),
+        }
+    }
+    if rejected > 0 {
+        eprintln!("WARNING: {} listed file(s) left out", rejected);
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn has_ext(path: &Path, wanted: &str) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(wanted))
+}
+
+pub(crate) fn has_glb_ext(path: &Path) -> bool {
     path.extension()
         .and_then(OsStr::to_str)
         .map(|ext| ext.eq_ignore_ascii_case("glb"))
         .unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_treats_out_of_range_tile_names_as_unparsed() {
+        let dir = std::env::temp_dir().join(format!("bing-test-mask-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mask_path = dir.join("world.geojson");
+        fs::write(
+            &mask_path,
+            r#"{"type":"Polygon","coordinates":[[[-180,-85],[180,-85],[180,85],[-180,85],[-180,-85]]]}"#,
+        )
+        .unwrap();
+        let mask = Mask::read_geojson(&mask_path).unwrap();
+
+        let files: Vec<PathBuf> =
+            ["3_1_1.glb", "64_1_1.glb", "31_0_0.glb", "3_-1_2.glb", "3/2/-1.glb"].iter().map(|f| dir.join(f)).collect();
+        let (kept, not_tiles) = apply_mask(files.clone(), &dir, &mask, Unparsed::Skip);
+        assert_eq!(kept, vec![dir.join("3_1_1.glb")]);
+        assert_eq!(not_tiles, 4);
+        let (kept, not_tiles) = apply_mask(files.clone(), &dir, &mask, Unparsed::Process);
+        assert_eq!(kept, files);
+        assert_eq!(not_tiles, 4);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,125 @@
+use crate::verify::sha256_hex;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Outcome of [`dedupe_identical`].
+#[derive(Debug, Default)]
+pub struct DedupeReport {
+    /// Files replaced by a link to an identical earlier file
+    pub linked: usize,
+    pub bytes_saved: u64,
+    /// Set when the filesystem supports neither hard nor symbolic links; the
+    /// remaining duplicates were left as they were
+    pub unsupported: Option<String>,
+}
+
+/// Replaces byte-identical files among `paths` with hard links (symbolic
+/// links where hard links fail) to the first copy in path order. Files are
+/// only hashed when another file has the same size, and files that already
+/// share an inode are left alone.
+pub fn dedupe_identical(paths: &[PathBuf]) -> Result<DedupeReport> {
+    let mut paths = paths.to_vec();
+    paths.sort();
+    paths.dedup();
+
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in paths {
+        let len = fs::metadata(&path).with_context(|| format!("Reading {:?}", path))?.len();
+        by_size.entry(len).or_default().push(path);
+    }
+    let candidates: Vec<(u64, PathBuf)> = by_size
+        .into_iter()
+        .filter(|(_, group)| group.len() > 1)
+        .flat_map(|(len, group)| group.into_iter().map(move |p| (len, p)))
+        .collect();
+
+    let hashed: Vec<(u64, String, PathBuf)> = candidates
+        .into_par_iter()
+        .map(|(len, path)| {
+            let bytes = fs::read(&path).with_context(|| format!("Reading {:?}", path))?;
+            Ok((len, sha256_hex(&bytes), path))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut groups: HashMap<(u64, String), Vec<PathBuf>> = HashMap::new();
+    for (len, hash, path) in hashed {
+        groups.entry((len, hash)).or_default().push(path);
+    }
+    let mut groups: Vec<_> = groups.into_iter().filter(|(_, g)| g.len() > 1).collect();
+    for (_, group) in &mut groups {
+        group.sort();
+    }
+    groups.sort_by(|a, b| a.1[0].cmp(&b.1[0]));
+
+    let mut report = DedupeReport::default();
+    for ((len, _), group) in groups {
+        let original = &group[0];
+        for duplicate in &group[1..] {
+            if same_file(original, duplicate) {
+                continue;
+            }
+            match replace_with_link(original, duplicate) {
+                Ok(()) => {
+                    report.linked += 1;
+                    report.bytes_saved += len;
+                }
+                Err(e) if is_unsupported(&e) => {
+                    report.unsupported = Some(e.to_string());
+                    return Ok(report);
+                }
+                Err(e) => return Err(e).with_context(|| format!("Linking {:?}", duplicate)),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Points `duplicate` at `original` through a temporary name, so a failure
+/// never loses the duplicate's data.
+fn replace_with_link(original: &Path, duplicate: &Path) -> io::Result<()> {
+    let mut tmp = duplicate.as_os_str().to_os_string();
+    tmp.push(".dedupe");
+    let tmp = PathBuf::from(tmp);
+    fs::remove_file(&tmp).ok();
+    if let Err(hard) = fs::hard_link(original, &tmp) {
+        symlink(original, &tmp).map_err(|_| hard)?;
+    }
+    fs::rename(&tmp, duplicate).inspect_err(|_| {
+        fs::remove_file(&tmp).ok();
+    })
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::canonicalize(original)?, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+fn is_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied | io::ErrorKind::CrossesDevices
+    )
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
@@ -0,0 +1,197 @@
+use crate::decompress::has_glb_ext;
+use crate::stats::{format_bytes, parse_tile_path};
+use crate::tile::tile_xy_to_quadkey;
+use crate::verify::sha256_hex;
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use flate2::read::MultiGzDecoder;
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Tiles of each kind listed on screen; the JSON report has them all.
+const LISTED: usize = 10;
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Earlier tile directory
+    pub old: PathBuf,
+
+    /// Later tile directory, e.g. a fresh download of the same area
+    pub new: PathBuf,
+
+    /// Also write the full comparison as JSON to this file
+    #[arg(long = "json")]
+    pub json: Option<PathBuf>,
+
+    /// Write the quadkeys of changed and new tiles here (one per line, for download --quadkeys-stdin)
+    #[arg(long = "changed-file")]
+    pub changed_file: Option<PathBuf>,
+}
+
+/// A tile found in a directory: its path and the size and SHA-256 of its
+/// content, inflated first for `.glb.gz` files.
+struct Found {
+    path: PathBuf,
+    bytes: u64,
+    hash: String,
+}
+
+type Tiles = BTreeMap<(u32, i32, i32), Found>;
+
+/// Compares two tile directories by tile coordinates, so their layouts and
+/// `--gzip` settings may differ, and reports tiles only in one of them and
+/// tiles whose content changed.
+pub fn run_diff(args: Args) -> Result<()> {
+    let (old, old_unparsed) = scan(&args.old)?;
+    let (new, new_unparsed) = scan(&args.new)?;
+
+    let only_old: Vec<_> = old.keys().filter(|k| !new.contains_key(k)).copied().collect();
+    let only_new: Vec<_> = new.keys().filter(|k| !old.contains_key(k)).copied().collect();
+    let changed: Vec<_> = old
+        .iter()
+        .filter(|(k, o)| new.get(k).is_some_and(|n| n.hash != o.hash))
+        .map(|(k, _)| *k)
+        .collect();
+    let identical = old.len() - only_old.len() - changed.len();
+
+    println!("Old: {} ({} tiles)", args.old.display(), old.len());
+    println!("New: {} ({} tiles)", args.new.display(), new.len());
+    if old_unparsed + new_unparsed > 0 {
+        println!("Skipped {} files whose names don't parse as tiles", old_unparsed + new_unparsed);
+    }
+    println!(
+        "Only in old: {}, only in new: {}, changed: {}, identical: {}",
+        only_old.len(),
+        only_new.len(),
+        changed.len(),
+        identical
+    );
+    list("only in old", &only_old, |k| old[k].path.display().to_string());
+    list("only in new", &only_new, |k| new[k].path.display().to_string());
+    list("changed", &changed, |k| {
+        format!("{} -> {}", format_bytes(old[k].bytes), format_bytes(new[k].bytes))
+    });
+
+    if let Some(path) = &args.json {
+        let tiles = |keys: &[(u32, i32, i32)], side: &Tiles| -> Vec<Value> {
+            keys.iter()
+                .map(|&(z, x, y)| {
+                    let found = &side[&(z, x, y)];
+                    json!({
+                        "z": z,
+                        "x": x,
+                        "y": y,
+                        "quadkey": tile_xy_to_quadkey(x, y, z),
+                        "path": found.path.to_string_lossy(),
+                        "bytes": found.bytes,
+                    })
+                })
+                .collect()
+        };
+        let changed_tiles: Vec<Value> = changed
+            .iter()
+            .map(|&(z, x, y)| {
+                let (o, n) = (&old[&(z, x, y)], &new[&(z, x, y)]);
+                json!({
+                    "z": z,
+                    "x": x,
+                    "y": y,
+                    "quadkey": tile_xy_to_quadkey(x, y, z),
+                    "old_bytes": o.bytes,
+                    "new_bytes": n.bytes,
+                    "old_sha256": o.hash,
+                    "new_sha256": n.hash,
+                })
+            })
+            .collect();
+        let report = json!({
+            "old": args.old.to_string_lossy(),
+            "new": args.new.to_string_lossy(),
+            "totals": {
+                "old": old.len(),
+                "new": new.len(),
+                "only_in_old": only_old.len(),
+                "only_in_new": only_new.len(),
+                "changed": changed.len(),
+                "identical": identical,
+            },
+            "only_in_old": tiles(&only_old, &old),
+            "only_in_new": tiles(&only_new, &new),
+            "changed": changed_tiles,
+        });
+        fs::write(path, serde_json::to_vec_pretty(&report)?)
+            .with_context(|| format!("Writing diff report {:?}", path))?;
+        println!("Wrote diff report {}", path.display());
+    }
+
+    if let Some(path) = &args.changed_file {
+        let mut keys: Vec<_> = changed.iter().chain(&only_new).copied().collect();
+        keys.sort();
+        let mut text: String = keys
+            .iter()
+            .map(|&(z, x, y)| tile_xy_to_quadkey(x, y, z))
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        fs::write(path, text).with_context(|| format!("Writing changed-tile list {:?}", path))?;
+        println!("Wrote {} changed and new quadkeys to {}", keys.len(), path.display());
+    }
+    Ok(())
+}
+
+fn list(kind: &str, keys: &[(u32, i32, i32)], detail: impl Fn(&(u32, i32, i32)) -> String) {
+    for key @ &(z, x, y) in keys.iter().take(LISTED) {
+        println!("  {} z{} x{} y{} ({}): {}", kind, z, x, y, tile_xy_to_quadkey(x, y, z), detail(key));
+    }
+    if keys.len() > LISTED {
+        println!("  ... and {} more {}", keys.len() - LISTED, kind);
+    }
+}
+
+/// Hashes every `.glb` and `.glb.gz` tile under `dir`. Returns the tiles by
+/// `(z, x, y)` and the number of files whose names don't parse as tiles.
+fn scan(dir: &Path) -> Result<(Tiles, usize)> {
+    if !dir.is_dir() {
+        return Err(anyhow!("{} is not a directory", dir.display()));
+    }
+    let mut files = Vec::new();
+    let mut unparsed = 0;
+    for entry in WalkDir::new(dir).follow_links(true) {
+        let entry = entry?;
+        let path = entry.path();
+        let gzipped = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gz"));
+        let tile_path = if gzipped { path.with_extension("") } else { path.to_path_buf() };
+        if !entry.file_type().is_file() || !has_glb_ext(&tile_path) {
+            continue;
+        }
+        let rel = tile_path.strip_prefix(dir).unwrap_or(&tile_path);
+        match parse_tile_path(rel) {
+            Some(key) => files.push((key, entry.into_path(), gzipped)),
+            None => unparsed += 1,
+        }
+    }
+
+    let found: Vec<((u32, i32, i32), Found)> = files
+        .into_par_iter()
+        .map(|(key, path, gzipped)| {
+            let mut bytes = fs::read(&path).with_context(|| format!("Reading {:?}", path))?;
+            if gzipped {
+                let mut inflated = Vec::new();
+                MultiGzDecoder::new(bytes.as_slice())
+                    .read_to_end(&mut inflated)
+                    .with_context(|| format!("Inflating {:?}", path))?;
+                bytes = inflated;
+            }
+            let hash = sha256_hex(&bytes);
+            Ok((key, Found { path, bytes: bytes.len() as u64, hash }))
+        })
+        .collect::<Result<_>>()?;
+    Ok((found.into_iter().collect(), unparsed))
+}
@@ -1,21 +1,214 @@
+use crate::decompress::has_glb_ext;
+use crate::dedupe::dedupe_identical;
+use crate::error::DownloadError;
+use crate::glb::check_header;
+use crate::layout::{flip_row, quadkey_prefix, tile_stem};
+use crate::manifest::{Manifest, ManifestTile, ManifestWriter};
+use crate::summary::{redact_key, RunSummary};
+use crate::merge::{write_merged, MergeInput};
+use crate::progress::{self, ColorMode, PlainProgress, PLAIN_INTERVAL};
+use crate::preview::{write_preview, PreviewEntry};
+use crate::tile::{
+    bbox_tile_ranges, bbox_wsen, checked_lat, clamp_lat, create_square_bbox, fractional_tile_to_lonlat, ground_resolution,
+    haversine_distance, quadkey_bounds, quadkey_to_tile_xy, zoom_for_resolution, range_tile_count, tile_bounds, tiles_in_ranges, tiles_lon_span, span_columns, common_quadkey_prefix, tile_center, tile_xy_to_quadkey,
+    wrap_lon, EARTH_CIRCUMFERENCE_M, TILE_SIZE_PX,
+};
+use crate::stats::{format_bytes, format_duration};
+use crate::tileset::{write_tileset, TilesetEntry};
+use crate::tms::{write_tms_metadata, MatrixLimits};
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future::join_all;
 use futures::stream::{self, StreamExt};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::cmp::{max, min};
-use std::f64::consts::PI;
-use std::fs;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufRead, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::task::{self, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::fs as tokio_fs;
+use tokio::io::AsyncWriteExt;
+use tracing::Instrument;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tower_layer::Layer;
+use tower_service::Service;
+use walkdir::WalkDir;
 
-const EARTH_LAT_MAX: f64 = 85.05112878;
-const DEFAULT_HOST: &str = "https://t.ssl.ak.tiles.virtualearth.net";
+pub(crate) const DEFAULT_HOSTS: [&str; 4] = [
+    "https://t0.ssl.ak.tiles.virtualearth.net",
+    "https://t1.ssl.ak.tiles.virtualearth.net",
+    "https://t2.ssl.ak.tiles.virtualearth.net",
+    "https://t3.ssl.ak.tiles.virtualearth.net",
+];
 const DEFAULT_G: &str = "15340";
 const DEFAULT_TF: &str = "3dv4";
-const USER_AGENT: &str = "TileFetcher/1.0 (+https://example.local)";
+pub(crate) const DEFAULT_URL_TEMPLATE: &str = "{host}/tiles/mtx{quadkey}?g={g}&tf={tf}&n=z&key={key}&form={form}";
+/// Bing's 2D aerial imagery endpoint, used by `--imagery`.
+const IMAGERY_URL_TEMPLATE: &str = "{host}/tiles/a{quadkey}.jpeg?g={g}";
+pub(crate) const USER_AGENT: &str = "TileFetcher/1.0 (+https://example.local)";
+/// `--polite` settings: the concurrency ceiling, the least spacing between
+/// requests to one host, and the User-Agent used unless one is given.
+const POLITE_CONCURRENCY: usize = 4;
+const POLITE_HOST_DELAY_MS: u64 = 500;
+const POLITE_USER_AGENT: &str = concat!(
+    "bing-maps-tile-downloader/",
+    env!("CARGO_PKG_VERSION"),
+    " (polite mode; +https://github.com/s1dny/bing-maps-tile-downloader)"
+);
+/// Public key used when no `--api-key` is given.
+pub(crate) const DEFAULT_API_KEY: &str = "Ar9wCt_eD79MwUsC3wup-erRDfnN0VKqPSZQ4yiCNDucBOJBeflFCNZQUgocler6";
+const KEY_COOLDOWN: Duration = Duration::from_secs(60);
+const MAX_ZOOM: u32 = 23;
+/// Default `--max-tile-bytes`: mtx tiles are at most a few MB, so anything
+/// past 16 MiB is a misbehaving endpoint rather than a tile.
+const DEFAULT_MAX_TILE_BYTES: u64 = 16 * 1024 * 1024;
+/// Abort when this many of the first completed requests are all HTTP 403.
+const EARLY_FORBIDDEN_LIMIT: usize = 10;
+const MAX_THROTTLE_PAUSE: Duration = Duration::from_secs(300);
+/// Pause before the first `--auto-retry` pass; doubles on each later pass.
+const AUTO_RETRY_BASE_DELAY: Duration = Duration::from_secs(10);
+const AUTO_RETRY_MAX_DELAY: Duration = Duration::from_secs(120);
+/// Pause before the first `--rename-retries` retry; doubles on each later one.
+const RENAME_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ClipMode {
+    /// Every tile touching the region
+    Intersect,
+    /// Only tiles lying fully inside the region
+    Contain,
+}
+
+/// What one `--rate-limit` token bucket covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RateLimitScope {
+    /// One bucket shared by all hosts
+    Global,
+    /// A bucket per host, so the total rate grows with the host count
+    PerHost,
+}
+
+/// Order in which the tiles of each zoom level are requested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TileOrder {
+    /// Row by row from the north-west corner
+    Row,
+    /// Nearest to the centre of the region first, so an interrupted run
+    /// leaves a usable core
+    Distance,
+}
+
+/// Line format of `--list`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    /// `z x y`, space-separated
+    Zxy,
+    /// The tile's quadkey
+    Quadkey,
+    /// The request URL, API key included
+    Url,
+}
+
+/// Payload requested through the tile URL's `form` parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TileForm {
+    /// 3D mesh tiles (.glb)
+    Web3d,
+    /// JPEG imagery tiles (.jpeg)
+    Jpeg,
+    /// PNG imagery tiles (.png)
+    Png,
+}
+
+impl TileForm {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TileForm::Web3d => "web3d",
+            TileForm::Jpeg => "jpeg",
+            TileForm::Png => "png",
+        }
+    }
+
+    /// File extension for tiles of this form.
+    pub fn extension(self) -> &'static str {
+        match self {
+            TileForm::Web3d => "glb",
+            TileForm::Jpeg => "jpeg",
+            TileForm::Png => "png",
+        }
+    }
+}
+
+/// How `download` treats a tile that already exists on disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OverwritePolicy {
+    /// Keep existing tiles without requesting them (unless --refresh-older-than or --conditional asks)
+    Skip,
+    /// Always download and replace
+    Overwrite,
+    /// Download, but replace only if the new body is larger (repairs truncated files)
+    IfLarger,
+    /// Replace only if the server's Last-Modified is newer than the file
+    IfNewer,
+}
+
+impl OverwritePolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            OverwritePolicy::Skip => "skip",
+            OverwritePolicy::Overwrite => "overwrite",
+            OverwritePolicy::IfLarger => "if-larger",
+            OverwritePolicy::IfNewer => "if-newer",
+        }
+    }
+}
+
+/// `--trace` output format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TraceFormat {
+    /// One human-readable line per finished request
+    Text,
+    /// One JSON object per finished request
+    Json,
+}
+
+/// Axis order used when printing a bounding box. Longitudes are printed
+/// west edge first, so a box crossing the antimeridian has min_lon > max_lon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BboxFormat {
+    /// min_lat,min_lon,max_lat,max_lon
+    Latlon,
+    /// min_lon,min_lat,max_lon,max_lat (GeoJSON)
+    Lonlat,
+}
+
+impl BboxFormat {
+    fn format(self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> String {
+        let (min_lat, max_lat) = (lat1.min(lat2), lat1.max(lat2));
+        let (min_lon, max_lon) = (lon1, lon2);
+        match self {
+            BboxFormat::Latlon => format!(
+                "{:.6},{:.6},{:.6},{:.6} (min_lat,min_lon,max_lat,max_lon)",
+                min_lat, min_lon, max_lat, max_lon
+            ),
+            BboxFormat::Lonlat => format!(
+                "{:.6},{:.6},{:.6},{:.6} (min_lon,min_lat,max_lon,max_lat)",
+                min_lon, min_lat, max_lon, max_lat
+            ),
+        }
+    }
+}
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -27,331 +220,4256 @@ pub struct Args {
     #[arg(long = "ne-coord")]
     pub ne_coord: Option<String>,
 
+    /// Axis order for the printed bbox: latlon or lonlat (GeoJSON)
+    #[arg(long = "bbox-format", value_enum, default_value_t = BboxFormat::Latlon)]
+    pub bbox_format: BboxFormat,
+
+    /// Error out when a bbox latitude is beyond ±85.05° instead of clamping it
+    #[arg(long = "no-clamp-lat")]
+    pub no_clamp_lat: bool,
+
+    /// Read quadkeys to download from stdin, one per line, instead of a bbox (zoom = quadkey length)
+    #[arg(long = "quadkeys-stdin")]
+    pub quadkeys_stdin: bool,
+
+    /// Abort on the first invalid line of --quadkeys-stdin instead of skipping it
+    #[arg(long = "strict-quadkeys", requires = "quadkeys_stdin")]
+    pub strict_quadkeys: bool,
+
+    /// Treat swapped --sw-coord/--ne-coord corners as an error instead of a warning
+    #[arg(long = "strict-bbox")]
+    pub strict_bbox: bool,
+
     /// Center (lat,lon)
-    #[arg(long = "center-coord")]
+    #[arg(long = "center-coord", group = "center")]
     pub center_coord: Option<String>,
 
+    /// Place name geocoded with the Bing Locations API (same key) and used as the center
+    #[cfg(feature = "geocode")]
+    #[arg(long = "place", group = "center", conflicts_with_all = ["sw_coord", "quadkeys_stdin"])]
+    pub place: Option<String>,
+
     /// Square size in meters
     #[arg(long = "size")]
     pub size: Option<f64>,
 
+    /// Skip tiles lying entirely inside this box, "sw_lat,sw_lon,ne_lat,ne_lon" (repeatable)
+    #[arg(long = "exclude-bbox", value_name = "BBOX")]
+    pub exclude_bbox: Vec<String>,
+
+    /// Download a circle of this radius in meters around the center instead of a square
+    #[arg(long = "radius", requires = "center", conflicts_with = "size")]
+    pub radius: Option<f64>,
+
     /// Output directory
     #[arg(long = "out", default_value = "./tiles")]
     pub out: PathBuf,
 
-    /// Bing API key
-    #[arg(long = "api-key", default_value = "Ar9wCt_eD79MwUsC3wup-erRDfnN0VKqPSZQ4yiCNDucBOJBeflFCNZQUgocler6")]
-    pub api_key: String,
+    /// Tile payload form; also picks the output extension (.glb, .jpeg or .png)
+    #[arg(long = "form", value_enum, default_value_t = TileForm::Web3d)]
+    pub form: TileForm,
+
+    /// Download Bing's 2D aerial imagery (.jpeg) for the region instead of 3D tiles
+    #[arg(long = "imagery", conflicts_with_all = ["form", "url_template"])]
+    pub imagery: bool,
+
+    /// Retries per tile after a transport error or a --retry-on status, each on the next host
+    #[arg(long = "retries", default_value_t = 3)]
+    pub retries: u32,
+
+    /// After the run, re-download the failed tiles up to this many more passes, with a growing pause between passes
+    #[arg(long = "auto-retry", default_value_t = 0)]
+    pub auto_retry: u32,
+
+    /// Backoff before the first retry of a tile, in milliseconds; doubles on each later retry
+    #[arg(long = "retry-base-ms", default_value_t = 500)]
+    pub retry_base_ms: u64,
+
+    /// Longest backoff between retries of a tile, in milliseconds; also caps Retry-After
+    #[arg(long = "retry-max-ms", default_value_t = 30_000)]
+    pub retry_max_ms: u64,
+
+    /// Randomize each backoff to between half and all of its nominal delay (reproducible with --seed)
+    #[arg(long = "retry-jitter")]
+    pub retry_jitter: bool,
+
+    /// HTTP status codes that trigger a retry with exponential backoff (comma-separated)
+    #[arg(long = "retry-on", value_delimiter = ',', default_value = "429,500,502,503,504")]
+    pub retry_on: Vec<u16>,
+
+    /// Tile hosts, used round-robin with failover to the next on errors (repeatable or comma-separated)
+    #[arg(long = "hosts", visible_alias = "host", value_delimiter = ',', default_values = DEFAULT_HOSTS)]
+    pub hosts: Vec<String>,
+
+    /// Start each tile on a randomly chosen host instead of round-robin; failover still moves to the next host
+    #[arg(long = "randomize-hosts")]
+    pub randomize_hosts: bool,
 
-    /// Zoom level (max ~20)
-    #[arg(long = "zoom", default_value_t = 18)]
-    pub zoom: u32,
+    /// Seed for random choices such as --randomize-hosts and --retry-jitter, to reproduce a run (random when omitted)
+    #[arg(long = "seed")]
+    pub seed: Option<u64>,
+
+    /// Request URL with {host}, {quadkey}, {g}, {tf}, {key}, {x}, {y}, {z} and {form} placeholders
+    #[arg(long = "url-template", default_value = DEFAULT_URL_TEMPLATE)]
+    pub url_template: String,
+
+    /// Bing API key (repeat or comma-separate to rotate through several keys)
+    #[arg(
+        long = "api-key",
+        visible_alias = "api-keys",
+        value_delimiter = ',',
+        default_value = DEFAULT_API_KEY
+    )]
+    pub api_key: Vec<String>,
+
+    /// Which boundary tiles to keep
+    #[arg(long = "clip-mode", value_enum, default_value_t = ClipMode::Intersect)]
+    pub clip_mode: ClipMode,
+
+    /// Download order within each zoom level: row (north to south) or distance from the centre
+    #[arg(long = "order", value_enum, default_value_t = TileOrder::Row)]
+    pub order: TileOrder,
+
+    /// Zoom level (max ~20); comma-separate to download several levels
+    #[arg(long = "zoom", value_delimiter = ',', default_value = "18")]
+    pub zoom: Vec<u32>,
+
+    /// Pick the zoom whose ground resolution at the bbox centre is nearest this many metres per pixel
+    #[arg(long = "resolution", conflicts_with = "zoom")]
+    pub resolution: Option<f64>,
+
+    /// Download every zoom from --min-zoom to --max-zoom, deriving each lower level from the parents of the --max-zoom tiles, into one directory per zoom
+    #[arg(
+        long = "pyramid",
+        requires_all = ["min_zoom", "max_zoom"],
+        conflicts_with_all = ["zoom", "resolution", "quadkeys_stdin"]
+    )]
+    pub pyramid: bool,
+
+    /// Lowest zoom of a --pyramid
+    #[arg(long = "min-zoom", requires = "pyramid")]
+    pub min_zoom: Option<u32>,
+
+    /// Highest zoom of a --pyramid; its tiles are the ones selected by the region
+    #[arg(long = "max-zoom", requires = "pyramid")]
+    pub max_zoom: Option<u32>,
+
+    /// Also download the single smallest tile containing the whole region into <out>/overview/, as low-res context
+    #[arg(long = "overview")]
+    pub overview: bool,
+
+    /// Write each zoom level under its own `z<zoom>/` directory, above any --split or --qk-prefix-depth folders
+    #[arg(long = "split-by-zoom")]
+    pub split_by_zoom: bool,
+
+    /// Number the y in file names from the south (TMS rows) while still requesting XYZ tiles; the flat-layout counterpart of `relayout --to tms`
+    #[arg(long = "flip-y")]
+    pub flip_y: bool,
 
     /// Concurrent requests
     #[arg(long = "concurrency", default_value_t = 100)]
     pub concurrency: usize,
 
+    /// Tiles written to disk at once, independent of --concurrency
+    #[arg(long = "io-concurrency", default_value_t = 16)]
+    pub io_concurrency: usize,
+
+    /// Retries of a failed move of a finished .part file into place, before falling back to copy and delete
+    #[arg(long = "rename-retries", default_value_t = 5)]
+    pub rename_retries: u32,
+
+    /// Generate and download region tiles this many at a time instead of listing them all up front, rewriting --manifest after each batch
+    #[arg(long = "batch-size", visible_alias = "max-tiles-per-batch", conflicts_with = "sample")]
+    pub batch_size: Option<usize>,
+
+    /// Least time between two requests to the same host, in milliseconds
+    #[arg(long = "host-delay-ms", default_value_t = 0)]
+    pub host_delay_ms: u64,
+
+    /// Be gentle with the service: at most 4 concurrent requests, at least 500ms between requests per host, and a descriptive User-Agent
+    #[arg(long = "polite")]
+    pub polite: bool,
+
+    /// Send at most this many requests per second, with bursts of up to one second's worth
+    #[arg(long = "rate-limit")]
+    pub rate_limit: Option<f64>,
+
+    /// Whether --rate-limit applies to all hosts together or to each host separately
+    #[arg(long = "rate-limit-scope", value_enum, default_value_t = RateLimitScope::Global, requires = "rate_limit")]
+    pub rate_limit_scope: RateLimitScope,
+
+    /// Adjust in-flight requests to reach roughly this many tiles per second (--concurrency becomes the ceiling)
+    #[arg(long = "target-tps")]
+    pub target_tps: Option<f64>,
+
+    /// Pause all workers after this many HTTP 429s within --throttle-window (0 = never)
+    #[arg(long = "throttle-threshold", default_value_t = 20)]
+    pub throttle_threshold: usize,
+
+    /// Window in seconds for counting HTTP 429s
+    #[arg(long = "throttle-window", default_value_t = 10)]
+    pub throttle_window: u64,
+
+    /// Initial global pause in seconds; doubles on each consecutive pause unless Retry-After is given
+    #[arg(long = "throttle-pause", default_value_t = 5)]
+    pub throttle_pause: u64,
+
+    /// Only re-download existing tiles last written more than this many days ago
+    #[arg(long = "refresh-older-than")]
+    pub refresh_older_than: Option<f64>,
+
+    /// After the run, remove subdirectories this run created that ended up empty
+    #[arg(long = "trim-empty-dirs")]
+    pub trim_empty_dirs: bool,
+
+    /// After the run, replace byte-identical saved tiles with links to the first copy
+    #[arg(long = "dedupe-identical")]
+    pub dedupe_identical: bool,
+
+    /// Abort if the bbox/zoom would queue more than this many tiles
+    #[arg(long = "max-tiles", default_value_t = 100_000)]
+    pub max_tiles: usize,
+
+    /// Instead of aborting when --max-tiles is exceeded, lower the zoom until the tiles fit
+    #[arg(long = "auto-downgrade-zoom", conflicts_with_all = ["force", "quadkeys_stdin"])]
+    pub auto_downgrade_zoom: bool,
+
+    /// Bypass safety checks such as --max-tiles
+    #[arg(long = "force")]
+    pub force: bool,
+
+    /// Print the tile plan and exit without downloading anything
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Print the region's tiles to stdout, one per line in --list-format, and exit without downloading or any other output
+    #[arg(long = "list", conflicts_with_all = ["dry_run", "validate_only"])]
+    pub list: bool,
+
+    /// Line format for --list
+    #[arg(long = "list-format", value_enum, default_value_t = ListFormat::Zxy, requires = "list")]
+    pub list_format: ListFormat,
+
+    /// With --dry-run, compare the plan with the output directory: how many tiles would be downloaded, skipped or overwritten
+    #[arg(long = "diff", requires = "dry_run")]
+    pub diff: bool,
+
+    /// Download nothing; check that the output directory has every tile the bbox/zoom implies
+    #[arg(long = "validate-only", conflicts_with = "dry_run")]
+    pub validate_only: bool,
+
+    /// With --validate-only, write the missing tiles' quadkeys here (one per line, for --quadkeys-stdin)
+    #[arg(long = "missing-file", requires = "validate_only")]
+    pub missing_file: Option<PathBuf>,
+
+    /// Before downloading, send this many throwaway HEAD requests (default 4) to each host to resolve DNS and fill the connection pool
+    #[arg(long = "warmup", num_args = 0..=1, default_missing_value = "4")]
+    pub warmup: Option<usize>,
+
+    /// With --dry-run, fetch this many sample tiles (default 10) to estimate total time and size
+    #[arg(long = "sample", num_args = 0..=1, default_missing_value = "10", requires = "dry_run")]
+    pub sample: Option<usize>,
+
     /// Split tiles into a grid of subdirectories (must be a perfect square: 1, 4, 9, 16, 25, etc.)
     #[arg(long = "split", default_value_t = 1)]
     pub split: usize,
-}
 
-#[inline]
-fn meters_to_degrees(meters: f64, lat_deg: f64) -> (f64, f64) {
-    // Spherical approximations consistent with Web Mercator usage.
-    let r = 6_371_000.0_f64;
-    let lat = meters / (r * PI / 180.0);
-    let lon = meters / (r * (lat_deg.to_radians().cos()) * PI / 180.0);
-    (lat, lon)
-}
-
-#[inline]
-fn create_square_bbox(center_lat: f64, center_lon: f64, size_m: f64) -> (f64, f64, f64, f64) {
-    let half = size_m / 2.0;
-    let (dlat, dlon) = meters_to_degrees(half, center_lat);
-    (
-        center_lat - dlat,
-        center_lon - dlon,
-        center_lat + dlat,
-        center_lon + dlon,
-    )
+    /// Nest files in directories named after the first N quadkey digits (0 = off)
+    #[arg(long = "qk-prefix-depth", default_value_t = 0)]
+    pub qk_prefix_depth: usize,
+
+    /// Per-request timeout in seconds
+    #[arg(long = "request-timeout", default_value_t = 30)]
+    pub request_timeout: u64,
+
+    /// Connection timeout in seconds
+    #[arg(long = "connect-timeout", default_value_t = 10)]
+    pub connect_timeout: u64,
+
+    /// User-Agent sent with every request
+    #[arg(long = "user-agent", default_value = USER_AGENT)]
+    pub user_agent: String,
+
+    /// Accept any Content-Type instead of failing tiles served as text, HTML, JSON or XML
+    #[arg(long = "no-content-type-check")]
+    pub no_content_type_check: bool,
+
+    /// Abort a tile whose body grows past this many bytes and count it as failed (0 = no limit)
+    #[arg(long = "max-tile-bytes", default_value_t = DEFAULT_MAX_TILE_BYTES)]
+    pub max_tile_bytes: u64,
+
+    /// Extra HTTP header sent with every request ("Name: Value", repeatable)
+    #[arg(long = "header")]
+    pub header: Vec<String>,
+
+    /// Follow at most this many redirects per tile request
+    #[arg(long = "max-redirects", default_value_t = 10)]
+    pub max_redirects: usize,
+
+    /// Never follow redirects; a 3xx response counts as a failed tile
+    #[arg(long = "no-redirects", conflicts_with = "max_redirects")]
+    pub no_redirects: bool,
+
+    /// Print debug detail, such as tiles that were served via a redirect
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+
+    /// Disable connection keep-alive: open a new connection for every request
+    #[arg(long = "no-pool")]
+    pub no_pool: bool,
+
+    /// Emit a tracing span per tile request (quadkey, coordinates, host, status, bytes, duration) as text or JSON lines
+    #[arg(long = "trace", value_enum)]
+    pub trace: Option<TraceFormat>,
+
+    /// Write --trace output to this file instead of stderr
+    #[arg(long = "trace-file", requires = "trace")]
+    pub trace_file: Option<PathBuf>,
+
+    /// After downloading, merge every tile into a single GLB at this path
+    #[arg(long = "merge")]
+    pub merge: Option<PathBuf>,
+
+    /// Finalize complete leftover .part GLBs from an interrupted run instead of deleting them
+    #[arg(long = "resume-parts")]
+    pub resume_parts: bool,
+
+    /// Keep the body of a failed request as <tile>.failed, with a .failed.txt note of the status and first bytes
+    #[arg(long = "keep-failed-partials")]
+    pub keep_failed_partials: bool,
+
+    /// Gzip each saved tile on disk as z_x_y.<ext>.gz, recording both sizes in --manifest
+    #[arg(long = "gzip", conflicts_with_all = ["merge", "tileset", "preview"])]
+    pub gzip: bool,
+
+    /// Send If-None-Match/If-Modified-Since from a per-tile .etag sidecar; 304 keeps the file
+    #[arg(long = "conditional")]
+    pub conditional: bool,
+
+    /// What to do with tiles already on disk
+    #[arg(long = "overwrite-policy", value_enum, default_value_t = OverwritePolicy::Skip)]
+    pub overwrite_policy: OverwritePolicy,
+
+    /// Write one CSV row per tile (z,x,y,quadkey,url,west,south,east,north,status) as it finishes
+    #[arg(long = "csv")]
+    pub csv: Option<PathBuf>,
+
+    /// Write one CSV row of timings per tile (quadkey,z,x,y,bytes,status,retries,elapsed_ms) as it finishes
+    #[arg(long = "timings-csv", value_name = "PATH")]
+    pub timings_csv: Option<PathBuf>,
+
+    /// Append timestamped run start, failure and run end lines to this file
+    #[arg(long = "log-file")]
+    pub log_file: Option<PathBuf>,
+
+    /// After downloading, write a Cesium 3D Tiles tileset.json into this directory
+    #[arg(long = "tileset")]
+    pub tileset: Option<PathBuf>,
+
+    /// After downloading, write a PNG with one cell per tile colored by its final status
+    #[cfg(feature = "heatmap")]
+    #[arg(long = "heatmap")]
+    pub heatmap: Option<PathBuf>,
+
+    /// After downloading, write a JSON manifest of the run (bbox in GeoJSON order, saved tiles)
+    #[arg(long = "manifest")]
+    pub manifest: Option<PathBuf>,
+
+    /// Merge this run into an existing --manifest instead of replacing it: tiles deduped by quadkey (newest wins), runs listed under "runs"
+    #[arg(long = "manifest-append", requires = "manifest")]
+    pub manifest_append: bool,
+
+    /// Write a small JSON summary of the run (inputs with keys masked, times, totals, throughput), even when tiles failed
+    #[arg(long = "summary-json", value_name = "PATH")]
+    pub summary_json: Option<PathBuf>,
+
+    /// After downloading, write an HTML page previewing every saved tile
+    #[arg(long = "preview")]
+    pub preview: Option<PathBuf>,
+
+    /// After downloading, write an OGC WebMercatorQuad TileMatrixSet JSON describing the zooms and tile ranges covered
+    #[arg(long = "tms-metadata")]
+    pub tms_metadata: Option<PathBuf>,
+
+    /// Redraw progress bars at most every this many milliseconds; without a terminal, print a progress line this often (default: 5000)
+    #[arg(long = "progress-interval")]
+    pub progress_interval: Option<u64>,
+
+    /// Set from the top-level `--color`
+    #[arg(skip)]
+    pub color: ColorMode,
 }
 
-#[inline]
-fn clamp_lat(lat: f64) -> f64 {
-    lat.max(-EARTH_LAT_MAX).min(EARTH_LAT_MAX)
+/// Round-robin pool of API keys. Keys that get rejected or throttled are
+/// benched for `KEY_COOLDOWN` so the remaining keys pick up the load.
+pub(crate) struct KeyPool {
+    keys: Vec<String>,
+    next: AtomicUsize,
+    benched_until: Vec<Mutex<Option<Instant>>>,
 }
 
-#[inline]
-fn wrap_lon(lon: f64) -> f64 {
-    // Safe wrap into [-180, 180)
-    let mut l = lon % 360.0;
-    if l >= 180.0 {
-        l -= 360.0;
+impl KeyPool {
+    pub(crate) fn new(keys: Vec<String>) -> Result<Self> {
+        let keys: Vec<String> = keys
+            .into_iter()
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty())
+            .collect();
+        if keys.is_empty() {
+            return Err(anyhow!("At least one non-empty --api-key is required"));
+        }
+        let benched_until = keys.iter().map(|_| Mutex::new(None)).collect();
+        Ok(Self {
+            keys,
+            next: AtomicUsize::new(0),
+            benched_until,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
     }
-    if l < -180.0 {
-        l += 360.0;
+
+    /// Picks the next key in rotation, skipping benched keys. Falls back to
+    /// the plain round-robin choice when every key is benched.
+    fn pick(&self) -> usize {
+        let n = self.keys.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % n;
+        let now = Instant::now();
+        (0..n)
+            .map(|i| (start + i) % n)
+            .find(|&idx| {
+                self.benched_until[idx]
+                    .lock()
+                    .unwrap()
+                    .is_none_or(|until| until <= now)
+            })
+            .unwrap_or(start)
     }
-    l
-}
 
-#[inline]
-fn lonlat_to_tile_xy(lon: f64, lat: f64, z: u32) -> (i32, i32) {
-    let lat = clamp_lat(lat);
-    let lon = wrap_lon(lon);
-    let n = (1u32 << z) as f64;
-    let lat_rad = lat.to_radians();
+    pub(crate) fn key(&self, idx: usize) -> &str {
+        &self.keys[idx]
+    }
 
-    let xf = ((lon + 180.0) / 360.0) * n;
-    let yf = (0.5 - ( ( (PI / 4.0) + (lat_rad / 2.0) ).tan().ln() / (2.0 * PI) )) * n;
+    /// Benches a key; returns true if it was not already benched.
+    fn bench(&self, idx: usize) -> bool {
+        let mut until = self.benched_until[idx].lock().unwrap();
+        let was_active = until.is_none_or(|t| t <= Instant::now());
+        *until = Some(Instant::now() + KEY_COOLDOWN);
+        was_active
+    }
 
-    // Python's int() floors for positive values; ensure we floor.
-    (xf.floor() as i32, yf.floor() as i32)
+    /// Replaces every key in `s` with a placeholder so logs never leak keys.
+    pub(crate) fn mask(&self, s: &str) -> String {
+        self.keys
+            .iter()
+            .enumerate()
+            .fold(s.to_string(), |acc, (i, k)| acc.replace(k, &format!("<key#{}>", i + 1)))
+    }
 }
 
-#[inline]
-fn tile_xy_to_quadkey(x: i32, y: i32, z: u32) -> String {
-    let mut q = String::with_capacity(z as usize);
-    let x_temp = x;
-    let y_temp = y;
-    for i in (1..=z).rev() {
-        let mask = 1 << (i - 1);
-        let mut digit = 0;
-        if (x_temp & mask) != 0 { digit += 1; }
-        if (y_temp & mask) != 0 { digit += 2; }
-        q.push(char::from(b'0' + digit));
-    }
-    q
+/// Global cool-down shared by all workers. Once `threshold` 429s land within
+/// `window`, every worker waits out a pause that doubles for each consecutive
+/// pause, or follows the server's `Retry-After` when one was sent.
+struct Throttle {
+    threshold: usize,
+    window: Duration,
+    base_pause: Duration,
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    recent: VecDeque<Instant>,
+    paused_until: Option<Instant>,
+    consecutive_pauses: u32,
+    resume_pending: bool,
 }
 
-fn bbox_tile_ranges(lat1: f64, lon1: f64, lat2: f64, lon2: f64, z: u32) -> Vec<(i32, i32, i32, i32)> {
-    let a_lon = wrap_lon(lon1);
-    let b_lon = wrap_lon(lon2);
-    let a_lat = clamp_lat(lat1);
-    let b_lat = clamp_lat(lat2);
+impl Throttle {
+    fn new(threshold: usize, window: Duration, base_pause: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            base_pause,
+            state: Mutex::new(ThrottleState::default()),
+        }
+    }
 
-    let (lon_min, lon_max) = if a_lon <= b_lon { (a_lon, b_lon) } else { (b_lon, a_lon) };
-    let (lat_min, lat_max) = if a_lat <= b_lat { (a_lat, b_lat) } else { (b_lat, a_lat) };
+    /// Waits while a global pause is active. Returns true for exactly one
+    /// caller after each pause ends, so the resume can be logged once.
+    async fn wait(&self) -> bool {
+        loop {
+            let until = {
+                let mut state = self.state.lock().unwrap();
+                match state.paused_until {
+                    Some(until) if until > Instant::now() => until,
+                    _ => return std::mem::take(&mut state.resume_pending),
+                }
+            };
+            tokio::time::sleep_until(until.into()).await;
+        }
+    }
 
-    let crosses_am = a_lon > b_lon;
+    /// Records a 429. Returns the pause length if this started a new pause.
+    fn record_429(&self, retry_after: Option<Duration>) -> Option<Duration> {
+        if self.threshold == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if state.paused_until.is_some_and(|until| until > now) {
+            return None;
+        }
+        state.recent.push_back(now);
+        while state
+            .recent
+            .front()
+            .is_some_and(|&t| now.duration_since(t) > self.window)
+        {
+            state.recent.pop_front();
+        }
+        if state.recent.len() < self.threshold {
+            return None;
+        }
 
-    let y1 = lonlat_to_tile_xy(lon_min, lat_min, z).1;
-    let y2 = lonlat_to_tile_xy(lon_min, lat_max, z).1;
-    let y3 = lonlat_to_tile_xy(lon_max, lat_min, z).1;
-    let y4 = lonlat_to_tile_xy(lon_max, lat_max, z).1;
-    let y_min = min(min(y1, y2), min(y3, y4));
-    let y_max = max(max(y1, y2), max(y3, y4));
+        let backoff = self
+            .base_pause
+            .saturating_mul(1 << state.consecutive_pauses.min(16));
+        let pause = retry_after.unwrap_or(backoff).min(MAX_THROTTLE_PAUSE);
+        state.recent.clear();
+        state.paused_until = Some(now + pause);
+        state.consecutive_pauses += 1;
+        state.resume_pending = true;
+        Some(pause)
+    }
 
-    if !crosses_am {
-        let x_min = lonlat_to_tile_xy(lon_min, lat_min, z).0;
-        let x_max = lonlat_to_tile_xy(lon_max, lat_min, z).0;
-        vec![(x_min, x_max, y_min, y_max)]
-    } else {
-        let x_min_a = lonlat_to_tile_xy(lon_min, lat_min, z).0;
-        let x_max_a = ((1i32 << z) - 1) as i32;
-        let x_min_b = 0i32;
-        let x_max_b = lonlat_to_tile_xy(lon_max, lat_min, z).0;
-        vec![(x_min_a, x_max_a, y_min, y_max), (x_min_b, x_max_b, y_min, y_max)]
+    /// A successful response resets the exponential backoff.
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.paused_until.is_none_or(|until| until <= Instant::now()) {
+            state.consecutive_pauses = 0;
+        }
     }
 }
 
-fn iter_tiles_in_ranges(ranges: &[(i32, i32, i32, i32)]) -> Vec<(i32, i32)> {
-    let mut tiles = Vec::new();
-    for &(x_min, x_max, y_min, y_max) in ranges {
-        for y in y_min..=y_max {
-            for x in x_min..=x_max {
-                tiles.push((x, y));
-            }
+/// Adjusts how many tiles may be in flight to approach a tiles/second target.
+/// By Little's law the in-flight count needed is `rate × latency`, so the
+/// permit count follows a moving average of the per-tile latency.
+struct TpsController {
+    target_tps: f64,
+    max: usize,
+    sem: Semaphore,
+    state: Mutex<TpsState>,
+}
+
+struct TpsState {
+    avg_latency: Option<f64>,
+    permits: usize,
+}
+
+impl TpsController {
+    fn new(target_tps: f64, max: usize) -> Self {
+        // Assume one second per tile until the first measurements arrive.
+        let permits = (target_tps.ceil() as usize).clamp(1, max);
+        Self {
+            target_tps,
+            max,
+            sem: Semaphore::new(permits),
+            state: Mutex::new(TpsState {
+                avg_latency: None,
+                permits,
+            }),
         }
     }
-    tiles
-}
 
-async fn download_one(client: &reqwest::Client, url: &str, out_path: &Path) -> Result<bool> {
-    if let Some(parent) = out_path.parent() {
-        tokio_fs::create_dir_all(parent).await.ok();
+    async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.sem.acquire().await.expect("semaphore is never closed")
     }
 
-    let resp = client
-        .get(url)
-        .timeout(Duration::from_secs(30))
-        .send()
-        .await
-        .with_context(|| format!("GET {}", url))?;
+    fn record(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let secs = latency.as_secs_f64();
+        let avg = state.avg_latency.map_or(secs, |avg| avg * 0.8 + secs * 0.2);
+        state.avg_latency = Some(avg);
 
-    if !resp.status().is_success() {
-        eprintln!("HTTP {} for {}", resp.status(), url);
-        return Ok(false);
+        let desired = ((self.target_tps * avg).round() as usize).clamp(1, self.max);
+        if desired > state.permits {
+            self.sem.add_permits(desired - state.permits);
+            state.permits = desired;
+        } else if desired < state.permits {
+            // Only idle permits can be forgotten; the rest shrink on later calls.
+            state.permits -= self.sem.forget_permits(state.permits - desired);
+        }
     }
 
-    let bytes = resp.bytes().await?;
-    if bytes.is_empty() {
-        eprintln!("Empty response for {}", url);
-        return Ok(false);
+    fn summary(&self) -> (usize, Option<f64>) {
+        let state = self.state.lock().unwrap();
+        (state.permits, state.avg_latency)
     }
+}
 
-    let tmp_path = out_path.with_extension(format!(
-        "{}.part",
-        out_path
-            .extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or_default()
-    ));
+/// Token buckets for `--rate-limit`, keyed by host (a single `""` bucket for
+/// the global scope). Every host has its bucket from the start, so workers
+/// share the map without locking it.
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<String, Mutex<Bucket>>,
+}
 
-    tokio_fs::write(&tmp_path, &bytes).await?;
-    // atomic-ish move
-    fs::rename(&tmp_path, out_path).with_context(|| "rename .part → final")?;
-    Ok(true)
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
 }
 
-fn parse_coordinates(s: &str) -> Result<(f64, f64)> {
-    let parts: Vec<_> = s.split(',').map(|p| p.trim()).collect();
-    if parts.len() != 2 {
-        return Err(anyhow!("Coordinates must be in format 'latitude,longitude'"));
+impl RateLimiter {
+    fn new(rate: f64, scope: RateLimitScope, hosts: &HostPool) -> Self {
+        let burst = rate.max(1.0);
+        let keys = match scope {
+            RateLimitScope::Global => vec![String::new()],
+            RateLimitScope::PerHost => hosts.hosts.clone(),
+        };
+        let now = Instant::now();
+        let buckets = keys
+            .into_iter()
+            .map(|key| (key, Mutex::new(Bucket { tokens: burst, refilled: now })))
+            .collect();
+        Self { rate, burst, buckets }
+    }
+
+    /// Waits for a token from `host`'s bucket.
+    async fn acquire(&self, host: &str) {
+        let bucket = self.buckets.get(host).or_else(|| self.buckets.get("")).expect("bucket for every host");
+        loop {
+            let wait = {
+                let mut bucket = bucket.lock().unwrap();
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.rate;
+                bucket.tokens = (bucket.tokens + refill).min(self.burst);
+                bucket.refilled = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+            tokio::time::sleep(wait).await;
+        }
     }
-    let lat: f64 = parts[0].parse()?;
-    let lon: f64 = parts[1].parse()?;
-    Ok((lat, lon))
 }
 
-fn validate_and_get_grid_size(split: usize) -> Result<usize> {
-    if split == 0 {
-        return Err(anyhow!("Split parameter must be greater than 0"));
+/// Parses a `Retry-After` header given in seconds.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Tile hosts used round-robin, one starting host per tile.
+pub(crate) struct HostPool {
+    hosts: Vec<String>,
+    next: AtomicUsize,
+    /// Set by `--randomize-hosts`: starting hosts are drawn from it instead.
+    rng: Option<Mutex<StdRng>>,
+    /// Set by `--host-delay-ms`: the spacing, and the earliest time each
+    /// host may be sent its next request.
+    spacing: Option<(Duration, Vec<Mutex<Instant>>)>,
+}
+
+impl HostPool {
+    pub(crate) fn new(hosts: Vec<String>) -> Result<Self> {
+        let hosts: Vec<String> = hosts
+            .into_iter()
+            .map(|h| h.trim().trim_end_matches('/').to_string())
+            .filter(|h| !h.is_empty())
+            .collect();
+        if hosts.is_empty() {
+            return Err(anyhow!("At least one non-empty --hosts entry is required"));
+        }
+        if let Some(bad) = hosts
+            .iter()
+            .find(|h| !(h.starts_with("http://") || h.starts_with("https://")))
+        {
+            return Err(anyhow!("Host '{}' must start with http:// or https://", bad));
+        }
+        Ok(Self {
+            hosts,
+            next: AtomicUsize::new(0),
+            rng: None,
+            spacing: None,
+        })
     }
-    
-    let grid_size = (split as f64).sqrt() as usize;
-    if grid_size * grid_size != split {
-        return Err(anyhow!("Split parameter must be a perfect square (1, 4, 9, 16, 25, etc.), got {}", split));
+
+    /// Draws starting hosts uniformly at random from a generator seeded
+    /// with `seed`.
+    pub(crate) fn randomized(mut self, seed: u64) -> Self {
+        self.rng = Some(Mutex::new(StdRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// Keeps requests to each host at least `interval` apart.
+    pub(crate) fn spaced(mut self, interval: Duration) -> Self {
+        let now = Instant::now();
+        self.spacing = Some((interval, self.hosts.iter().map(|_| Mutex::new(now)).collect()));
+        self
+    }
+
+    /// Waits until host `idx` (wrapping, as in [`get`](Self::get)) may be
+    /// sent a request, booking that slot.
+    async fn wait_turn(&self, idx: usize) {
+        let Some((interval, slots)) = &self.spacing else {
+            return;
+        };
+        let at = {
+            let mut next = slots[idx % slots.len()].lock().unwrap();
+            let at = (*next).max(Instant::now());
+            *next = at + *interval;
+            at
+        };
+        tokio::time::sleep_until(at.into()).await;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    fn pick(&self) -> usize {
+        match &self.rng {
+            Some(rng) => rng.lock().unwrap().random_range(0..self.hosts.len()),
+            None => self.next.fetch_add(1, Ordering::Relaxed) % self.hosts.len(),
+        }
+    }
+
+    /// Host at `idx`, wrapping so callers can walk forward for failover.
+    pub(crate) fn get(&self, idx: usize) -> &str {
+        &self.hosts[idx % self.hosts.len()]
     }
-    
-    Ok(grid_size)
 }
 
-fn get_tile_subfolder(x: i32, y: i32, grid_size: usize) -> String {
-    if grid_size == 1 {
-        return String::new();
+/// Result of a tile request that completed without a transport error.
+enum TileOutcome {
+    /// Tile written to disk; carries the byte count.
+    Saved(u64),
+    /// 304 for a conditional request: the file on disk is current.
+    NotModified,
+    Empty,
+    /// Body over `--max-tile-bytes`; nothing was kept.
+    TooLarge,
+    /// `--overwrite-policy` kept the file already on disk.
+    KeptExisting,
+    /// A success status with an error-page `Content-Type`; carries the type.
+    WrongContentType(String),
+    /// Writing the tile failed because the disk or quota is full; carries
+    /// the error. Ends the whole run.
+    DiskFull(String),
+    Http {
+        status: reqwest::StatusCode,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl TileOutcome {
+    /// Value of the `outcome` field of a request's tracing span.
+    fn label(&self) -> &'static str {
+        match self {
+            TileOutcome::Saved(_) => "saved",
+            TileOutcome::NotModified => "not_modified",
+            TileOutcome::Empty => "empty",
+            TileOutcome::TooLarge => "too_large",
+            TileOutcome::KeptExisting => "kept",
+            TileOutcome::WrongContentType(_) => "wrong_content_type",
+            TileOutcome::DiskFull(_) => "disk_full",
+            TileOutcome::Http { .. } => "http_error",
+        }
     }
-    
-    let grid_x = (x.abs() as usize) % grid_size;
-    let grid_y = (y.abs() as usize) % grid_size;
-    
-    format!("{:02}_{:02}", grid_x, grid_y)
 }
 
-pub async fn run_download(args: Args) -> Result<()> {
+/// Per-request settings for [`download_one`]. The request timeout is not
+/// among them: it is set once on the client (`--request-timeout`).
+#[derive(Clone, Copy)]
+struct FetchOptions {
+    conditional: bool,
+    max_bytes: Option<u64>,
+    policy: OverwritePolicy,
+    keep_failed: bool,
+    check_content_type: bool,
+    /// Gzip the body before writing it (`--gzip`)
+    gzip: bool,
+    rename_retries: u32,
+}
 
-    // Validate split parameter
-    let grid_size = validate_and_get_grid_size(args.split)?;
+/// Removes `.part` files left in `out_dir` by an interrupted run. With
+/// `resume`, a leftover GLB whose header and length check out is renamed to
+/// its final name instead, unless that file already exists. Returns
+/// `(removed, finalized)`.
+fn clean_part_files(out_dir: &Path, resume: bool) -> Result<(usize, usize)> {
+    let (mut removed, mut finalized) = (0, 0);
+    if !out_dir.is_dir() {
+        return Ok((removed, finalized));
+    }
+    for entry in WalkDir::new(out_dir) {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || path.extension().is_none_or(|e| e != "part") {
+            continue;
+        }
+        let final_path = path.with_extension("");
+        if resume
+            && !final_path.exists()
+            && has_glb_ext(&final_path)
+            && fs::read(path).is_ok_and(|bytes| check_header(&bytes).is_ok())
+        {
+            fs::rename(path, &final_path)
+                .with_context(|| format!("Finalizing {:?}", path))?;
+            finalized += 1;
+        } else {
+            fs::remove_file(path).with_context(|| format!("Removing {:?}", path))?;
+            removed += 1;
+        }
+    }
+    Ok((removed, finalized))
+}
 
-    // Determine bbox
-    let (lat1, lon1, lat2, lon2) = if let (Some(center), Some(size)) = (&args.center_coord, args.size)
-    {
-        let (clat, clon) = parse_coordinates(center)?;
-        let (a, b, c, d) = create_square_bbox(clat, clon, size);
-        println!(
-            "Calculated bbox: ({:.6}, {:.6}) to ({:.6}, {:.6})",
-            a, b, c, d
-        );
-        (a, b, c, d)
-    } else if let (Some(sw), Some(ne)) = (&args.sw_coord, &args.ne_coord) {
-        let (lat_sw, lon_sw) = parse_coordinates(sw)?;
-        let (lat_ne, lon_ne) = parse_coordinates(ne)?;
-        println!(
-            "Using specified bbox: ({:.6}, {:.6}) to ({:.6}, {:.6})",
-            lat_sw, lon_sw, lat_ne, lon_ne
-        );
-        (lat_sw, lon_sw, lat_ne, lon_ne)
-    } else {
-        eprintln!("ERROR: Must specify either (--sw-coord, --ne-coord) OR (--center-coord, --size)");
-        return Ok(());
+/// Undoes compression the HTTP layer didn't strip. reqwest decodes bodies that
+/// declare `Content-Encoding`, but some endpoints gzip the payload without the
+/// header, so a body that starts with a gzip or zlib signature instead of
+/// `glTF` is inflated here.
+pub(crate) fn decode_body(bytes: &[u8]) -> Result<Cow<'_, [u8]>> {
+    let decoded = match bytes {
+        [0x1f, 0x8b, ..] => {
+            let mut out = Vec::new();
+            MultiGzDecoder::new(bytes).read_to_end(&mut out)?;
+            out
+        }
+        _ if is_compressed(bytes) => {
+            let mut out = Vec::new();
+            ZlibDecoder::new(bytes).read_to_end(&mut out)?;
+            out
+        }
+        _ => return Ok(Cow::Borrowed(bytes)),
     };
+    Ok(Cow::Owned(decoded))
+}
 
-    let client = reqwest::Client::builder()
-        .user_agent(USER_AGENT)
-        .connect_timeout(Duration::from_secs(10))
-        .pool_idle_timeout(Duration::from_secs(30))
-        .pool_max_idle_per_host(32)
-        .build()?;
+/// Whether `bytes` starts with a gzip or zlib header.
+fn is_compressed(bytes: &[u8]) -> bool {
+    match bytes {
+        [0x1f, 0x8b, ..] => true,
+        [0x78, second, ..] => (0x7800u16 | *second as u16).is_multiple_of(31),
+        _ => false,
+    }
+}
+
+/// Temp-file path for `out_path`: `.part` is appended to the full file name
+/// (`foo.glb` → `foo.glb.part`, `foo` → `foo.part`) so it never collides with
+/// another output regardless of extension.
+fn part_path(out_path: &Path) -> PathBuf {
+    let mut name = out_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    out_path.with_file_name(name)
+}
 
-    let z = args.zoom;
+async fn download_one(
+    client: &reqwest::Client,
+    url: &str,
+    log_url: &str,
+    out_path: &Path,
+    fetch: FetchOptions,
+    io: &Semaphore,
+    on_redirect: &(dyn Fn(&str, bool) + Sync),
+) -> Result<TileOutcome> {
+    let FetchOptions { conditional, max_bytes, policy, keep_failed, check_content_type, gzip, rename_retries } =
+        fetch;
 
-    let ranges = bbox_tile_ranges(lat1, lon1, lat2, lon2, z);
-    let tiles = iter_tiles_in_ranges(&ranges);
-    if tiles.is_empty() {
-        println!("No tiles in the specified range.");
-        return Ok(());
+    let mut request = client.get(url);
+    if conditional && out_path.exists() {
+        for (name, value) in read_validators(out_path).await {
+            request = request.header(name, value);
+        }
     }
-    
-    println!("Zoom level: {}", args.zoom);
-    println!("Tile range: {:?}", ranges);
-    println!("Tile total: {} ", tiles.len());
-    println!("Concurrency: {}", args.concurrency);
-    if args.split > 1 {
-        println!("Split: {} ({}x{} grid)", args.split, grid_size, grid_size);
+    let mut resp = request
+        .send()
+        .await
+        .with_context(|| format!("GET {}", log_url))?;
+
+    if resp.url().as_str() != url {
+        on_redirect(resp.url().as_str(), true);
+    } else if resp.status().is_redirection()
+        && let Some(location) = resp.headers().get(reqwest::header::LOCATION)
+    {
+        on_redirect(&String::from_utf8_lossy(location.as_bytes()), false);
     }
-    println!("Directory: {}", args.out.display());
 
-    let pb = ProgressBar::new(tiles.len() as u64);
-    pb.set_style(
-        ProgressStyle::with_template(
-            "[{elapsed_precise}] [{bar:30.cyan/blue}] {pos}/{len} - ETA {eta}",
+    tracing::Span::current().record("status", resp.status().as_u16());
+
+    if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+        // Bump the mtime so --refresh-older-than treats the tile as fresh.
+        if let Ok(file) = File::options().write(true).open(out_path) {
+            file.set_modified(SystemTime::now()).ok();
+        }
+        return Ok(TileOutcome::NotModified);
+    }
+    let status = resp.status();
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+    let note = |reason: &str| {
+        format!(
+            "url: {}\nstatus: {}\ncontent-type: {}\nreason: {}\n",
+            log_url, status, content_type, reason
         )
-        .unwrap(),
-    );
+    };
+    let limit = max_bytes.unwrap_or(u64::MAX);
 
-    let ok_count = Arc::new(AtomicUsize::new(0));
-    let ok_count_clone = ok_count.clone();
-    let out_dir = Arc::new(args.out);
-    let api_key = Arc::new(args.api_key);
-    let client = Arc::new(client);
-    let host = Arc::new(DEFAULT_HOST.to_string());
-    let grid_size = Arc::new(grid_size);
-
-    // Work stream with bounded concurrency, progress updates as each completes.
-    stream::iter(tiles.into_iter())
-        .for_each_concurrent(args.concurrency, {
-            let pb = pb.clone();
-            move |(x, y)| {
-                let pb = pb.clone();
-                let ok_count = ok_count_clone.clone();
-                let out_dir = out_dir.clone();
-                let api_key = api_key.clone();
-                let client = client.clone();
-                let host = host.clone();
-                let grid_size = grid_size.clone();
-
-                async move {
-                    let qk = tile_xy_to_quadkey(x, y, z);
-                    let url = format!(
-                        "{}/tiles/mtx{}?g={}&tf={}&n=z&key={}&form=web3d",
-                        host, qk, DEFAULT_G, DEFAULT_TF, api_key
-                    );
-                    
-                    // Determine subfolder based on tile coordinates
-                    let subfolder = get_tile_subfolder(x, y, *grid_size);
-                    let final_dir = if subfolder.is_empty() {
-                        out_dir.as_ref().clone()
-                    } else {
-                        out_dir.join(&subfolder)
-                    };
-                    
-                    let out_path = final_dir.join(format!("{}_{}_{}.glb", z, x, y));
-                    let res = download_one(&client, &url, &out_path).await.unwrap_or_else(|e| {
-                        eprintln!("Exception downloading {}: {}", url, e);
-                        false
-                    });
-                    if res {
-                        ok_count.fetch_add(1, Ordering::Relaxed);
-                    }
-                    pb.inc(1);
+    let error_page = check_content_type && is_error_page_type(&content_type);
+    if !status.is_success() || error_page {
+        let retry_after = retry_after(resp.headers());
+        let reason = if error_page {
+            format!("Content-Type {}", content_type)
+        } else {
+            format!("HTTP {}", status)
+        };
+        if keep_failed {
+            // Error pages are small; read at most --max-tile-bytes of it.
+            let mut body = Vec::new();
+            while let Ok(Some(chunk)) = resp.chunk().await {
+                body.extend_from_slice(&chunk);
+                if body.len() as u64 > limit {
+                    break;
                 }
             }
-        })
-        .await;
+            let _io = io.acquire().await;
+            keep_failed_body(&body, out_path, &note(&reason)).await;
+        }
+        if !status.is_success() {
+            return Ok(TileOutcome::Http { status, retry_after });
+        }
+        return Ok(TileOutcome::WrongContentType(content_type));
+    }
 
-    pb.finish_and_clear();
-    let ok = ok_count.load(Ordering::Relaxed);
-    println!("Done: Saved {}/{} tiles", ok, ok);
+    let validators = if conditional {
+        validator_lines(resp.headers())
+    } else {
+        String::new()
+    };
+    let existing = fs::metadata(out_path).ok().filter(|m| m.is_file());
+    if policy == OverwritePolicy::IfNewer
+        && let Some(meta) = &existing
+    {
+        let server = resp
+            .headers()
+            .get(reqwest::header::LAST_MODIFIED)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_http_date);
+        // Without a Last-Modified there is nothing to prove the tile newer.
+        if !server.is_some_and(|server| meta.modified().is_ok_and(|local| server > local)) {
+            return Ok(TileOutcome::KeptExisting);
+        }
+    }
 
+    if resp.content_length().is_some_and(|len| len > limit) {
+        return Ok(TileOutcome::TooLarge);
+    }
+
+    // Stream the body into the .part file so an oversized response is cut
+    // off as soon as it passes the limit. Only the writes hold an
+    // --io-concurrency permit, not the waits on the network between them.
+    let tmp_path = part_path(out_path);
+    let mut part = match PartFile::create(&tmp_path, out_path, gzip, io).await {
+        Ok(part) => part,
+        Err(e) => return store_failed(&tmp_path, out_path, e).await,
+    };
+    let mut received = 0u64;
+    loop {
+        let chunk = match resp.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                let e = anyhow::Error::from(e).context(format!("Reading body of {}", log_url));
+                part.discard(keep_failed.then(|| note(&format!("{:#}", e)))).await;
+                return Err(e);
+            }
+        };
+        received += chunk.len() as u64;
+        if let Err(e) = part.write(&chunk).await {
+            return store_failed(&tmp_path, out_path, e).await;
+        }
+        if received > limit {
+            part.discard(keep_failed.then(|| note("body exceeds --max-tile-bytes"))).await;
+            return Ok(TileOutcome::TooLarge);
+        }
+    }
+    if received == 0 {
+        part.discard(None).await;
+        return Ok(TileOutcome::Empty);
+    }
+    let compressed = match part.finish().await {
+        Ok(compressed) => compressed,
+        Err(e) => return store_failed(&tmp_path, out_path, e).await,
+    };
+
+    let mut written = received;
+    // Rare: a gzip/zlib-wrapped body that reqwest didn't decode.
+    if compressed {
+        let raw = {
+            let _io = io.acquire().await;
+            tokio_fs::read(&tmp_path).await.with_context(|| format!("Reading {:?}", tmp_path))?
+        };
+        let decoded = decode_body(&raw).with_context(|| format!("Decoding body of {}", log_url));
+        let decoded = match decoded {
+            Ok(decoded) if decoded.len() as u64 <= limit => decoded,
+            result => {
+                let _io = io.acquire().await;
+                if keep_failed {
+                    let reason = match &result {
+                        Err(e) => format!("{:#}", e),
+                        Ok(_) => "decoded body exceeds --max-tile-bytes".to_string(),
+                    };
+                    keep_failed_body(&raw, out_path, &note(&reason)).await;
+                }
+                tokio_fs::remove_file(&tmp_path).await.ok();
+                result?;
+                return Ok(TileOutcome::TooLarge);
+            }
+        };
+        written = decoded.len() as u64;
+        let stored = if gzip {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&decoded)?;
+            Cow::Owned(encoder.finish()?)
+        } else {
+            decoded
+        };
+        let rewritten = {
+            let _io = io.acquire().await;
+            tokio_fs::write(&tmp_path, &stored).await
+        };
+        if let Err(e) = rewritten {
+            return store_failed(&tmp_path, out_path, e).await;
+        }
+    }
+
+    // With --gzip both sides of the comparison are compressed sizes.
+    if policy == OverwritePolicy::IfLarger
+        && let Some(meta) = &existing
+        && tokio_fs::metadata(&tmp_path).await.is_ok_and(|part| part.len() <= meta.len())
+    {
+        tokio_fs::remove_file(&tmp_path).await.ok();
+        return Ok(TileOutcome::KeptExisting);
+    }
+
+    let moved = {
+        let _io = io.acquire().await;
+        move_into_place(&tmp_path, out_path, rename_retries).await
+    };
+    if let Err(e) = moved {
+        return store_failed(&tmp_path, out_path, e).await;
+    }
+    if keep_failed {
+        // A retry succeeded: drop the evidence of the earlier failure.
+        let failed = failed_path(out_path);
+        if tokio_fs::remove_file(&failed).await.is_ok() {
+            let mut sidecar = failed.into_os_string();
+            sidecar.push(".txt");
+            tokio_fs::remove_file(PathBuf::from(sidecar)).await.ok();
+        }
+    }
+    if conditional {
+        let sidecar = validator_path(out_path);
+        if validators.is_empty() {
+            tokio_fs::remove_file(&sidecar).await.ok();
+        } else {
+            tokio_fs::write(&sidecar, validators).await.ok();
+        }
+    }
+    Ok(TileOutcome::Saved(written))
+}
+
+/// The `.part` file a tile body streams into, gzip-encoded on the way for
+/// `--gzip` unless the body turns out to be compressed already. Every write
+/// holds an `--io-concurrency` permit.
+struct PartFile<'a> {
+    path: &'a Path,
+    out_path: &'a Path,
+    file: tokio_fs::File,
+    io: &'a Semaphore,
+    gzip: bool,
+    /// First bytes of the body, held back until there are enough to tell
+    /// whether it is compressed; `None` once written.
+    head: Option<Vec<u8>>,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+    /// Whether the body started with a gzip or zlib header
+    compressed: bool,
+    /// Whether the file holds the body gzip-encoded
+    encoded: bool,
+}
+
+impl<'a> PartFile<'a> {
+    async fn create(path: &'a Path, out_path: &'a Path, gzip: bool, io: &'a Semaphore) -> std::io::Result<Self> {
+        let _io = io.acquire().await;
+        if let Some(parent) = out_path.parent() {
+            tokio_fs::create_dir_all(parent).await.ok();
+        }
+        let file = tokio_fs::File::create(path).await?;
+        Ok(Self {
+            path,
+            out_path,
+            file,
+            io,
+            gzip,
+            head: Some(Vec::new()),
+            encoder: None,
+            compressed: false,
+            encoded: false,
+        })
+    }
+
+    async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        match &mut self.head {
+            Some(head) => {
+                head.extend_from_slice(chunk);
+                if head.len() < 2 {
+                    return Ok(());
+                }
+                self.start().await
+            }
+            None => self.put(chunk).await,
+        }
+    }
+
+    /// Decides from the held-back head whether to gzip, then writes it.
+    async fn start(&mut self) -> std::io::Result<()> {
+        let head = self.head.take().unwrap_or_default();
+        self.compressed = is_compressed(&head);
+        if self.gzip && !self.compressed {
+            self.encoder = Some(GzEncoder::new(Vec::new(), Compression::default()));
+            self.encoded = true;
+        }
+        self.put(&head).await
+    }
+
+    async fn put(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        let encoded;
+        let bytes = match &mut self.encoder {
+            Some(encoder) => {
+                encoder.write_all(bytes)?;
+                encoded = std::mem::take(encoder.get_mut());
+                &encoded[..]
+            }
+            None => bytes,
+        };
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let _io = self.io.acquire().await;
+        self.file.write_all(bytes).await
+    }
+
+    /// Writes whatever is still held back or buffered and flushes the file.
+    async fn close(&mut self) -> std::io::Result<()> {
+        if self.head.is_some() {
+            self.start().await?;
+        }
+        if let Some(encoder) = self.encoder.take() {
+            let tail = encoder.finish()?;
+            self.put(&tail).await?;
+        }
+        let _io = self.io.acquire().await;
+        self.file.flush().await
+    }
+
+    /// Closes the file once the whole body is in. Returns whether the body
+    /// was gzip or zlib data, which is then stored as received.
+    async fn finish(mut self) -> std::io::Result<bool> {
+        self.close().await?;
+        Ok(self.compressed)
+    }
+
+    /// Removes the file. With `note` (`--keep-failed-partials`) the body as
+    /// received so far is kept as `<tile>.failed` first.
+    async fn discard(mut self, note: Option<String>) {
+        let closed = self.close().await.is_ok();
+        let Self { path, out_path, file, io, encoded, .. } = self;
+        drop(file);
+        let _io = io.acquire().await;
+        if let Some(note) = note
+            && closed
+            && let Ok(mut body) = tokio_fs::read(path).await
+        {
+            let mut raw = Vec::new();
+            if encoded && MultiGzDecoder::new(body.as_slice()).read_to_end(&mut raw).is_ok() {
+                body = raw;
+            }
+            keep_failed_body(&body, out_path, &note).await;
+        }
+        tokio_fs::remove_file(path).await.ok();
+    }
+}
+
+/// Ends a tile whose `.part` file could not be written or moved into place:
+/// the file is removed, and running out of space gives `DiskFull` rather
+/// than an error.
+async fn store_failed(tmp_path: &Path, out_path: &Path, e: std::io::Error) -> Result<TileOutcome> {
+    tokio_fs::remove_file(tmp_path).await.ok();
+    if is_disk_full(&e) {
+        return Ok(TileOutcome::DiskFull(format!("writing {:?}: {}", out_path, e)));
+    }
+    Err(anyhow::Error::from(e).context(format!("Writing {:?}", out_path)))
+}
+
+/// Renames the finished `tmp` over `out`, retrying with a growing pause
+/// while something such as a virus scanner or indexer holds either file
+/// open, then falls back to copying and deleting `tmp`.
+async fn move_into_place(tmp: &Path, out: &Path, retries: u32) -> std::io::Result<()> {
+    let mut delay = RENAME_RETRY_DELAY;
+    let mut attempt = 0;
+    let error = loop {
+        match tokio_fs::rename(tmp, out).await {
+            Ok(()) => return Ok(()),
+            Err(e) if is_disk_full(&e) || attempt >= retries => break e,
+            Err(_) => {}
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    };
+    if is_disk_full(&error) {
+        return Err(error);
+    }
+    match tokio_fs::copy(tmp, out).await {
+        Ok(_) => {
+            tokio_fs::remove_file(tmp).await.ok();
+            Ok(())
+        }
+        Err(copy_error) => Err(std::io::Error::new(
+            copy_error.kind(),
+            format!(
+                "moving {:?} into place failed after {} retries ({}), and copying it failed too ({})",
+                tmp, retries, error, copy_error
+            ),
+        )),
+    }
+}
+
+/// Whether a write failed for lack of space (ENOSPC) or quota (EDQUOT),
+/// after which every later write would fail the same way.
+fn is_disk_full(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded
+    )
+}
+
+/// Whether a `Content-Type` names a text or JSON/XML document, as served by
+/// error pages, rather than a model or image. Parameters such as `charset`
+/// are ignored.
+fn is_error_page_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime == "application/json"
+        || mime == "application/xml"
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+}
+
+/// `foo.glb` → `foo.glb.failed`, kept by `--keep-failed-partials`.
+fn failed_path(out_path: &Path) -> PathBuf {
+    let mut name = out_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".failed");
+    out_path.with_file_name(name)
+}
+
+/// `--keep-failed-partials`: writes `body` to `<tile>.failed` and
+/// `<tile>.failed.txt` with `note` plus a hex/ASCII dump of the first bytes.
+/// Best effort; errors only mean the evidence is lost.
+async fn keep_failed_body(body: &[u8], out_path: &Path, note: &str) {
+    const PREVIEW_BYTES: usize = 64;
+
+    if let Some(parent) = out_path.parent() {
+        tokio_fs::create_dir_all(parent).await.ok();
+    }
+    let failed = failed_path(out_path);
+    if tokio_fs::write(&failed, body).await.is_err() {
+        return;
+    }
+    let head = &body[..body.len().min(PREVIEW_BYTES)];
+    let hex: Vec<String> = head.iter().map(|b| format!("{:02x}", b)).collect();
+    let ascii: String = head
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect();
+    let text = format!(
+        "{}bytes: {}\nfirst bytes (hex): {}\nfirst bytes (ascii): {}\n",
+        note,
+        body.len(),
+        hex.join(" "),
+        ascii
+    );
+    let mut sidecar = failed.into_os_string();
+    sidecar.push(".txt");
+    tokio_fs::write(PathBuf::from(sidecar), text).await.ok();
+}
+
+/// Sidecar holding a tile's HTTP cache validators: `foo.glb` → `foo.glb.etag`.
+fn validator_path(out_path: &Path) -> PathBuf {
+    let mut name = out_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".etag");
+    out_path.with_file_name(name)
+}
+
+/// `ETag` and `Last-Modified` of a response as `Name: value` lines.
+fn validator_lines(headers: &HeaderMap) -> String {
+    let mut out = String::new();
+    for name in [reqwest::header::ETAG, reqwest::header::LAST_MODIFIED] {
+        if let Some(value) = headers.get(&name).and_then(|v| v.to_str().ok()) {
+            out.push_str(&format!("{}: {}\n", name.as_str(), value));
+        }
+    }
+    out
+}
+
+/// Conditional request headers built from a tile's sidecar, if it has one.
+async fn read_validators(out_path: &Path) -> Vec<(HeaderName, String)> {
+    let Ok(text) = tokio_fs::read_to_string(validator_path(out_path)).await else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|line| line.split_once(": "))
+        .filter_map(|(name, value)| match name {
+            "etag" => Some((reqwest::header::IF_NONE_MATCH, value.to_string())),
+            "last-modified" => Some((reqwest::header::IF_MODIFIED_SINCE, value.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Places each saved tile relative to the centre of the downloaded area, in
+/// metres, using glTF's Y-up convention (+X east, +Z south). Positions are
+/// taken in Web Mercator and scaled at the centre latitude, which is accurate
+/// for city-sized areas.
+fn merge_inputs(saved: &[SavedTile]) -> Vec<MergeInput> {
+    // Tile centres as fractions of the world, so mixed zooms share one frame.
+    let centre = |t: &SavedTile| {
+        let n = (1u64 << t.z) as f64;
+        ((t.x as f64 + 0.5) / n, (t.y as f64 + 0.5) / n)
+    };
+    let (mut min_fx, mut max_fx, mut min_fy, mut max_fy) = (1.0f64, 0.0f64, 1.0f64, 0.0f64);
+    for t in saved {
+        let (fx, fy) = centre(t);
+        min_fx = min_fx.min(fx);
+        max_fx = max_fx.max(fx);
+        min_fy = min_fy.min(fy);
+        max_fy = max_fy.max(fy);
+    }
+    let ref_fx = (min_fx + max_fx) / 2.0;
+    let ref_fy = (min_fy + max_fy) / 2.0;
+    let (_, ref_lat) = fractional_tile_to_lonlat(ref_fx, ref_fy, 0);
+    let world_m = EARTH_CIRCUMFERENCE_M * ref_lat.to_radians().cos();
+
+    saved
+        .iter()
+        .map(|t| {
+            let (fx, fy) = centre(t);
+            MergeInput {
+                path: t.path.clone(),
+                name: format!("{}_{}_{}", t.z, t.x, t.y),
+                translation: [(fx - ref_fx) * world_m, 0.0, (fy - ref_fy) * world_m],
+            }
+        })
+        .collect()
+}
+
+/// Path of `path` relative to `base` as a forward-slash URI, walking up with
+/// `..` when `path` is not inside `base`.
+fn relative_uri(path: &Path, base: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let base = std::path::absolute(base).unwrap_or_else(|_| base.to_path_buf());
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let ups = base.components().count() - common;
+    let parts: Vec<String> = std::iter::repeat_n("..".to_string(), ups)
+        .chain(
+            path.components()
+                .skip(common)
+                .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect();
+    parts.join("/")
+}
+
+fn parse_coordinates(s: &str) -> Result<(f64, f64)> {
+    let parts: Vec<_> = s.split(',').map(|p| p.trim()).collect();
+    if parts.len() != 2 {
+        return Err(anyhow!("Coordinates must be in format 'latitude,longitude'"));
+    }
+    let lat: f64 = parts[0].parse()?;
+    let lon: f64 = parts[1].parse()?;
+    Ok((lat, lon))
+}
+
+/// Parses an `--exclude-bbox` as `(sw_lat, sw_lon, ne_lat, ne_lon)`. As for
+/// `--sw-coord`/`--ne-coord`, a west edge east of the east edge crosses the
+/// antimeridian.
+fn parse_exclude_bbox(s: &str) -> Result<(f64, f64, f64, f64)> {
+    let parts: Vec<f64> = s
+        .split(',')
+        .map(|p| p.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("--exclude-bbox {:?}: expected four numbers", s))?;
+    let [lat1, lon1, lat2, lon2] = parts[..] else {
+        return Err(anyhow!("--exclude-bbox {:?}: expected 'sw_lat,sw_lon,ne_lat,ne_lon'", s));
+    };
+    if [lat1, lat2].iter().any(|lat| lat.abs() > 90.0) || [lon1, lon2].iter().any(|lon| lon.abs() > 180.0) {
+        return Err(anyhow!("--exclude-bbox {:?}: coordinates out of range", s));
+    }
+    Ok((lat1, lon1, lat2, lon2))
+}
+
+/// Describes how the given corners look swapped, if they do. A SW corner north
+/// of NE is always a mistake; a SW corner east of NE is read as crossing the
+/// antimeridian, which is only suspicious when that makes the box wider than
+/// half the globe.
+fn swapped_corners(lat_sw: f64, lon_sw: f64, lat_ne: f64, lon_ne: f64) -> Vec<String> {
+    let mut problems = Vec::new();
+    if lat_sw > lat_ne {
+        problems.push(format!(
+            "--sw-coord latitude {:.6} is north of --ne-coord latitude {:.6}",
+            lat_sw, lat_ne
+        ));
+    }
+    let (a_lon, b_lon) = (wrap_lon(lon_sw), wrap_lon(lon_ne));
+    if a_lon > b_lon && (b_lon + 360.0 - a_lon) > 180.0 {
+        problems.push(format!(
+            "--sw-coord longitude {:.6} is east of --ne-coord longitude {:.6} (treating as an antimeridian crossing {:.1}° wide)",
+            lon_sw,
+            lon_ne,
+            b_lon + 360.0 - a_lon
+        ));
+    }
+    problems
+}
+
+/// Reads one quadkey per line (blank lines and `#` comments ignored) and
+/// returns the distinct tiles in input order. Invalid lines are reported with
+/// their line number and skipped, or abort the run when `strict`.
+fn read_quadkeys(reader: impl BufRead, strict: bool) -> Result<Vec<(i32, i32, u32)>> {
+    let mut tiles = Vec::new();
+    let mut seen = HashSet::new();
+    let (mut invalid, mut duplicates) = (0, 0);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.context("Reading quadkeys from stdin")?;
+        let qk = line.trim();
+        if qk.is_empty() || qk.starts_with('#') {
+            continue;
+        }
+        let tile = quadkey_to_tile_xy(qk).filter(|&(_, _, z)| (1..=MAX_ZOOM).contains(&z));
+        let Some(tile) = tile else {
+            let msg = format!(
+                "line {}: invalid quadkey '{}' (expected 1-{} digits 0-3)",
+                i + 1,
+                qk,
+                MAX_ZOOM
+            );
+            if strict {
+                return Err(anyhow!(msg));
+            }
+            eprintln!("WARNING: {}; skipping", msg);
+            invalid += 1;
+            continue;
+        };
+        if seen.insert(tile) {
+            tiles.push(tile);
+        } else {
+            duplicates += 1;
+        }
+    }
+    println!(
+        "Quadkeys from stdin: {} tiles ({} invalid, {} duplicates skipped)",
+        tiles.len(),
+        invalid,
+        duplicates
+    );
+    Ok(tiles)
+}
+
+/// True if tile `(x, y, z)` lies entirely inside the bbox spanned by the two
+/// corners, which may cross the antimeridian when `lon1 > lon2`.
+fn tile_inside_bbox(x: i32, y: i32, z: u32, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> bool {
+    let (west, south, east, north) = tile_bounds(x, y, z);
+    let (lat_a, lat_b) = (clamp_lat(lat1), clamp_lat(lat2));
+    if south < lat_a.min(lat_b) || north > lat_a.max(lat_b) {
+        return false;
+    }
+    let (lon_a, lon_b) = (wrap_lon(lon1), wrap_lon(lon2));
+    if (lon2 - lon1).abs() >= 360.0 {
+        true
+    } else if lon_a <= lon_b {
+        west >= lon_a && east <= lon_b
+    } else {
+        (west >= lon_a && east <= 180.0) || (west >= -180.0 && east <= lon_b)
+    }
+}
+
+/// Which region tiles are kept: `--radius` or `--clip-mode contain`
+/// clipping, then `--exclude-bbox` holes.
+struct RegionFilter {
+    /// `(lat, lon, radius_m)` of a `--radius` circle
+    circle: Option<(f64, f64, f64)>,
+    clip: ClipMode,
+    bbox: (f64, f64, f64, f64),
+    exclusions: Vec<(f64, f64, f64, f64)>,
+}
+
+impl RegionFilter {
+    /// Whether the tile survives `--radius` / `--clip-mode` clipping.
+    fn clips(&self, x: i32, y: i32, z: u32) -> bool {
+        let (lat1, lon1, lat2, lon2) = self.bbox;
+        match self.circle {
+            Some((lat, lon, radius)) => tile_in_circle(x, y, z, lat, lon, radius, self.clip),
+            None if self.clip == ClipMode::Contain => tile_inside_bbox(x, y, z, lat1, lon1, lat2, lon2),
+            None => true,
+        }
+    }
+
+    /// Whether the tile lies entirely inside an `--exclude-bbox`.
+    fn excludes(&self, x: i32, y: i32, z: u32) -> bool {
+        self.exclusions
+            .iter()
+            .any(|&(a, b, c, d)| tile_inside_bbox(x, y, z, a, b, c, d))
+    }
+}
+
+/// Inclusive `(min_x, max_x, min_y, max_y)` ranges of one zoom level.
+type TileRanges = Vec<(i32, i32, i32, i32)>;
+
+/// Region tiles generated on demand, level by level, so a `--batch-size` run
+/// never holds the whole tile list.
+struct RegionTiles {
+    /// `(zoom, tile ranges)` per level
+    levels: Vec<(u32, TileRanges)>,
+    filter: RegionFilter,
+}
+
+impl RegionTiles {
+    fn iter(&self) -> impl Iterator<Item = (i32, i32, u32)> + Send + '_ {
+        self.levels
+            .iter()
+            .flat_map(|(z, ranges)| tiles_in_ranges(ranges).map(move |(x, y)| (x, y, *z)))
+            .filter(|&(x, y, z)| self.filter.clips(x, y, z) && !self.filter.excludes(x, y, z))
+    }
+}
+
+/// Inclusive `(min_x, max_x, min_y, max_y)` ranges of a set of tiles at zoom
+/// `z`, two when they lie either side of the antimeridian, as from
+/// [`bbox_tile_ranges`]. Empty without tiles.
+fn tile_extent(tiles: &[(i32, i32)], z: u32) -> Vec<(i32, i32, i32, i32)> {
+    let Some(span) = tiles_lon_span(tiles.iter().map(|&(x, _)| (x, z))) else {
+        return Vec::new();
+    };
+    let min_y = tiles.iter().map(|t| t.1).min().unwrap_or(0);
+    let max_y = tiles.iter().map(|t| t.1).max().unwrap_or(0);
+    span_columns(span, z).into_iter().map(|(min_x, max_x)| (min_x, max_x, min_y, max_y)).collect()
+}
+
+/// Ancestors of `children` (all at one zoom) at every zoom from `min_zoom` up
+/// to the level below theirs, found by truncating their quadkeys, so each
+/// level holds exactly the parents of the one above. Returned lowest zoom
+/// first, with the per-zoom extents in `per_zoom` form.
+#[allow(clippy::type_complexity)]
+fn pyramid_parents(
+    children: &[(i32, i32, u32)],
+    min_zoom: u32,
+) -> (Vec<(i32, i32, u32)>, Vec<(u32, Vec<(i32, i32, i32, i32)>, usize)>) {
+    let Some(&(_, _, max_zoom)) = children.first() else {
+        return (Vec::new(), Vec::new());
+    };
+    let quadkeys: Vec<String> = children.iter().map(|&(x, y, z)| tile_xy_to_quadkey(x, y, z)).collect();
+    let mut tiles = Vec::new();
+    let mut per_zoom = Vec::new();
+    for z in min_zoom..max_zoom {
+        let mut seen = HashSet::new();
+        let level: Vec<(i32, i32)> = quadkeys
+            .iter()
+            .map(|qk| &qk[..z as usize])
+            .filter(|prefix| seen.insert(*prefix))
+            .filter_map(quadkey_to_tile_xy)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        per_zoom.push((z, tile_extent(&level, z), level.len()));
+        tiles.extend(level.into_iter().map(|(x, y)| (x, y, z)));
+    }
+    (tiles, per_zoom)
+}
+
+/// Whether tile `(x, y, z)` lies within `radius_m` of `(lat, lon)`: any part
+/// of it for [`ClipMode::Intersect`], all four corners for [`ClipMode::Contain`].
+fn tile_in_circle(x: i32, y: i32, z: u32, lat: f64, lon: f64, radius_m: f64, clip: ClipMode) -> bool {
+    let (west, south, east, north) = tile_bounds(x, y, z);
+    match clip {
+        ClipMode::Intersect => {
+            let (near_lon, near_lat) = (lon.clamp(west, east), lat.clamp(south, north));
+            haversine_distance(lat, lon, near_lat, near_lon) <= radius_m
+        }
+        ClipMode::Contain => [(south, west), (south, east), (north, west), (north, east)]
+            .iter()
+            .all(|&(clat, clon)| haversine_distance(lat, lon, clat, clon) <= radius_m),
+    }
+}
+
+/// Centre of a bbox given by two corners, across the antimeridian when the
+/// west corner lies east of the east one.
+fn bbox_center(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> (f64, f64) {
+    let (west, east) = (wrap_lon(lon1), wrap_lon(lon2));
+    let lon = if west <= east {
+        (west + east) / 2.0
+    } else {
+        wrap_lon((west + east) / 2.0 + 180.0)
+    };
+    ((clamp_lat(lat1) + clamp_lat(lat2)) / 2.0, lon)
+}
+
+fn validate_and_get_grid_size(split: usize) -> Result<usize> {
+    if split == 0 {
+        return Err(anyhow!("Split parameter must be greater than 0"));
+    }
+    
+    let grid_size = (split as f64).sqrt() as usize;
+    if grid_size * grid_size != split {
+        return Err(anyhow!("Split parameter must be a perfect square (1, 4, 9, 16, 25, etc.), got {}", split));
+    }
+    
+    Ok(grid_size)
+}
+
+pub(crate) fn parse_headers(entries: &[String]) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for entry in entries {
+        let (name, value) = entry
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Header must be in format 'Name: Value', got '{}'", entry))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .with_context(|| format!("Invalid header name in '{}'", entry))?;
+        let value = HeaderValue::from_str(value.trim())
+            .with_context(|| format!("Invalid header value in '{}'", entry))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// Parses an IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`), the form HTTP
+/// servers send in Last-Modified.
+fn parse_http_date(s: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let [_, day, month, year, time, "GMT"] = s.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year: i64 = year.parse().ok()?;
+    let hms: Vec<u64> = time.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let [h, m, sec] = hms[..] else {
+        return None;
+    };
+    // Days since 1970-01-01 from a civil date (inverse of utc_timestamp's).
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + h * 3_600 + m * 60 + sec))
+}
+
+/// True if `path` exists and was modified less than `max_age` ago.
+async fn is_fresh(path: &Path, max_age: Duration) -> bool {
+    match tokio_fs::metadata(path).await.and_then(|m| m.modified()) {
+        Ok(modified) => modified.elapsed().is_ok_and(|age| age < max_age),
+        Err(_) => false,
+    }
+}
+
+fn validate_retry_on(codes: &[u16]) -> Result<Vec<u16>> {
+    for &code in codes {
+        if !(100..=599).contains(&code) {
+            return Err(anyhow!("--retry-on: {} is not a valid HTTP status code", code));
+        }
+        if code == 404 {
+            return Err(anyhow!("--retry-on: 404 is never retryable (the tile does not exist)"));
+        }
+        if (200..300).contains(&code) {
+            return Err(anyhow!("--retry-on: {} is a success status", code));
+        }
+    }
+    let mut codes = codes.to_vec();
+    codes.sort_unstable();
+    codes.dedup();
+    Ok(codes)
+}
+
+/// Writes `tiles` to stdout for `--list`, one line each. A closed pipe, as
+/// from `| head`, ends the listing quietly.
+fn list_tiles(
+    tiles: impl IntoIterator<Item = (i32, i32, u32)>,
+    format: ListFormat,
+    hosts: &HostPool,
+    keys: &KeyPool,
+    urls: &UrlTemplate,
+) -> Result<()> {
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let written = tiles.into_iter().try_for_each(|(x, y, z)| match format {
+        ListFormat::Zxy => writeln!(out, "{} {} {}", z, x, y),
+        ListFormat::Quadkey => writeln!(out, "{}", tile_xy_to_quadkey(x, y, z)),
+        ListFormat::Url => {
+            let quadkey = tile_xy_to_quadkey(x, y, z);
+            let url = urls.render(hosts.get(hosts.pick()), (x, y, z), &quadkey, keys.key(keys.pick()));
+            writeln!(out, "{}", url)
+        }
+    });
+    match written.and_then(|()| out.flush()) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e).context("Writing tile list"),
+        _ => Ok(()),
+    }
+}
+
+/// Failures worth another `--auto-retry` pass. A 404 or an empty body means
+/// there is no tile, which another pass will not change.
+fn retryable_failure(status: TileStatus) -> bool {
+    match status {
+        TileStatus::Http(404) | TileStatus::Empty => false,
+        TileStatus::Http(_) | TileStatus::Error => true,
+        TileStatus::Saved | TileStatus::Kept | TileStatus::Unchanged => false,
+    }
+}
+
+/// Per-tile retry pauses: `base`, doubling per retry, capped at `max`.
+/// With jitter each pause is drawn from the upper half of that, so tiles that
+/// failed together don't all retry at the same instant.
+struct Backoff {
+    base: Duration,
+    max: Duration,
+    jitter: Option<Mutex<StdRng>>,
+}
+
+impl Backoff {
+    fn new(base_ms: u64, max_ms: u64, jitter_seed: Option<u64>) -> Result<Self> {
+        if base_ms == 0 {
+            return Err(anyhow!("--retry-base-ms must be greater than 0"));
+        }
+        if max_ms < base_ms {
+            return Err(anyhow!(
+                "--retry-max-ms ({}) must be at least --retry-base-ms ({})",
+                max_ms,
+                base_ms
+            ));
+        }
+        Ok(Backoff {
+            base: Duration::from_millis(base_ms),
+            max: Duration::from_millis(max_ms),
+            jitter: jitter_seed.map(|seed| Mutex::new(StdRng::seed_from_u64(seed))),
+        })
+    }
+
+    /// Pause before retry `attempt` (1-based), before jitter.
+    fn nominal(&self, attempt: u32) -> Duration {
+        self.base.saturating_mul(1u32 << (attempt - 1).min(16)).min(self.max)
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let nominal = self.nominal(attempt);
+        match &self.jitter {
+            Some(rng) => nominal.mul_f64(rng.lock().unwrap().random_range(0.5..=1.0)),
+            None => nominal,
+        }
+    }
+
+    /// The nominal pauses before each of `retries` retries, for the plan output.
+    fn schedule(&self, retries: u32) -> String {
+        let pauses: Vec<String> = (1..=retries.min(8))
+            .map(|attempt| format!("{}ms", self.nominal(attempt).as_millis()))
+            .collect();
+        let more = if retries > 8 { ", ..." } else { "" };
+        format!("{}{}", pauses.join(", "), more)
+    }
+}
+
+pub(crate) fn validate_timeout(name: &str, secs: u64) -> Result<Duration> {
+    if secs == 0 {
+        return Err(anyhow!("{} must be greater than 0 seconds", name));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn get_tile_subfolder(x: i32, y: i32, grid_size: usize) -> String {
+    if grid_size == 1 {
+        return String::new();
+    }
+    
+    let grid_x = (x.unsigned_abs() as usize) % grid_size;
+    let grid_y = (y.unsigned_abs() as usize) % grid_size;
+    
+    format!("{:02}_{:02}", grid_x, grid_y)
+}
+
+/// Sends `per_host` concurrent HEAD requests to the root of every host so DNS
+/// lookups, TLS handshakes and pooled connections are done before the first
+/// tile is timed. Any response counts; returns how many requests got one.
+async fn warm_up(client: &reqwest::Client, hosts: &HostPool, per_host: usize) -> usize {
+    let requests = (0..hosts.len())
+        .flat_map(|h| std::iter::repeat_n(hosts.get(h), per_host))
+        .map(|host| client.head(format!("{}/", host.trim_end_matches('/'))).send());
+    join_all(requests).await.iter().filter(|r| r.is_ok()).count()
+}
+
+/// Counters shared by all download tasks.
+#[derive(Default)]
+struct RunStats {
+    saved: AtomicUsize,
+    kept: AtomicUsize,
+    unchanged: AtomicUsize,
+    refreshed: AtomicUsize,
+    /// HTTP requests sent, retries included
+    requests: AtomicUsize,
+    /// First out-of-space write error; the run stops once it is set
+    disk_full: Mutex<Option<String>>,
+}
+
+/// Connector layer counting the connections the client opens, so the number
+/// of pooled reuses can be reported as requests minus connections.
+#[derive(Clone, Default)]
+struct ConnectionCounter(Arc<AtomicUsize>);
+
+impl<S> Layer<S> for ConnectionCounter {
+    type Service = CountedConnector<S>;
+
+    fn layer(&self, inner: S) -> CountedConnector<S> {
+        CountedConnector {
+            inner,
+            opened: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CountedConnector<S> {
+    inner: S,
+    opened: Arc<AtomicUsize>,
+}
+
+impl<S: Service<R>, R> Service<R> for CountedConnector<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: R) -> S::Future {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        self.inner.call(dst)
+    }
+}
+
+/// Per-tile CSV report, appended to as each tile finishes.
+struct TileCsv {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl TileCsv {
+    fn create(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        let file = File::create(path).with_context(|| format!("Creating CSV {:?}", path))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "z,x,y,quadkey,url,west,south,east,north,status")?;
+        out.flush()?;
+        Ok(Self { out: Mutex::new(out) })
+    }
+
+    fn record(&self, r: &TileResult) {
+        let (west, south, east, north) = tile_bounds(r.x, r.y, r.z);
+        let mut out = self.out.lock().unwrap();
+        // A failed report row must not abort the download.
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{:.8},{:.8},{:.8},{:.8},{}",
+            r.z,
+            r.x,
+            r.y,
+            r.quadkey,
+            csv_field(&r.url),
+            west,
+            south,
+            east,
+            north,
+            csv_field(&r.status.label())
+        )
+        .and_then(|_| out.flush());
+    }
+}
+
+/// Per-tile timing report for `--timings-csv`, appended to as each tile
+/// finishes.
+struct TimingsCsv {
+    out: BufWriter<File>,
+}
+
+impl TimingsCsv {
+    fn create(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        let file = File::create(path).with_context(|| format!("Creating timings CSV {:?}", path))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "quadkey,z,x,y,bytes,status,retries,elapsed_ms")?;
+        out.flush()?;
+        Ok(Self { out })
+    }
+
+    fn record(&mut self, r: &TileResult) {
+        // A failed report row must not abort the download.
+        let _ = writeln!(
+            self.out,
+            "{},{},{},{},{},{},{},{:.1}",
+            r.quadkey,
+            r.z,
+            r.x,
+            r.y,
+            r.bytes,
+            csv_field(&r.status.label()),
+            r.retries,
+            r.elapsed.as_secs_f64() * 1000.0
+        )
+        .and_then(|_| self.out.flush());
+    }
+}
+
+/// Prints the most frequent tile statuses, e.g. `saved 950, http_404 40`.
+fn print_status_histogram(histogram: &HashMap<String, usize>) {
+    let mut counts: Vec<(&String, &usize)> = histogram.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    let top: Vec<String> = counts
+        .iter()
+        .take(5)
+        .map(|(label, n)| format!("{} {}", label, n))
+        .collect();
+    if !top.is_empty() {
+        println!("Statuses: {}", top.join(", "));
+    }
+}
+
+/// Audit log appended to across runs: one `<timestamp> event=<name> key=value...`
+/// line per run start, tile failure and run end.
+struct RunLog {
+    out: BufWriter<File>,
+}
+
+impl RunLog {
+    fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Opening log file {:?}", path))?;
+        Ok(Self {
+            out: BufWriter::new(file),
+        })
+    }
+
+    fn event(&mut self, name: &str, fields: &str) {
+        // Logging must never abort the download.
+        let _ = writeln!(self.out, "{} event={} {}", utc_timestamp(), name, fields)
+            .and_then(|_| self.out.flush());
+    }
+}
+
+/// Current UTC time as `YYYY-MM-DDTHH:MM:SSZ`.
+pub(crate) fn utc_timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Final state of one tile in a download run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileStatus {
+    /// Downloaded and written to disk
+    Saved,
+    /// Skipped because the existing file is still fresh (`--refresh-older-than`)
+    Kept,
+    /// The server answered 304 to a conditional request (`--conditional`)
+    Unchanged,
+    /// The server returned an empty body
+    Empty,
+    /// The server answered with this non-success HTTP status
+    Http(u16),
+    /// Transport or filesystem error on every host tried
+    Error,
+}
+
+impl TileStatus {
+    /// Short label used in reports, e.g. `saved` or `http_404`.
+    pub fn label(&self) -> String {
+        match self {
+            TileStatus::Saved => "saved".into(),
+            TileStatus::Kept => "kept".into(),
+            TileStatus::Unchanged => "unchanged".into(),
+            TileStatus::Empty => "empty".into(),
+            TileStatus::Http(code) => format!("http_{}", code),
+            TileStatus::Error => "error".into(),
+        }
+    }
+}
+
+/// Outcome of a single tile, passed to the [`run_download_with`] callback as
+/// soon as the tile finishes.
+#[derive(Debug, Clone)]
+pub struct TileResult {
+    pub x: i32,
+    pub y: i32,
+    pub z: u32,
+    pub quadkey: String,
+    /// Request URL with API keys masked
+    pub url: String,
+    pub status: TileStatus,
+    /// Bytes written to disk (0 unless saved)
+    pub bytes: u64,
+    /// Location on disk for saved, kept and unchanged tiles
+    pub path: Option<PathBuf>,
+    /// Failure description for `Http` and `Error` results
+    pub error: Option<String>,
+    /// Requests retried within this run of the tile (not counting
+    /// `--auto-retry` passes, which report the tile again)
+    pub retries: u32,
+    /// Time from picking the tile up to its result, retry pauses included
+    pub elapsed: Duration,
+}
+
+/// Placeholders accepted by `--url-template`.
+const URL_PLACEHOLDERS: [&str; 9] = ["host", "quadkey", "g", "tf", "key", "x", "y", "z", "form"];
+
+/// A parsed `--url-template`: literal text with `{name}` placeholders.
+pub(crate) struct UrlTemplate {
+    parts: Vec<UrlPart>,
+    form: TileForm,
+}
+
+enum UrlPart {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+impl UrlTemplate {
+    pub(crate) fn new(template: &str, form: TileForm) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|i| start + i)
+                .ok_or_else(|| anyhow!("--url-template has an unclosed '{{': {}", template))?;
+            let name = &rest[start + 1..end];
+            let placeholder = URL_PLACEHOLDERS.iter().find(|p| **p == name).ok_or_else(|| {
+                anyhow!(
+                    "--url-template: unknown placeholder {{{}}} (expected one of {})",
+                    name,
+                    URL_PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                )
+            })?;
+            if start > 0 {
+                parts.push(UrlPart::Literal(rest[..start].to_string()));
+            }
+            parts.push(UrlPart::Placeholder(placeholder));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(UrlPart::Literal(rest.to_string()));
+        }
+        Ok(Self { parts, form })
+    }
+
+    pub(crate) fn render(&self, host: &str, (x, y, z): (i32, i32, u32), quadkey: &str, key: &str) -> String {
+        let mut url = String::new();
+        for part in &self.parts {
+            match part {
+                UrlPart::Literal(text) => url.push_str(text),
+                UrlPart::Placeholder(name) => match *name {
+                    "host" => url.push_str(host),
+                    "quadkey" => url.push_str(quadkey),
+                    "g" => url.push_str(DEFAULT_G),
+                    "tf" => url.push_str(DEFAULT_TF),
+                    "key" => url.push_str(key),
+                    "x" => url.push_str(&x.to_string()),
+                    "y" => url.push_str(&y.to_string()),
+                    "z" => url.push_str(&z.to_string()),
+                    "form" => url.push_str(self.form.as_str()),
+                    _ => unreachable!("checked in UrlTemplate::new"),
+                },
+            }
+        }
+        url
+    }
+}
+
+/// State shared by every download task.
+struct Worker {
+    client: reqwest::Client,
+    keys: KeyPool,
+    hosts: HostPool,
+    throttle: Throttle,
+    tps: Option<Arc<TpsController>>,
+    rate_limit: Option<Arc<RateLimiter>>,
+    fetch: FetchOptions,
+    refresh_age: Option<Duration>,
+    paths: TilePaths,
+    retries: u32,
+    retry_on: Vec<u16>,
+    backoff: Backoff,
+    verbose: bool,
+    /// Set by `--trim-empty-dirs`
+    new_dirs: Option<NewDirs>,
+    urls: UrlTemplate,
+    /// `--io-concurrency` permits around each tile's filesystem writes
+    io: Semaphore,
+    stats: Arc<RunStats>,
+    mp: MultiProgress,
+}
+
+impl Worker {
+    /// Prints through the progress bars so output doesn't interleave with them.
+    fn log(&self, msg: &str) {
+        self.mp.suspend(|| eprintln!("{}", msg));
+    }
+
+    fn log_redirect(&self, log_url: &str, target: &str, followed: bool) {
+        if self.verbose {
+            let action = if followed { "served via redirect" } else { "redirect not followed" };
+            self.log(&format!("DEBUG: {} {} to {}", log_url, action, self.keys.mask(target)));
+        }
+    }
+
+    /// Downloads one tile, retrying on the next host after transport errors
+    /// and `--retry-on` statuses.
+    async fn run_tile(&self, x: i32, y: i32, z: u32) -> TileResult {
+        let quadkey = tile_xy_to_quadkey(x, y, z);
+        let out_path = self.paths.path(x, y, z, &quadkey);
+        if let Some(dirs) = &self.new_dirs {
+            dirs.note(&out_path);
+        }
+        let qk = quadkey.as_str();
+        let started = Instant::now();
+        let finish = |status, url, bytes, path, error, retries| TileResult {
+            x,
+            y,
+            z,
+            quadkey: quadkey.clone(),
+            url,
+            status,
+            bytes,
+            path,
+            error,
+            retries,
+            elapsed: started.elapsed(),
+        };
+        let first_host = self.hosts.pick();
+
+        if let Some(max_age) = self.refresh_age {
+            if is_fresh(&out_path, max_age).await {
+                self.stats.kept.fetch_add(1, Ordering::Relaxed);
+                let url = self.urls.render(self.hosts.get(first_host), (x, y, z), qk, "");
+                return finish(TileStatus::Kept, url, 0, Some(out_path), None, 0);
+            }
+            if out_path.exists() {
+                self.stats.refreshed.fetch_add(1, Ordering::Relaxed);
+            }
+        } else if self.fetch.policy == OverwritePolicy::Skip
+            && !self.fetch.conditional
+            && fs::metadata(&out_path).is_ok_and(|m| m.is_file() && m.len() > 0)
+        {
+            self.stats.kept.fetch_add(1, Ordering::Relaxed);
+            let url = self.urls.render(self.hosts.get(first_host), (x, y, z), qk, "");
+            return finish(TileStatus::Kept, url, 0, Some(out_path), None, 0);
+        }
+
+        let mut attempt = 0;
+        let mut retry_hint = None;
+        loop {
+            if attempt > 0 {
+                tokio::time::sleep(retry_hint.take().unwrap_or_else(|| self.backoff.delay(attempt))).await;
+            }
+            // Each retry moves on to the next host.
+            let host = self.hosts.get(first_host + attempt as usize);
+            self.hosts.wait_turn(first_host + attempt as usize).await;
+            if let Some(limiter) = &self.rate_limit {
+                limiter.acquire(host).await;
+            }
+            let can_retry = attempt < self.retries;
+            attempt += 1;
+
+            let key_idx = self.keys.pick();
+            let url = self.urls.render(host, (x, y, z), qk, self.keys.key(key_idx));
+            let log_url = self.keys.mask(&url);
+
+            if self.throttle.wait().await {
+                self.log("WARNING: throttle pause over; resuming downloads");
+            }
+            let _permit = match &self.tps {
+                Some(tps) => Some(tps.acquire().await),
+                None => None,
+            };
+            let span = tracing::info_span!(
+                "tile_request",
+                quadkey = qk,
+                x,
+                y,
+                z,
+                attempt,
+                host,
+                status = tracing::field::Empty,
+                outcome = tracing::field::Empty,
+                bytes = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            );
+            let started = Instant::now();
+            self.stats.requests.fetch_add(1, Ordering::Relaxed);
+            let result = download_one(
+                &self.client,
+                &url,
+                &log_url,
+                &out_path,
+                self.fetch,
+                &self.io,
+                &|target, followed| self.log_redirect(&log_url, target, followed),
+            )
+            .instrument(span.clone())
+            .await;
+            if let Some(tps) = &self.tps {
+                tps.record(started.elapsed());
+            }
+            span.record("duration_ms", started.elapsed().as_millis() as u64);
+            match &result {
+                Ok(outcome) => {
+                    span.record("outcome", outcome.label());
+                    if let TileOutcome::Saved(bytes) = outcome {
+                        span.record("bytes", bytes);
+                    }
+                }
+                Err(_) => {
+                    span.record("outcome", "error");
+                }
+            }
+            drop(span);
+
+            match result {
+                Ok(TileOutcome::Saved(bytes)) => {
+                    self.stats.saved.fetch_add(1, Ordering::Relaxed);
+                    self.throttle.record_success();
+                    return finish(TileStatus::Saved, log_url, bytes, Some(out_path), None, attempt - 1);
+                }
+                Ok(TileOutcome::Http { status, retry_after }) => {
+                    self.log(&format!("HTTP {} for {}", status, log_url));
+                    if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        && let Some(pause) = self.throttle.record_429(retry_after)
+                    {
+                        self.log(&format!(
+                            "WARNING: too many HTTP 429 responses; pausing all downloads for {}s{}",
+                            pause.as_secs(),
+                            if retry_after.is_some() { " (Retry-After)" } else { "" }
+                        ));
+                    }
+                    if (status == reqwest::StatusCode::FORBIDDEN
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS)
+                        && self.keys.bench(key_idx)
+                        && self.keys.len() > 1
+                    {
+                        self.log(&format!(
+                            "API key #{} is being rejected or throttled; deprioritizing it for {}s",
+                            key_idx + 1,
+                            KEY_COOLDOWN.as_secs()
+                        ));
+                    }
+                    if can_retry && self.retry_on.contains(&status.as_u16()) {
+                        retry_hint = retry_after.map(|d| d.min(self.backoff.max));
+                        continue;
+                    }
+                    let error = format!("HTTP {}", status);
+                    return finish(TileStatus::Http(status.as_u16()), log_url, 0, None, Some(error), attempt - 1);
+                }
+                Ok(TileOutcome::NotModified) => {
+                    self.stats.unchanged.fetch_add(1, Ordering::Relaxed);
+                    self.throttle.record_success();
+                    return finish(TileStatus::Unchanged, log_url, 0, Some(out_path), None, attempt - 1);
+                }
+                Ok(TileOutcome::KeptExisting) => {
+                    self.stats.kept.fetch_add(1, Ordering::Relaxed);
+                    self.throttle.record_success();
+                    return finish(TileStatus::Kept, log_url, 0, Some(out_path), None, attempt - 1);
+                }
+                Ok(TileOutcome::WrongContentType(content_type)) => {
+                    let error = format!("unexpected Content-Type {}", content_type);
+                    self.log(&format!("Rejected {}: {}", log_url, error));
+                    return finish(TileStatus::Error, log_url, 0, None, Some(error), attempt - 1);
+                }
+                Ok(TileOutcome::DiskFull(error)) => {
+                    self.stats.disk_full.lock().unwrap().get_or_insert_with(|| error.clone());
+                    return finish(TileStatus::Error, log_url, 0, None, Some(error), attempt - 1);
+                }
+                Ok(TileOutcome::TooLarge) => {
+                    let error = format!(
+                        "body exceeds --max-tile-bytes ({})",
+                        self.fetch.max_bytes.unwrap_or_default()
+                    );
+                    self.log(&format!("Discarded {}: {}", log_url, error));
+                    return finish(TileStatus::Error, log_url, 0, None, Some(error), attempt - 1);
+                }
+                Ok(TileOutcome::Empty) => {
+                    self.log(&format!("Empty response for {}", log_url));
+                    return finish(TileStatus::Empty, log_url, 0, None, None, attempt - 1);
+                }
+                Err(e) => {
+                    let error = self.keys.mask(&format!("{:#}", e));
+                    self.log(&format!("Exception downloading {}: {}", log_url, error));
+                    if can_retry {
+                        continue;
+                    }
+                    return finish(TileStatus::Error, log_url, 0, None, Some(error), attempt - 1);
+                }
+            }
+        }
+    }
+}
+
+/// Installs the global `--trace` subscriber: one event per closed
+/// `tile_request` span, carrying its fields and busy/idle times.
+fn init_tracing(format: TraceFormat, file: Option<&Path>) -> Result<()> {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    let writer = match file {
+        Some(path) => {
+            let file = File::create(path).with_context(|| format!("Creating --trace-file {:?}", path))?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false)
+        .with_writer(writer);
+    let installed = match format {
+        TraceFormat::Text => builder.try_init(),
+        TraceFormat::Json => builder.json().try_init(),
+    };
+    if installed.is_err() {
+        eprintln!("WARNING: a tracing subscriber is already installed; --trace is ignored");
+    }
     Ok(())
 }
+
+/// `--validate-only`: checks that every expected tile exists (non-empty) at
+/// its layout path, without any requests. Missing tiles are listed as
+/// quadkeys in `missing_file`, ready for `--quadkeys-stdin`.
+fn validate_coverage(
+    tiles: impl IntoIterator<Item = (i32, i32, u32)>,
+    paths: &TilePaths,
+    missing_file: Option<&Path>,
+) -> Result<RunOutcome> {
+    let mut missing = Vec::new();
+    let mut expected = 0;
+    for (x, y, z) in tiles {
+        expected += 1;
+        let quadkey = tile_xy_to_quadkey(x, y, z);
+        let present = fs::metadata(paths.path(x, y, z, &quadkey)).is_ok_and(|m| m.is_file() && m.len() > 0);
+        if !present {
+            missing.push(quadkey);
+        }
+    }
+
+    let found = expected - missing.len();
+    println!("Validate: {}/{} expected tiles present, {} missing", found, expected, missing.len());
+    for quadkey in missing.iter().take(10) {
+        let (x, y, z) = quadkey_to_tile_xy(quadkey).unwrap_or_default();
+        println!("  missing z{} x{} y{} ({})", z, x, y, quadkey);
+    }
+    if missing.len() > 10 {
+        println!("  ... and {} more", missing.len() - 10);
+    }
+    if let Some(path) = missing_file {
+        let mut text = missing.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        fs::write(path, text).with_context(|| format!("Writing missing-tile list {:?}", path))?;
+        println!("Wrote {} missing quadkeys to {}", missing.len(), path.display());
+    }
+
+    Ok(if missing.is_empty() {
+        RunOutcome::Complete
+    } else if found == 0 {
+        RunOutcome::Failed
+    } else {
+        RunOutcome::Partial { failed: missing.len() }
+    })
+}
+
+/// `--dry-run --diff`: sorts the planned tiles into missing, kept and
+/// replaced by checking the output directory the same way a run would.
+/// Files the run would only revalidate (`--conditional`, or an overwrite
+/// policy that compares with the server) count as overwritten.
+fn diff_existing(
+    tiles: impl IntoIterator<Item = (i32, i32, u32)>,
+    paths: &TilePaths,
+    policy: OverwritePolicy,
+    conditional: bool,
+    refresh_age: Option<Duration>,
+) {
+    let (mut missing, mut skip, mut overwrite) = (0usize, 0usize, 0usize);
+    for (x, y, z) in tiles {
+        let quadkey = tile_xy_to_quadkey(x, y, z);
+        let meta = match fs::metadata(paths.path(x, y, z, &quadkey)) {
+            Ok(m) if m.is_file() && m.len() > 0 => m,
+            _ => {
+                missing += 1;
+                continue;
+            }
+        };
+        let kept = match refresh_age {
+            Some(max_age) => meta
+                .modified()
+                .is_ok_and(|t| t.elapsed().is_ok_and(|age| age < max_age)),
+            None => policy == OverwritePolicy::Skip && !conditional,
+        };
+        if kept {
+            skip += 1;
+        } else {
+            overwrite += 1;
+        }
+    }
+    println!("Diff against {}:", paths.out_dir.display());
+    println!("  would download:  {} (missing)", missing);
+    println!("  would skip:      {} (present)", skip);
+    if refresh_age.is_some() {
+        println!("  would overwrite: {} (present, older than --refresh-older-than)", overwrite);
+    } else {
+        println!("  would overwrite: {} (present, --overwrite-policy {})", overwrite, policy.as_str());
+    }
+}
+
+/// Fetches a handful of tiles into memory for a `--dry-run --sample` estimate.
+struct SampleRequest<'a> {
+    client: &'a reqwest::Client,
+    keys: &'a KeyPool,
+    hosts: &'a HostPool,
+    urls: &'a UrlTemplate,
+    timeout: Duration,
+}
+
+impl SampleRequest<'_> {
+    /// Requests `n` tiles spread evenly over `tiles`, one at a time, then
+    /// extrapolates the full run's duration and size.
+    async fn estimate(&self, tiles: &[(i32, i32, u32)], n: usize, in_flight: f64) {
+        if tiles.is_empty() {
+            return;
+        }
+        let n = n.clamp(1, tiles.len());
+        let step = tiles.len() as f64 / n as f64;
+        let mut latency_total = 0.0;
+        let mut sizes = Vec::new();
+        let mut failed = 0;
+        for i in 0..n {
+            let (x, y, z) = tiles[(i as f64 * step) as usize];
+            let qk = tile_xy_to_quadkey(x, y, z);
+            let host = self.hosts.get(self.hosts.pick());
+            let url = self.urls.render(host, (x, y, z), &qk, self.keys.key(self.keys.pick()));
+            let started = Instant::now();
+            let body = match self.client.get(&url).timeout(self.timeout).send().await {
+                Ok(resp) if resp.status().is_success() => resp.bytes().await.ok(),
+                Ok(resp) => {
+                    eprintln!("Sample: HTTP {} for {}", resp.status(), self.keys.mask(&url));
+                    None
+                }
+                Err(e) => {
+                    eprintln!("Sample: {}", self.keys.mask(&e.to_string()));
+                    None
+                }
+            };
+            let Some(bytes) = body else {
+                failed += 1;
+                continue;
+            };
+            latency_total += started.elapsed().as_secs_f64();
+            if !bytes.is_empty() {
+                sizes.push(bytes.len() as u64);
+            }
+        }
+
+        let answered = n - failed;
+        if answered == 0 || sizes.is_empty() {
+            println!("Sample: {} tiles, {} failed; no tile data received, cannot estimate.", n, failed);
+            return;
+        }
+        let avg_latency = latency_total / answered as f64;
+        println!(
+            "Sample: {} tiles, {} failed, average latency {:.0} ms",
+            n,
+            failed,
+            avg_latency * 1000.0
+        );
+        let avg_size = sizes.iter().sum::<u64>() as f64 / sizes.len() as f64;
+        let hit_rate = sizes.len() as f64 / answered as f64;
+        let total = tiles.len() as f64;
+        println!(
+            "Estimate: ~{} and ~{} for {} tiles",
+            format_duration(total * avg_latency / in_flight.max(1.0)),
+            format_bytes((total * hit_rate * avg_size) as u64),
+            tiles.len()
+        );
+        println!(
+            "  assumes {:.0} requests in flight at the sampled latency, {:.0}% of tiles returning data \
+             (average {}), and no throttling or retries",
+            in_flight,
+            hit_rate * 100.0,
+            format_bytes(avg_size as u64)
+        );
+    }
+}
+
+/// A tile written to disk during this run.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SavedTile {
+    z: u32,
+    x: i32,
+    y: i32,
+    path: PathBuf,
+    bytes: u64,
+    /// Size on disk with `--gzip`
+    compressed_bytes: Option<u64>,
+}
+
+fn tile_style(multi_zoom: bool) -> ProgressStyle {
+    let template = if multi_zoom {
+        "z{prefix:<3} [{elapsed_precise}] [{bar:30.cyan/blue}] {pos}/{len} - ETA {eta}"
+    } else {
+        "[{elapsed_precise}] [{bar:30.cyan/blue}] {pos}/{len} - ETA {eta}"
+    };
+    ProgressStyle::with_template(template).unwrap()
+}
+
+/// Directories below the output root that this run had to create, so that
+/// `--trim-empty-dirs` never touches anything that existed beforehand.
+struct NewDirs {
+    root: PathBuf,
+    created: Mutex<HashSet<PathBuf>>,
+}
+
+impl NewDirs {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            created: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Records every missing ancestor of `file` below the root. Called before
+    /// the tile's directory is created.
+    fn note(&self, file: &Path) {
+        let mut created = self.created.lock().unwrap();
+        for dir in file.ancestors().skip(1) {
+            if dir == self.root || !dir.starts_with(&self.root) || dir.exists() {
+                break;
+            }
+            created.insert(dir.to_path_buf());
+        }
+    }
+
+    /// Removes the recorded directories that are empty, deepest first, and
+    /// returns how many were removed. `remove_dir` refuses non-empty ones.
+    fn trim(&self) -> usize {
+        let mut dirs: Vec<PathBuf> = self.created.lock().unwrap().iter().cloned().collect();
+        dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        dirs.iter().filter(|d| fs::remove_dir(d).is_ok()).count()
+    }
+}
+
+/// Builds the on-disk path of a tile from the output layout options.
+struct TilePaths {
+    out_dir: PathBuf,
+    grid_size: usize,
+    qk_prefix_depth: usize,
+    /// Put each zoom level in its own top-level `z<z>/` directory
+    /// (`--split-by-zoom`, implied by `--pyramid`).
+    zoom_dirs: bool,
+    /// Append `.gz` to the extension (`--gzip`)
+    gzip: bool,
+    /// Write TMS row numbers in file names (`--flip-y`). Quadkeys, requests
+    /// and `--split` folders keep XYZ rows.
+    flip_y: bool,
+    extension: &'static str,
+}
+
+impl TilePaths {
+    fn path(&self, x: i32, y: i32, z: u32, quadkey: &str) -> PathBuf {
+        let mut dir = self.out_dir.clone();
+        if self.zoom_dirs {
+            dir.push(format!("z{}", z));
+        }
+
+        // Determine subfolder based on tile coordinates
+        let subfolder = get_tile_subfolder(x, y, self.grid_size);
+        if !subfolder.is_empty() {
+            dir.push(subfolder);
+        }
+        if self.qk_prefix_depth > 0 {
+            dir.push(quadkey_prefix(quadkey, self.qk_prefix_depth));
+        }
+
+        let row = if self.flip_y { flip_row(y, z) } else { y };
+        let gz = if self.gzip { ".gz" } else { "" };
+        dir.join(format!("{}.{}{}", tile_stem(z, x, row), self.extension, gz))
+    }
+}
+
+/// How a download that ran to the end went. Errors that stop the run (bad
+/// arguments, a rejected API key) are returned as a [`DownloadError`] instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// Every queued tile was saved, kept or unchanged, or nothing was queued.
+    Complete,
+    /// Some tiles succeeded and `failed` did not.
+    Partial { failed: usize },
+    /// Tiles were queued but none succeeded.
+    Failed,
+}
+
+pub async fn run_download(args: Args) -> Result<RunOutcome, DownloadError> {
+    run_download_with(args, |_| {}).await
+}
+
+/// Runs a download like [`run_download`], calling `on_tile` with each tile's
+/// [`TileResult`] as it finishes. The CLI's progress bars and `--csv` report
+/// are driven from the same results.
+pub async fn run_download_with<F>(args: Args, on_tile: F) -> Result<RunOutcome, DownloadError>
+where
+    F: FnMut(TileResult),
+{
+    Ok(download(args, on_tile).await?)
+}
+
+async fn download<F>(mut args: Args, mut on_tile: F) -> Result<RunOutcome>
+where
+    F: FnMut(TileResult),
+{
+    args.color.apply();
+    // --list keeps stdout to the tile lines alone, so the plan is not printed.
+    let list = args.list;
+    macro_rules! plan {
+        ($($arg:tt)*) => {
+            if !list {
+                println!($($arg)*);
+            }
+        };
+    }
+    if args.imagery {
+        args.form = TileForm::Jpeg;
+        args.url_template = IMAGERY_URL_TEMPLATE.to_string();
+    }
+    // Explicit settings stricter than the preset are kept.
+    if args.polite {
+        args.concurrency = args.concurrency.min(POLITE_CONCURRENCY);
+        args.host_delay_ms = args.host_delay_ms.max(POLITE_HOST_DELAY_MS);
+        if args.user_agent == USER_AGENT {
+            args.user_agent = POLITE_USER_AGENT.to_string();
+        }
+    }
+    if let Some(format) = args.trace {
+        init_tracing(format, args.trace_file.as_deref())?;
+    }
+
+    // Validate split parameter
+    let grid_size = validate_and_get_grid_size(args.split)?;
+
+    // Validate timeouts
+    let request_timeout = validate_timeout("--request-timeout", args.request_timeout)?;
+    let connect_timeout = validate_timeout("--connect-timeout", args.connect_timeout)?;
+    let progress_interval = progress::interval(args.progress_interval)?;
+    if args.io_concurrency == 0 {
+        return Err(anyhow!("--io-concurrency must be greater than 0"));
+    }
+    if args.batch_size == Some(0) {
+        return Err(anyhow!("--batch-size must be greater than 0"));
+    }
+    if args.batch_size.is_some() && args.order == TileOrder::Distance {
+        return Err(anyhow!("--batch-size downloads in row order; it can't be combined with --order distance"));
+    }
+    if args.batch_size.is_some() && args.pyramid {
+        return Err(anyhow!("--batch-size only generates the --max-zoom tiles; it can't be combined with --pyramid"));
+    }
+
+    let refresh_age = match args.refresh_older_than {
+        Some(days) if !(days.is_finite() && days >= 0.0) => {
+            return Err(anyhow!("--refresh-older-than must be a non-negative number of days"));
+        }
+        Some(days) => Some(Duration::from_secs_f64(days * 86_400.0)),
+        None => None,
+    };
+
+    let tps = match args.target_tps {
+        Some(t) if !(t.is_finite() && t > 0.0) => {
+            return Err(anyhow!("--target-tps must be a positive number"));
+        }
+        Some(t) => Some(Arc::new(TpsController::new(t, args.concurrency.max(1)))),
+        None => None,
+    };
+
+    let throttle = Throttle::new(
+        args.throttle_threshold,
+        Duration::from_secs(args.throttle_window),
+        Duration::from_secs(args.throttle_pause),
+    );
+
+    if args.form != TileForm::Web3d {
+        let glb_only = [
+            ("--merge", args.merge.is_some()),
+            ("--tileset", args.tileset.is_some()),
+            ("--preview", args.preview.is_some()),
+        ];
+        if let Some((flag, _)) = glb_only.iter().find(|(_, set)| *set) {
+            let source = if args.imagery {
+                "--imagery".to_string()
+            } else {
+                format!("--form {}", args.form.as_str())
+            };
+            return Err(anyhow!(
+                "{} needs GLB tiles, but {} downloads .{} imagery",
+                flag,
+                source,
+                args.form.extension()
+            ));
+        }
+    }
+
+    let retry_on = validate_retry_on(&args.retry_on)?;
+    let keys = KeyPool::new(args.api_key)?;
+    let mut hosts = HostPool::new(args.hosts)?;
+    if args.host_delay_ms > 0 {
+        hosts = hosts.spaced(Duration::from_millis(args.host_delay_ms));
+    }
+    // Drawn up front so an unseeded run can still be replayed with --seed.
+    let seed = args.seed.unwrap_or_else(rand::random);
+    if args.randomize_hosts {
+        hosts = hosts.randomized(seed);
+    }
+    let backoff = Backoff::new(args.retry_base_ms, args.retry_max_ms, args.retry_jitter.then_some(seed))?;
+    let rate_limit = match args.rate_limit {
+        Some(r) if !(r.is_finite() && r > 0.0) => {
+            return Err(anyhow!("--rate-limit must be a positive number of requests per second"));
+        }
+        Some(r) => Some(Arc::new(RateLimiter::new(r, args.rate_limit_scope, &hosts))),
+        None => None,
+    };
+    let urls = UrlTemplate::new(&args.url_template, args.form)?;
+    let headers = parse_headers(&args.header)?;
+    if args.user_agent.trim().is_empty() {
+        return Err(anyhow!("--user-agent must not be empty"));
+    }
+
+    // Determine bbox
+    let listed = if args.quadkeys_stdin {
+        Some(read_quadkeys(std::io::stdin().lock(), args.strict_quadkeys)?)
+    } else {
+        None
+    };
+
+    #[cfg(feature = "geocode")]
+    let geocoded = match &args.place {
+        Some(place) => {
+            let found = crate::geocode::locate(place, keys.key(0), &args.user_agent, request_timeout)
+                .await
+                .map_err(DownloadError::invalid_bbox)?;
+            plan!("Place {:?} → {} ({:.6}, {:.6})", place, found.name, found.lat, found.lon);
+            Some(format!("{},{}", found.lat, found.lon))
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "geocode"))]
+    let geocoded: Option<String> = None;
+    let center_coord = geocoded.as_ref().or(args.center_coord.as_ref());
+
+    let (lat1, lon1, lat2, lon2) = if let Some(listed) = &listed {
+        if listed.is_empty() {
+            return Err(anyhow!("No valid quadkeys on stdin"));
+        }
+        // West-first, so tiles either side of the antimeridian give a
+        // narrow box crossing it rather than a world-wide one.
+        let (west, east) = tiles_lon_span(listed.iter().map(|&(x, _, z)| (x, z))).unwrap_or((-180.0, 180.0));
+        let (mut south, mut north) = (90.0f64, -90.0f64);
+        for &(x, y, z) in listed {
+            let (_, s, _, n) = tile_bounds(x, y, z);
+            south = south.min(s);
+            north = north.max(n);
+        }
+        plan!(
+            "Bbox of listed quadkeys: {}",
+            args.bbox_format.format(south, west, north, east)
+        );
+        (south, west, north, east)
+    } else if let (Some(center), Some(size)) =
+        (center_coord, args.size.or(args.radius.map(|r| r * 2.0)))
+    {
+        let (clat, clon) = parse_coordinates(center).map_err(DownloadError::invalid_bbox)?;
+        let (a, b, c, d) = create_square_bbox(clat, clon, size);
+        plan!("Calculated bbox: {}", args.bbox_format.format(a, b, c, d));
+        (a, b, c, d)
+    } else if let (Some(sw), Some(ne)) = (&args.sw_coord, &args.ne_coord) {
+        let (lat_sw, lon_sw) = parse_coordinates(sw).map_err(DownloadError::invalid_bbox)?;
+        let (lat_ne, lon_ne) = parse_coordinates(ne).map_err(DownloadError::invalid_bbox)?;
+        let problems = swapped_corners(lat_sw, lon_sw, lat_ne, lon_ne);
+        if !problems.is_empty() {
+            if args.strict_bbox {
+                let msg = format!("Bbox corners look swapped: {}", problems.join("; "));
+                return Err(DownloadError::invalid_bbox(msg).into());
+            }
+            for problem in &problems {
+                eprintln!("WARNING: corners look swapped: {}; continuing with the normalized box", problem);
+            }
+        }
+        plan!(
+            "Using specified bbox: {}",
+            args.bbox_format.format(lat_sw, lon_sw, lat_ne, lon_ne)
+        );
+        (lat_sw, lon_sw, lat_ne, lon_ne)
+    } else {
+        return Err(DownloadError::invalid_bbox(
+            "Must specify either (--sw-coord, --ne-coord) OR (--center-coord, --size | --radius)",
+        )
+        .into());
+    };
+    let circle = match args.radius {
+        Some(r) if !(r.is_finite() && r > 0.0) => {
+            return Err(anyhow!("--radius must be a positive number of meters"));
+        }
+        Some(r) => {
+            let (clat, clon) = bbox_center(lat1, lon1, lat2, lon2);
+            Some((clat, clon, r))
+        }
+        None => None,
+    };
+    if args.no_clamp_lat {
+        for lat in [lat1, lat2] {
+            checked_lat(lat)
+                .context("--no-clamp-lat")
+                .map_err(DownloadError::invalid_bbox)?;
+        }
+    }
+
+    let connections = ConnectionCounter::default();
+    let client = reqwest::Client::builder()
+        .user_agent(args.user_agent.as_str())
+        // Applied after the default User-Agent so a --header can override it.
+        .default_headers(headers)
+        .connect_timeout(connect_timeout)
+        .timeout(request_timeout)
+        .pool_idle_timeout(Duration::from_secs(30))
+        // 0 idle connections: every request opens a fresh connection.
+        .pool_max_idle_per_host(if args.no_pool { 0 } else { 32 })
+        .connector_layer(connections.clone())
+        .redirect(if args.no_redirects {
+            reqwest::redirect::Policy::none()
+        } else {
+            reqwest::redirect::Policy::limited(args.max_redirects)
+        })
+        .build()?;
+
+    let mut zooms = match (&listed, args.resolution) {
+        (Some(listed), _) => listed.iter().map(|&(_, _, z)| z).collect(),
+        (None, Some(res)) => {
+            if !(res.is_finite() && res > 0.0) {
+                return Err(anyhow!("--resolution must be a positive number of metres per pixel"));
+            }
+            let center_lat = (lat1 + lat2) / 2.0;
+            let z = zoom_for_resolution(center_lat, res);
+            plan!(
+                "Resolution {} m/px → zoom {} ({:.2} m/px at the bbox centre)",
+                res,
+                z,
+                ground_resolution(center_lat, z)
+            );
+            vec![z]
+        }
+        (None, None) => match (args.min_zoom, args.max_zoom) {
+            (Some(min), Some(max)) if args.pyramid => {
+                if min == 0 || min > max {
+                    return Err(anyhow!("--pyramid needs 1 <= --min-zoom <= --max-zoom"));
+                }
+                (min..=max).collect()
+            }
+            _ => args.zoom.clone(),
+        },
+    };
+    zooms.sort_unstable();
+    zooms.dedup();
+    if let Some(&z) = zooms.iter().find(|&&z| z > MAX_ZOOM) {
+        return Err(anyhow!("Zoom {} is above the maximum of {}", z, MAX_ZOOM));
+    }
+
+    let filter = RegionFilter {
+        circle,
+        clip: args.clip_mode,
+        bbox: (lat1, lon1, lat2, lon2),
+        exclusions: args
+            .exclude_bbox
+            .iter()
+            .map(|s| parse_exclude_bbox(s))
+            .collect::<Result<Vec<_>>>()
+            .map_err(DownloadError::invalid_bbox)?,
+    };
+    // With --batch-size, region tiles are only counted here and generated
+    // again as the download pulls them.
+    let lazy = args.batch_size.is_some() && listed.is_none();
+
+    let requested_zooms = zooms.clone();
+    let mut tiles: Vec<(i32, i32, u32)>;
+    let mut levels;
+    let mut per_zoom;
+    let mut duplicates;
+    let mut intersecting;
+    let mut excluded;
+    let mut total;
+    loop {
+        tiles = Vec::new();
+        levels = Vec::new();
+        per_zoom = Vec::new();
+        duplicates = 0;
+        intersecting = 0;
+        excluded = 0;
+        // Clipping keeps at least ~3/4 of a large region's tiles, so a level
+        // whose raw count is over twice the cap is skipped without building
+        // its tile list.
+        let can_downgrade = args.auto_downgrade_zoom && zooms[0] > 1;
+        let raw: usize = if can_downgrade {
+            zooms
+                .iter()
+                .map(|&z| range_tile_count(&bbox_tile_ranges(lat1, lon1, lat2, lon2, z)))
+                .sum()
+        } else {
+            0
+        };
+        let skip = can_downgrade && raw / 2 > args.max_tiles;
+        for &z in zooms.iter().filter(|_| !skip) {
+            if let Some(listed) = &listed {
+                let mut zoom_tiles: Vec<(i32, i32)> =
+                    listed.iter().filter(|t| t.2 == z).map(|&(x, y, _)| (x, y)).collect();
+                let before = zoom_tiles.len();
+                zoom_tiles.retain(|&(x, y)| !filter.excludes(x, y, z));
+                excluded += before - zoom_tiles.len();
+                if zoom_tiles.is_empty() {
+                    continue;
+                }
+                per_zoom.push((z, tile_extent(&zoom_tiles, z), zoom_tiles.len()));
+                tiles.extend(zoom_tiles.into_iter().map(|(x, y)| (x, y, z)));
+                continue;
+            }
+            if args.pyramid && Some(&z) != zooms.last() {
+                continue;
+            }
+            let ranges = bbox_tile_ranges(lat1, lon1, lat2, lon2, z);
+            let (mut unique, mut kept) = (0, 0);
+            for (x, y) in tiles_in_ranges(&ranges) {
+                unique += 1;
+                if !filter.clips(x, y, z) {
+                    continue;
+                }
+                if filter.excludes(x, y, z) {
+                    excluded += 1;
+                    continue;
+                }
+                kept += 1;
+                if !lazy {
+                    tiles.push((x, y, z));
+                }
+            }
+            duplicates += range_tile_count(&ranges) - unique;
+            intersecting += unique;
+            if lazy {
+                levels.push((z, ranges.clone()));
+            }
+            per_zoom.push((z, ranges, kept));
+        }
+        if args.pyramid && !skip {
+            let (parents, parent_zooms) = pyramid_parents(&tiles, zooms[0]);
+            tiles.splice(0..0, parents);
+            per_zoom.splice(0..0, parent_zooms);
+        }
+        total = per_zoom.iter().map(|p| p.2).sum::<usize>();
+        let over = if skip { raw } else { total };
+        if !can_downgrade || over <= args.max_tiles {
+            break;
+        }
+        zooms = zooms.iter().map(|z| z - 1).collect();
+        plan!(
+            "Auto-downgrade: {}{} tiles exceeds --max-tiles {}; trying zoom {}",
+            if skip { "about " } else { "" },
+            over,
+            args.max_tiles,
+            zooms.iter().map(|z| z.to_string()).collect::<Vec<_>>().join(", ")
+        );
+    }
+    if zooms != requested_zooms {
+        let center_lat = (lat1 + lat2) / 2.0;
+        let top = *zooms.last().unwrap_or(&1);
+        plan!(
+            "Auto-downgrade: zoom {} → {} fits {} tiles ({:.2} m/px at the bbox centre)",
+            requested_zooms.last().unwrap_or(&top),
+            top,
+            total,
+            ground_resolution(center_lat, top)
+        );
+    }
+    if let Some((_, _, radius)) = circle {
+        plan!(
+            "Radius: {} m keeps {} of {} tiles in the bounding square",
+            radius,
+            total,
+            intersecting
+        );
+    } else if args.clip_mode == ClipMode::Contain {
+        plan!(
+            "Clip mode: contain keeps {} tiles fully inside the region (intersect would keep {})",
+            total,
+            intersecting
+        );
+    }
+    if !filter.exclusions.is_empty() {
+        plan!("Excluded: {} tiles inside --exclude-bbox", excluded);
+    }
+    if total == 0 {
+        plan!("No tiles in the specified range.");
+        return Ok(RunOutcome::Complete);
+    }
+    if total > args.max_tiles && !args.force {
+        return Err(anyhow!(
+            "{} tiles exceeds --max-tiles {}; narrow the bbox, lower --zoom, or pass --auto-downgrade-zoom or --force",
+            total,
+            args.max_tiles
+        ));
+    }
+
+    if args.order == TileOrder::Distance {
+        let (clat, clon) = bbox_center(lat1, lon1, lat2, lon2);
+        // Distances are non-negative, so their bit patterns sort like the values.
+        tiles.sort_by_cached_key(|&(x, y, z)| {
+            let (lon, lat) = tile_center(x, y, z);
+            (z, haversine_distance(clat, clon, lat, lon).to_bits())
+        });
+        plan!("Order: nearest to {:.6},{:.6} first", clat, clon);
+    }
+
+    let multi_zoom = zooms.len() > 1;
+    if multi_zoom {
+        plan!(
+            "Zoom levels: {}",
+            zooms.iter().map(|z| z.to_string()).collect::<Vec<_>>().join(", ")
+        );
+        for (z, ranges, count) in &per_zoom {
+            plan!("  z{}: {} tiles, range {:?}", z, count, ranges);
+        }
+    } else {
+        plan!("Zoom level: {}", zooms[0]);
+        plan!("Tile range: {:?}", per_zoom[0].1);
+    }
+    let center_lat = (lat1 + lat2) / 2.0;
+    for &z in &zooms {
+        let mpp = ground_resolution(center_lat, z);
+        plan!(
+            "Zoom {} ≈ {:.2} m/px ({:.0} m/tile) at {:.1}°{}",
+            z,
+            mpp,
+            mpp * TILE_SIZE_PX as f64,
+            center_lat.abs(),
+            if center_lat < 0.0 { 'S' } else { 'N' }
+        );
+    }
+    if duplicates > 0 {
+        plan!("Tile total: {} ({} duplicates removed)", total, duplicates);
+    } else {
+        plan!("Tile total: {} ", total);
+    }
+    if let Some(n) = args.batch_size {
+        plan!("Batches: {} of up to {} tiles", total.div_ceil(n), n);
+    }
+    if args.imagery {
+        plan!("Imagery: aerial .{} tiles", args.form.extension());
+    }
+    match args.target_tps {
+        Some(t) => plan!("Concurrency: adaptive, targeting {} tiles/s (max {})", t, args.concurrency),
+        None => plan!("Concurrency: {}", args.concurrency),
+    }
+    if let Some(rate) = args.rate_limit {
+        match args.rate_limit_scope {
+            RateLimitScope::Global => plan!("Rate limit: {} requests/s", rate),
+            RateLimitScope::PerHost => {
+                plan!("Rate limit: {} requests/s per host ({} hosts)", rate, hosts.len())
+            }
+        }
+    }
+    if args.host_delay_ms > 0 && !args.polite {
+        plan!("Host delay: at least {}ms between requests to each host", args.host_delay_ms);
+    }
+    if args.polite {
+        plan!(
+            "Polite: concurrency {}, host delay {}ms, User-Agent {:?}",
+            args.concurrency,
+            args.host_delay_ms,
+            args.user_agent
+        );
+    }
+    if args.io_concurrency < args.concurrency {
+        plan!("Disk writes: up to {} at once", args.io_concurrency);
+    }
+    if keys.len() > 1 {
+        plan!("API keys: {} (round-robin)", keys.len());
+    }
+    if hosts.len() > 1 {
+        if args.randomize_hosts {
+            plan!("Hosts: {} (random per tile with failover, --seed {})", hosts.len(), seed);
+        } else {
+            plan!("Hosts: {} (round-robin with failover)", hosts.len());
+        }
+    }
+    if args.retries > 0 {
+        let jitter = if args.retry_jitter {
+            format!(", jittered to 50-100%, --seed {}", seed)
+        } else {
+            String::new()
+        };
+        plan!("Retry backoff: {}{}", backoff.schedule(args.retries), jitter);
+    }
+    if args.split > 1 {
+        plan!("Split: {} ({}x{} grid)", args.split, grid_size, grid_size);
+    }
+    if args.qk_prefix_depth > 0 {
+        plan!("Quadkey prefix depth: {}", args.qk_prefix_depth);
+    }
+    if let Some(days) = args.refresh_older_than {
+        plan!("Refreshing tiles older than {} day(s)", days);
+    }
+    plan!("Directory: {}", args.out.display());
+    // Corners of the deepest level's ranges; a box split at the antimeridian
+    // spans the whole world and has no covering tile.
+    let overview = args.overview.then(|| {
+        let (z, ranges, _) = per_zoom.iter().max_by_key(|p| p.0).expect("per_zoom is non-empty");
+        let corners: Vec<String> = ranges
+            .iter()
+            .flat_map(|&(x0, x1, y0, y1)| [tile_xy_to_quadkey(x0, y0, *z), tile_xy_to_quadkey(x1, y1, *z)])
+            .collect();
+        common_quadkey_prefix(&corners)
+    });
+    match overview.as_deref() {
+        Some("") => plan!("Overview: no single tile covers the area (its corners share no quadkey prefix)"),
+        Some(quadkey) => plan!("Overview: quadkey {} (zoom {})", quadkey, quadkey.len()),
+        None => {}
+    }
+
+    let paths = TilePaths {
+        out_dir: args.out.clone(),
+        grid_size,
+        qk_prefix_depth: args.qk_prefix_depth,
+        zoom_dirs: args.pyramid || args.split_by_zoom,
+        gzip: args.gzip,
+        flip_y: args.flip_y,
+        extension: args.form.extension(),
+    };
+    let region = RegionTiles { levels, filter };
+
+    if args.list {
+        let format = args.list_format;
+        if lazy {
+            list_tiles(region.iter(), format, &hosts, &keys, &urls)?;
+        } else {
+            list_tiles(tiles.iter().copied(), format, &hosts, &keys, &urls)?;
+        }
+        return Ok(RunOutcome::Complete);
+    }
+    if args.dry_run {
+        if args.diff {
+            let policy = args.overwrite_policy;
+            if lazy {
+                diff_existing(region.iter(), &paths, policy, args.conditional, refresh_age);
+            } else {
+                diff_existing(tiles.iter().copied(), &paths, policy, args.conditional, refresh_age);
+            }
+        }
+        if let Some(n) = args.sample {
+            let sample = SampleRequest {
+                client: &client,
+                keys: &keys,
+                hosts: &hosts,
+                urls: &urls,
+                timeout: request_timeout,
+            };
+            let in_flight = args.target_tps.map_or(args.concurrency as f64, |tps| {
+                tps.min(args.concurrency as f64)
+            });
+            sample.estimate(&tiles, n, in_flight).await;
+        }
+        println!("Dry run: {} tiles would be downloaded; nothing was written.", total);
+        return Ok(RunOutcome::Complete);
+    }
+
+    if args.validate_only {
+        return if lazy {
+            validate_coverage(region.iter(), &paths, args.missing_file.as_deref())
+        } else {
+            validate_coverage(tiles, &paths, args.missing_file.as_deref())
+        };
+    }
+
+    // Read once up front: with --batch-size the file is rewritten as the run goes.
+    let previous_manifest = match &args.manifest {
+        Some(path) if args.manifest_append && path.exists() => {
+            let previous = Manifest::read(path)?;
+            if previous.form != args.form.as_str() {
+                return Err(anyhow!(
+                    "--manifest-append: {} lists {} tiles, but this run downloads {}",
+                    path.display(),
+                    previous.form,
+                    args.form.as_str()
+                ));
+            }
+            Some(previous)
+        }
+        _ => None,
+    };
+    let (removed, finalized) = clean_part_files(&args.out, args.resume_parts)?;
+    if removed + finalized > 0 {
+        println!(
+            "Leftover .part files: {} removed, {} finalized",
+            removed, finalized
+        );
+    }
+
+    // One bar per zoom level, stacked. Error output goes through `mp.suspend`
+    // so it doesn't interleave with the bars.
+    let mp = MultiProgress::with_draw_target(progress::draw_target(progress_interval));
+    let bars: HashMap<u32, ProgressBar> = per_zoom
+        .iter()
+        .map(|(z, _, count)| {
+            let pb = mp.add(ProgressBar::new(*count as u64));
+            pb.set_style(tile_style(multi_zoom));
+            pb.set_prefix(z.to_string());
+            (*z, pb)
+        })
+        .collect();
+    let plain = if progress::interactive() {
+        None
+    } else {
+        mp.set_draw_target(ProgressDrawTarget::hidden());
+        Some(PlainProgress::start(
+            "Downloaded",
+            bars.values().cloned().collect(),
+            progress_interval.unwrap_or(PLAIN_INTERVAL),
+        ))
+    };
+
+    let stats = Arc::new(RunStats::default());
+    let mut saved = Vec::new();
+    let csv = match &args.csv {
+        Some(path) => Some(TileCsv::create(path)?),
+        None => None,
+    };
+    let mut timings = match &args.timings_csv {
+        Some(path) => Some(TimingsCsv::create(path)?),
+        None => None,
+    };
+    let mut run_log = match &args.log_file {
+        Some(path) => Some(RunLog::open(path)?),
+        None => None,
+    };
+    if let Some(per_host) = args.warmup {
+        let started = Instant::now();
+        let opened = warm_up(&client, &hosts, per_host).await;
+        stats.requests.fetch_add(hosts.len() * per_host, Ordering::Relaxed);
+        mp.suspend(|| {
+            println!(
+                "Warmup: {}/{} requests to {} host(s) in {} ms",
+                opened,
+                hosts.len() * per_host,
+                hosts.len(),
+                started.elapsed().as_millis()
+            )
+        });
+    }
+    let run_started = Instant::now();
+    let started_at = utc_timestamp();
+    if let Some(log) = &mut run_log {
+        let zoom_list: Vec<String> = zooms.iter().map(u32::to_string).collect();
+        log.event(
+            "start",
+            &format!(
+                "bbox={:.6},{:.6},{:.6},{:.6} zoom={} tiles={} out={:?}",
+                lat1,
+                lon1,
+                lat2,
+                lon2,
+                zoom_list.join(","),
+                total,
+                args.out.display().to_string()
+            ),
+        );
+    }
+    let new_dirs = args.trim_empty_dirs.then(|| NewDirs::new(&args.out));
+    let worker = Arc::new(Worker {
+        client,
+        keys,
+        hosts,
+        throttle,
+        tps: tps.clone(),
+        rate_limit,
+        fetch: FetchOptions {
+            conditional: args.conditional,
+            max_bytes: (args.max_tile_bytes > 0).then_some(args.max_tile_bytes),
+            policy: args.overwrite_policy,
+            keep_failed: args.keep_failed_partials,
+            check_content_type: !args.no_content_type_check,
+            gzip: args.gzip,
+            rename_retries: args.rename_retries,
+        },
+        refresh_age,
+        paths,
+        retries: args.retries,
+        retry_on,
+        backoff,
+        verbose: args.verbose,
+        new_dirs,
+        urls,
+        io: Semaphore::new(args.io_concurrency),
+        stats: stats.clone(),
+        mp: mp.clone(),
+    });
+
+    // Work stream with bounded concurrency. Results are handled here, in
+    // completion order, so the progress bars, CSV and caller all see the
+    // same per-tile events.
+    let mut histogram: HashMap<String, usize> = HashMap::new();
+    let mut requested = 0usize;
+    let mut forbidden = 0usize;
+    let mut downloaded_bytes = 0u64;
+    #[cfg(feature = "heatmap")]
+    let mut coverage: Option<HashMap<(i32, i32, u32), TileStatus>> = args.heatmap.as_ref().map(|_| HashMap::new());
+    let manifest_tiles = |saved: &mut Vec<SavedTile>, manifest_path: &Path| -> Vec<ManifestTile> {
+        let base = manifest_path.parent().unwrap_or(Path::new("."));
+        saved.sort();
+        saved
+            .iter()
+            .map(|t| ManifestTile {
+                z: t.z,
+                x: t.x,
+                y: t.y,
+                quadkey: tile_xy_to_quadkey(t.x, t.y, t.z),
+                path: relative_uri(&t.path, base),
+                bytes: t.bytes,
+                compressed_bytes: t.compressed_bytes,
+            })
+            .collect()
+    };
+    let manifest_of = |tiles: Vec<ManifestTile>| Manifest {
+        bbox: bbox_wsen(lat1, lon1, lat2, lon2),
+        zooms: zooms.clone(),
+        form: args.form.as_str().to_string(),
+        created: utc_timestamp(),
+        tiles,
+        runs: Vec::new(),
+    };
+    // Every saved tile is only kept for the post-steps that need the full
+    // list. With --batch-size, the manifest instead gets each batch's tiles
+    // appended as the batch finishes; with --manifest-append, in a .part file
+    // beside it, so an interrupted run leaves the previous manifest intact.
+    let keep_saved = args.dedupe_identical
+        || args.merge.is_some()
+        || args.tileset.is_some()
+        || args.preview.is_some()
+        || (args.manifest.is_some() && args.batch_size.is_none());
+    let mut manifest_writer = match (&args.manifest, args.batch_size) {
+        (Some(path), Some(_)) => {
+            let path = if previous_manifest.is_some() { part_path(path) } else { path.clone() };
+            Some(ManifestWriter::create(&path, &manifest_of(Vec::new()))?)
+        }
+        _ => None,
+    };
+    let mut batch_saved = Vec::new();
+    let summary_of = |result: &str, histogram: &HashMap<String, usize>, bytes: u64| {
+        let saved = stats.saved.load(Ordering::Relaxed);
+        let skipped = stats.kept.load(Ordering::Relaxed) + stats.unchanged.load(Ordering::Relaxed);
+        let empty = histogram.get("empty").copied().unwrap_or(0);
+        let settled: usize = histogram.values().sum();
+        RunSummary {
+            result: result.to_string(),
+            started: started_at.clone(),
+            finished: utc_timestamp(),
+            duration_secs: run_started.elapsed().as_secs_f64(),
+            bbox: bbox_wsen(lat1, lon1, lat2, lon2),
+            zooms: zooms.clone(),
+            form: args.form.as_str().to_string(),
+            concurrency: args.concurrency,
+            hosts: worker.hosts.hosts.clone(),
+            api_keys: worker.keys.keys.iter().map(|k| redact_key(k)).collect(),
+            out: args.out.display().to_string(),
+            tiles: total,
+            saved,
+            skipped,
+            empty,
+            failed: settled.saturating_sub(saved + skipped + empty),
+            statuses: histogram.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            bytes,
+        }
+    };
+    let mut pending: Box<dyn Iterator<Item = (i32, i32, u32)> + Send + '_> = if lazy {
+        Box::new(region.iter())
+    } else {
+        Box::new(tiles.into_iter())
+    };
+    let mut pass = 0;
+    loop {
+        let mut attempted = 0;
+        let mut failed = Vec::new();
+        // Without --batch-size the whole pass is one batch.
+        loop {
+            let batch: Vec<_> = pending.by_ref().take(args.batch_size.unwrap_or(usize::MAX)).collect();
+            if batch.is_empty() {
+                break;
+            }
+            attempted += batch.len();
+            let mut results = stream::iter(batch)
+                .map(|(x, y, z)| {
+                    let worker = worker.clone();
+                    async move { worker.run_tile(x, y, z).await }
+                })
+                .buffer_unordered(args.concurrency);
+            while let Some(result) = results.next().await {
+                if let Some(csv) = &csv {
+                    csv.record(&result);
+                }
+                if let Some(timings) = &mut timings {
+                    timings.record(&result);
+                }
+                bars[&result.z].inc(1);
+                if let Some(log) = &mut run_log
+                    && matches!(result.status, TileStatus::Empty | TileStatus::Http(_) | TileStatus::Error)
+                {
+                    log.event(
+                        "failure",
+                        &format!(
+                            "z={} x={} y={} quadkey={} status={} error={:?}",
+                            result.z,
+                            result.x,
+                            result.y,
+                            result.quadkey,
+                            result.status.label(),
+                            result.error.as_deref().unwrap_or("")
+                        ),
+                    );
+                }
+                *histogram.entry(result.status.label()).or_default() += 1;
+                if result.status != TileStatus::Kept {
+                    requested += 1;
+                    if result.status == TileStatus::Http(403) {
+                        forbidden += 1;
+                    }
+                    if requested == EARLY_FORBIDDEN_LIMIT && forbidden == requested {
+                        for pb in bars.values() {
+                            pb.finish_and_clear();
+                        }
+                        print_status_histogram(&histogram);
+                        if let Some(log) = &mut run_log {
+                            log.event(
+                                "end",
+                                &format!(
+                                    "result=aborted reason=forbidden saved={} failed={} duration={:.1}s",
+                                    stats.saved.load(Ordering::Relaxed),
+                                    requested - stats.saved.load(Ordering::Relaxed),
+                                    run_started.elapsed().as_secs_f64()
+                                ),
+                            );
+                        }
+                        if let Some(path) = &args.summary_json {
+                            summary_of("aborted", &histogram, downloaded_bytes).write(path)?;
+                        }
+                        return Err(DownloadError::Http {
+                            status: 403,
+                            requests: EARLY_FORBIDDEN_LIMIT,
+                        }
+                        .into());
+                    }
+                }
+                let disk_full = stats.disk_full.lock().unwrap().clone();
+                if let Some(error) = disk_full {
+                    for pb in bars.values() {
+                        pb.finish_and_clear();
+                    }
+                    print_status_histogram(&histogram);
+                    println!(
+                        "Aborted: Saved {}/{} tiles before the disk filled up",
+                        stats.saved.load(Ordering::Relaxed),
+                        total
+                    );
+                    if let Some(log) = &mut run_log {
+                        log.event(
+                            "end",
+                            &format!(
+                                "result=aborted reason=disk_full saved={} duration={:.1}s",
+                                stats.saved.load(Ordering::Relaxed),
+                                run_started.elapsed().as_secs_f64()
+                            ),
+                        );
+                    }
+                    if let Some(path) = &args.summary_json {
+                        // The summary may not fit either; the disk-full error matters more.
+                        summary_of("aborted", &histogram, downloaded_bytes).write(path).ok();
+                    }
+                    return Err(DownloadError::DiskFull { error }.into());
+                }
+                if result.status == TileStatus::Saved {
+                    downloaded_bytes += result.bytes;
+                }
+                if result.status == TileStatus::Saved
+                    && let Some(path) = &result.path
+                    && (keep_saved || manifest_writer.is_some())
+                {
+                    let tile = SavedTile {
+                        z: result.z,
+                        x: result.x,
+                        y: result.y,
+                        path: path.clone(),
+                        bytes: result.bytes,
+                        compressed_bytes: args.gzip.then(|| fs::metadata(path).map_or(0, |m| m.len())),
+                    };
+                    if manifest_writer.is_some() {
+                        batch_saved.push(tile.clone());
+                    }
+                    if keep_saved {
+                        saved.push(tile);
+                    }
+                }
+
+                #[cfg(feature = "heatmap")]
+                if let Some(coverage) = &mut coverage {
+                    coverage.insert((result.x, result.y, result.z), result.status);
+                }
+                if retryable_failure(result.status) {
+                    failed.push((result.x, result.y, result.z, result.status.label()));
+                }
+                on_tile(result);
+            }
+            drop(results);
+            if let (Some(writer), Some(manifest_path)) = (&mut manifest_writer, &args.manifest) {
+                writer.append(&manifest_tiles(&mut batch_saved, manifest_path))?;
+                batch_saved.clear();
+            }
+        }
+        if pass > 0 {
+            mp.suspend(|| {
+                println!(
+                    "Auto-retry pass {}: {} of {} tiles recovered",
+                    pass,
+                    attempted - failed.len(),
+                    attempted
+                )
+            });
+        }
+        if failed.is_empty() || pass >= args.auto_retry {
+            break;
+        }
+
+        pass += 1;
+        let pause = AUTO_RETRY_BASE_DELAY
+            .saturating_mul(1u32 << (pass - 1).min(16))
+            .min(AUTO_RETRY_MAX_DELAY);
+        mp.suspend(|| {
+            println!(
+                "Auto-retry pass {}/{}: retrying {} failed tiles in {}s",
+                pass,
+                args.auto_retry,
+                failed.len(),
+                pause.as_secs()
+            )
+        });
+        tokio::time::sleep(pause).await;
+        // The retried tiles count again once this pass settles them.
+        for (_, _, z, label) in &failed {
+            if let Some(count) = histogram.get_mut(label) {
+                *count -= 1;
+                if *count == 0 {
+                    histogram.remove(label);
+                }
+            }
+            let pb = &bars[z];
+            pb.set_position(pb.position().saturating_sub(1));
+        }
+        pending = Box::new(failed.into_iter().map(|(x, y, z, _)| (x, y, z)));
+    }
+
+    if let Some(plain) = plain {
+        plain.finish();
+    }
+    for pb in bars.values() {
+        pb.finish_and_clear();
+    }
+    let ok = stats.saved.load(Ordering::Relaxed);
+    println!("Done: Saved {}/{} tiles", ok, total);
+    let elapsed = run_started.elapsed().as_secs_f64();
+    println!(
+        "Time: {}, {:.1} tiles/s, {}/s",
+        format_duration(elapsed),
+        total as f64 / elapsed.max(1e-3),
+        format_bytes((downloaded_bytes as f64 / elapsed.max(1e-3)) as u64)
+    );
+    let kept = stats.kept.load(Ordering::Relaxed);
+    let unchanged = stats.unchanged.load(Ordering::Relaxed);
+    let done = ok + kept + unchanged;
+    if let Some(log) = &mut run_log {
+        log.event(
+            "end",
+            &format!(
+                "result={} saved={} kept={} unchanged={} failed={} duration={:.1}s",
+                if done == total { "ok" } else { "partial" },
+                ok,
+                kept,
+                unchanged,
+                total - done,
+                run_started.elapsed().as_secs_f64()
+            ),
+        );
+    }
+    print_status_histogram(&histogram);
+    // Written before the post-run steps so a failing merge or export still
+    // leaves the summary behind.
+    if let Some(path) = &args.summary_json {
+        let result = if done == total {
+            "complete"
+        } else if done == 0 {
+            "failed"
+        } else {
+            "partial"
+        };
+        summary_of(result, &histogram, downloaded_bytes).write(path)?;
+        println!("Wrote summary {}", path.display());
+    }
+    if forbidden > 0 {
+        eprintln!(
+            "WARNING: {} request(s) returned HTTP 403; check that the API key is valid and within quota",
+            forbidden
+        );
+    }
+    if args.verbose || args.no_pool {
+        let requests = stats.requests.load(Ordering::Relaxed);
+        let opened = connections.0.load(Ordering::Relaxed);
+        println!(
+            "Connections: {} opened for {} tile requests ({} reused{})",
+            opened,
+            requests,
+            requests.saturating_sub(opened),
+            if args.no_pool { ", --no-pool" } else { "" }
+        );
+    }
+    if let Some(tps) = &tps {
+        let (permits, avg) = tps.summary();
+        println!(
+            "Adaptive concurrency: settled at {} in flight, average latency {:.0} ms",
+            permits,
+            avg.unwrap_or(0.0) * 1000.0
+        );
+    }
+    if refresh_age.is_some() {
+        println!(
+            "Refresh: {} refreshed, {} kept (still fresh)",
+            stats.refreshed.load(Ordering::Relaxed),
+            stats.kept.load(Ordering::Relaxed)
+        );
+    } else if kept > 0 {
+        println!(
+            "Existing: {} tiles kept (--overwrite-policy {})",
+            kept,
+            args.overwrite_policy.as_str()
+        );
+    }
+    if args.conditional {
+        println!(
+            "Conditional: {} unchanged (HTTP 304), {} downloaded",
+            stats.unchanged.load(Ordering::Relaxed),
+            ok
+        );
+    }
+
+    if let Some(dirs) = &worker.new_dirs {
+        let trimmed = dirs.trim();
+        if trimmed > 0 {
+            println!("Removed {} empty directories", trimmed);
+        }
+    }
+
+    if let Some(quadkey) = overview.as_deref().filter(|q| !q.is_empty()) {
+        let (x, y, z) = quadkey_to_tile_xy(quadkey).unwrap_or_default();
+        let out_path = TilePaths {
+            out_dir: args.out.join("overview"),
+            grid_size: 1,
+            qk_prefix_depth: 0,
+            zoom_dirs: false,
+            gzip: args.gzip,
+            flip_y: args.flip_y,
+            extension: args.form.extension(),
+        }
+        .path(x, y, z, quadkey);
+        let host = worker.hosts.get(worker.hosts.pick());
+        let url = worker.urls.render(host, (x, y, z), quadkey, worker.keys.key(worker.keys.pick()));
+        let log_url = worker.keys.mask(&url);
+        let fetch = FetchOptions { policy: OverwritePolicy::Overwrite, ..worker.fetch };
+        match download_one(&worker.client, &url, &log_url, &out_path, fetch, &worker.io, &|_, _| {}).await {
+            Ok(TileOutcome::Saved(bytes)) => {
+                println!("Overview: saved {} ({})", out_path.display(), format_bytes(bytes))
+            }
+            Ok(outcome) => eprintln!("WARNING: overview tile {} not saved: {}", quadkey, outcome.label()),
+            Err(e) => eprintln!("WARNING: overview tile {} not saved: {}", quadkey, worker.keys.mask(&format!("{:#}", e))),
+        }
+    }
+    if args.dedupe_identical && !saved.is_empty() {
+        let paths: Vec<PathBuf> = saved.iter().map(|t| t.path.clone()).collect();
+        let report = dedupe_identical(&paths)?;
+        println!(
+            "Dedupe: linked {} identical tiles, saving {}",
+            report.linked,
+            format_bytes(report.bytes_saved)
+        );
+        if let Some(reason) = report.unsupported {
+            eprintln!(
+                "WARNING: the filesystem does not support links ({}); remaining duplicates were left in place",
+                reason
+            );
+        }
+    }
+
+    if let Some(merge_path) = &args.merge {
+        saved.sort();
+        if saved.is_empty() {
+            println!("Nothing to merge.");
+        } else {
+            write_merged(&merge_inputs(&saved), merge_path)?;
+            println!("Merged {} tiles into {}", saved.len(), merge_path.display());
+        }
+    }
+
+    if let Some(tileset_dir) = &args.tileset {
+        let entries: Vec<TilesetEntry> = saved
+            .iter()
+            .map(|t| TilesetEntry {
+                quadkey: tile_xy_to_quadkey(t.x, t.y, t.z),
+                uri: relative_uri(&t.path, tileset_dir),
+            })
+            .collect();
+        if entries.is_empty() {
+            println!("No tiles saved; skipping tileset.json.");
+        } else {
+            let tileset_path = tileset_dir.join("tileset.json");
+            write_tileset(&entries, quadkey_bounds, &tileset_path)?;
+            println!("Wrote {} ({} tiles)", tileset_path.display(), entries.len());
+        }
+    }
+
+    if let Some(preview_path) = &args.preview {
+        let base = preview_path.parent().unwrap_or(Path::new("."));
+        saved.sort();
+        let entries: Vec<PreviewEntry> = saved
+            .iter()
+            .map(|t| PreviewEntry {
+                name: format!("{}_{}_{}", t.z, t.x, t.y),
+                quadkey: tile_xy_to_quadkey(t.x, t.y, t.z),
+                uri: relative_uri(&t.path, base),
+                bounds: tile_bounds(t.x, t.y, t.z),
+            })
+            .collect();
+        write_preview(&entries, preview_path)?;
+        println!("Wrote preview {} ({} tiles)", preview_path.display(), entries.len());
+    }
+
+    #[cfg(feature = "heatmap")]
+    if let (Some(path), Some(coverage)) = (&args.heatmap, coverage) {
+        let cells: Vec<_> = coverage.into_iter().map(|((x, y, z), status)| (x, y, z, status)).collect();
+        let ranges: Vec<_> = per_zoom.iter().map(|(z, ranges, _)| (*z, ranges.clone())).collect();
+        // The heatmap is only a picture of the run; failing to draw it must
+        // not fail a finished download or keep the manifest from being written.
+        match crate::heatmap::write_heatmap(&cells, &ranges, path) {
+            Ok(()) => println!("Wrote heatmap {} ({} tiles)", path.display(), cells.len()),
+            Err(e) => eprintln!("WARNING: heatmap not written: {:#}", e),
+        }
+    }
+
+    if let Some(manifest_path) = &args.manifest {
+        // A batched manifest is already complete on disk; --manifest-append
+        // merges it into the previous one once, at the end.
+        let (tiles, runs) = match (&manifest_writer, &previous_manifest) {
+            (Some(writer), None) => (writer.tiles(), 0),
+            (writer, previous) => {
+                let manifest = match writer {
+                    Some(_) => Manifest::read(&part_path(manifest_path))?,
+                    None => manifest_of(manifest_tiles(&mut saved, manifest_path)),
+                };
+                let manifest = match previous {
+                    Some(previous) => manifest.append_to(previous.clone()),
+                    None => manifest,
+                };
+                manifest.write(manifest_path)?;
+                if writer.is_some() {
+                    fs::remove_file(part_path(manifest_path)).ok();
+                }
+                (manifest.tiles.len(), manifest.runs.len())
+            }
+        };
+        if runs == 0 {
+            println!("Wrote manifest {} ({} tiles)", manifest_path.display(), tiles);
+        } else {
+            println!("Wrote manifest {} ({} tiles from {} runs)", manifest_path.display(), tiles, runs);
+        }
+    }
+
+    if let Some(tms_path) = &args.tms_metadata {
+        // Ranges split at the antimeridian are reported as the full matrix
+        // width, since each tile matrix gets a single column range.
+        let limits: Vec<MatrixLimits> = per_zoom
+            .iter()
+            .map(|(z, ranges, _)| {
+                let wraps = ranges.len() > 1;
+                MatrixLimits {
+                    z: *z,
+                    min_col: if wraps { 0 } else { ranges[0].0 },
+                    max_col: if wraps { (1i32 << z) - 1 } else { ranges[0].1 },
+                    min_row: ranges.iter().map(|r| r.2).min().unwrap_or(0),
+                    max_row: ranges.iter().map(|r| r.3).max().unwrap_or(0),
+                }
+            })
+            .collect();
+        write_tms_metadata(bbox_wsen(lat1, lon1, lat2, lon2), &limits, tms_path)?;
+        println!("Wrote TileMatrixSet metadata {} ({} zoom levels)", tms_path.display(), limits.len());
+    }
+
+    Ok(if done == total {
+        RunOutcome::Complete
+    } else if done == 0 {
+        RunOutcome::Failed
+    } else {
+        RunOutcome::Partial { failed: total - done }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh, empty directory under the system temp dir for one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bing-test-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Serves `body` once on a local port, without `Content-Encoding` or
+    /// `Content-Length` headers (so it is read until the connection closes),
+    /// and returns its URL.
+    fn serve_once(body: Vec<u8>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/tile.glb", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while request.read_line(&mut line).is_ok_and(|n| n > 2) {
+                line.clear();
+            }
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: model/gltf-binary\r\nConnection: close\r\n\r\n";
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        url
+    }
+
+    fn fetch_options(gzip: bool) -> FetchOptions {
+        FetchOptions {
+            conditional: false,
+            max_bytes: None,
+            policy: OverwritePolicy::Overwrite,
+            keep_failed: false,
+            check_content_type: false,
+            gzip,
+            rename_retries: 0,
+        }
+    }
+
+    fn gzipped(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A small GLB-looking body: the `glTF` magic and some payload.
+    fn tile_body() -> Vec<u8> {
+        let mut body = b"glTF".to_vec();
+        body.extend((0..5000u32).map(|i| (i * 7 % 251) as u8));
+        body
+    }
+
+    #[test]
+    fn part_path_appends_to_the_full_file_name() {
+        assert_eq!(part_path(Path::new("out/foo.glb")), PathBuf::from("out/foo.glb.part"));
+        assert_eq!(part_path(Path::new("out/foo")), PathBuf::from("out/foo.part"));
+    }
+
+    #[test]
+    fn writers_sharing_a_directory_keep_separate_part_files() {
+        // Replacing the extension would have given both outputs `foo.part`.
+        let dir = scratch_dir("part-path");
+        let outputs = [dir.join("foo.glb"), dir.join("foo")];
+        std::thread::scope(|scope| {
+            for (i, out) in outputs.iter().enumerate() {
+                scope.spawn(move || {
+                    let tmp = part_path(out);
+                    for _ in 0..50 {
+                        fs::write(&tmp, vec![i as u8; 4096]).unwrap();
+                    }
+                    fs::rename(&tmp, out).unwrap();
+                });
+            }
+        });
+        for (i, out) in outputs.iter().enumerate() {
+            assert_eq!(fs::read(out).unwrap(), vec![i as u8; 4096]);
+            assert!(!part_path(out).exists());
+        }
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn decode_body_inflates_gzip_and_zlib() {
+        let body = tile_body();
+        assert!(is_compressed(&gzipped(&body)));
+        assert_eq!(decode_body(&gzipped(&body)).unwrap().as_ref(), &body[..]);
+
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&body).unwrap();
+        let zlib = zlib.finish().unwrap();
+        assert!(is_compressed(&zlib));
+        assert_eq!(decode_body(&zlib).unwrap().as_ref(), &body[..]);
+
+        // A plain GLB is left alone.
+        assert!(!is_compressed(&body));
+        assert!(matches!(decode_body(&body).unwrap(), Cow::Borrowed(_)));
+    }
+
+    #[tokio::test]
+    async fn gzip_body_without_content_encoding_is_written_decoded() {
+        let dir = scratch_dir("gzip-body");
+        let body = tile_body();
+        let out = dir.join("1_0_0.glb");
+        let url = serve_once(gzipped(&body));
+        let outcome = download_one(
+            &reqwest::Client::new(),
+            &url,
+            &url,
+            &out,
+            fetch_options(false),
+            &Semaphore::new(1),
+            &|_, _| {},
+        )
+        .await
+        .unwrap();
+        assert!(matches!(outcome, TileOutcome::Saved(n) if n == body.len() as u64));
+        assert_eq!(fs::read(&out).unwrap(), body);
+        assert!(!part_path(&out).exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    async fn fetch_to(out: &Path, served: Vec<u8>, fetch: FetchOptions) -> TileOutcome {
+        let url = serve_once(served);
+        download_one(&reqwest::Client::new(), &url, &url, out, fetch, &Semaphore::new(1), &|_, _| {})
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn gzip_option_stores_the_streamed_body_compressed() {
+        let dir = scratch_dir("gzip-option");
+        let body = tile_body();
+        for (name, served) in [("plain.glb", body.clone()), ("wrapped.glb", gzipped(&body))] {
+            let out = dir.join(name);
+            let outcome = fetch_to(&out, served, fetch_options(true)).await;
+            assert!(matches!(outcome, TileOutcome::Saved(n) if n == body.len() as u64), "{}", name);
+            let stored = fs::read(&out).unwrap();
+            assert!(stored.starts_with(&[0x1f, 0x8b]), "{}", name);
+            assert_eq!(decode_body(&stored).unwrap().as_ref(), &body[..], "{}", name);
+            assert!(!part_path(&out).exists());
+        }
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn oversized_streamed_body_is_cut_off_and_kept_as_failed() {
+        let dir = scratch_dir("too-large");
+        let body = tile_body();
+        let out = dir.join("1_0_0.glb");
+        let fetch = FetchOptions { max_bytes: Some(1000), keep_failed: true, ..fetch_options(true) };
+        let outcome = fetch_to(&out, body.clone(), fetch).await;
+        assert!(matches!(outcome, TileOutcome::TooLarge));
+        assert!(!out.exists());
+        assert!(!part_path(&out).exists());
+        // The evidence is the body as received, not the --gzip encoding.
+        let failed = fs::read(failed_path(&out)).unwrap();
+        assert!(failed.len() > 1000 && body.starts_with(&failed));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn if_larger_keeps_a_bigger_existing_tile() {
+        let dir = scratch_dir("if-larger");
+        let out = dir.join("1_0_0.glb");
+        fs::write(&out, vec![7u8; 10_000]).unwrap();
+        let fetch = FetchOptions { policy: OverwritePolicy::IfLarger, ..fetch_options(false) };
+        assert!(matches!(fetch_to(&out, tile_body(), fetch).await, TileOutcome::KeptExisting));
+        assert_eq!(fs::read(&out).unwrap(), vec![7u8; 10_000]);
+        assert!(!part_path(&out).exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn bbox_format_keeps_an_antimeridian_box_west_first() {
+        assert_eq!(
+            BboxFormat::Lonlat.format(-10.0, 170.0, 10.0, -170.0),
+            "170.000000,-10.000000,-170.000000,10.000000 (min_lon,min_lat,max_lon,max_lat)"
+        );
+        assert_eq!(
+            BboxFormat::Latlon.format(10.0, 1.0, -10.0, 2.0),
+            "-10.000000,1.000000,10.000000,2.000000 (min_lat,min_lon,max_lat,max_lon)"
+        );
+    }
+
+    #[test]
+    fn flip_y_changes_the_file_row_but_not_the_quadkey() {
+        let paths = |flip_y| TilePaths {
+            out_dir: PathBuf::from("out"),
+            grid_size: 1,
+            qk_prefix_depth: 3,
+            zoom_dirs: false,
+            gzip: false,
+            flip_y,
+            extension: "glb",
+        };
+        let (x, y, z) = (2, 1, 3);
+        let quadkey = tile_xy_to_quadkey(x, y, z);
+        assert_eq!(quadkey, "012");
+        // The row in the name is TMS (7 - 1); the quadkey folder stays XYZ.
+        assert_eq!(paths(true).path(x, y, z, &quadkey), PathBuf::from("out/012/3_2_6.glb"));
+        assert_eq!(paths(false).path(x, y, z, &quadkey), PathBuf::from("out/012/3_2_1.glb"));
+        assert_ne!(tile_xy_to_quadkey(x, flip_row(y, z), z), quadkey);
+    }
+
+    #[test]
+    fn backoff_doubles_to_the_cap_and_seeded_jitter_repeats() {
+        let backoff = Backoff::new(50, 120, None).unwrap();
+        let nominal: Vec<u128> = (1..=4).map(|attempt| backoff.nominal(attempt).as_millis()).collect();
+        assert_eq!(nominal, [50, 100, 120, 120]);
+        assert_eq!(backoff.delay(2), Duration::from_millis(100));
+        assert_eq!(backoff.schedule(3), "50ms, 100ms, 120ms");
+        assert_eq!(backoff.schedule(10), "50ms, 100ms, 120ms, 120ms, 120ms, 120ms, 120ms, 120ms, ...");
+        // A huge attempt number saturates instead of overflowing the shift.
+        assert_eq!(backoff.nominal(u32::MAX), Duration::from_millis(120));
+
+        let delays = |seed| {
+            let backoff = Backoff::new(1_000, 8_000, Some(seed)).unwrap();
+            (1..=6).map(|attempt| backoff.delay(attempt)).collect::<Vec<_>>()
+        };
+        let first = delays(7);
+        assert_eq!(first, delays(7));
+        assert_ne!(first, delays(8));
+        let backoff = Backoff::new(1_000, 8_000, Some(7)).unwrap();
+        for (attempt, delay) in (1..).zip(&first) {
+            let nominal = backoff.nominal(attempt);
+            assert!(*delay >= nominal / 2 && *delay <= nominal, "retry {}: {:?} of {:?}", attempt, delay, nominal);
+        }
+        // Jitter never changes the schedule shown in the plan.
+        assert_eq!(backoff.schedule(4), "1000ms, 2000ms, 4000ms, 8000ms");
+
+        assert!(Backoff::new(0, 10, None).is_err());
+        assert!(Backoff::new(100, 50, None).is_err());
+    }
+
+    /// Serves HTTP 503 to the first `failures` requests and `body` to the
+    /// next, one connection each. The stub runs on the test's runtime, so the
+    /// arrival times it returns are on the (paused) tokio clock.
+    async fn serve_503_then(failures: usize, body: Vec<u8>) -> (String, tokio::task::JoinHandle<Vec<tokio::time::Instant>>) {
+        use tokio::io::AsyncBufReadExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut arrivals = Vec::new();
+            for i in 0..=failures {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut request = tokio::io::BufReader::new(stream);
+                let mut line = String::new();
+                while request.read_line(&mut line).await.is_ok_and(|n| n > 2) {
+                    line.clear();
+                }
+                arrivals.push(tokio::time::Instant::now());
+                let stream = request.get_mut();
+                if i < failures {
+                    let head = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+                    stream.write_all(head.as_bytes()).await.unwrap();
+                } else {
+                    let head = "HTTP/1.1 200 OK\r\nContent-Type: model/gltf-binary\r\nConnection: close\r\n\r\n";
+                    stream.write_all(head.as_bytes()).await.unwrap();
+                    stream.write_all(&body).await.unwrap();
+                }
+                stream.shutdown().await.ok();
+            }
+            arrivals
+        });
+        (host, server)
+    }
+
+    /// A worker with one host and key that retries 503s `retries` times. Its
+    /// client has no timeout: while any timer is pending, a paused clock
+    /// jumps ahead each time the runtime waits on the stub's socket.
+    fn retrying_worker(host: String, out_dir: PathBuf, retries: u32, backoff: Backoff) -> Worker {
+        Worker {
+            client: reqwest::Client::new(),
+            keys: KeyPool::new(vec!["test-key".to_string()]).unwrap(),
+            hosts: HostPool::new(vec![host]).unwrap(),
+            throttle: Throttle::new(0, Duration::from_secs(60), Duration::from_secs(60)),
+            tps: None,
+            rate_limit: None,
+            fetch: fetch_options(false),
+            refresh_age: None,
+            paths: TilePaths {
+                out_dir,
+                grid_size: 1,
+                qk_prefix_depth: 0,
+                zoom_dirs: false,
+                gzip: false,
+                flip_y: false,
+                extension: "glb",
+            },
+            retries,
+            retry_on: vec![503],
+            backoff,
+            verbose: false,
+            new_dirs: None,
+            urls: UrlTemplate::new("{host}/{quadkey}.glb?key={key}", TileForm::Web3d).unwrap(),
+            io: Semaphore::new(1),
+            stats: Arc::default(),
+            mp: MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_wait_out_the_backoff_between_attempts() {
+        const FAILURES: usize = 4;
+        for (name, seed) in [("no-jitter", None), ("jitter", Some(7))] {
+            let (host, server) = serve_503_then(FAILURES, tile_body()).await;
+            let dir = scratch_dir(&format!("retry-backoff-{}", name));
+            let worker = retrying_worker(host, dir.clone(), FAILURES as u32, Backoff::new(200, 1_000, seed).unwrap());
+            let result = worker.run_tile(1, 2, 3).await;
+            assert_eq!(result.status, TileStatus::Saved, "{}: {:?}", name, result.error);
+            assert_eq!(result.retries, FAILURES as u32);
+
+            // An identically seeded backoff draws the same jittered pauses.
+            // Tokio timers fire on whole milliseconds, so a sleep may run up
+            // to one tick past its delay.
+            let expected = Backoff::new(200, 1_000, seed).unwrap();
+            let arrivals = server.await.unwrap();
+            assert_eq!(arrivals.len(), FAILURES + 1);
+            for (attempt, pair) in (1..).zip(arrivals.windows(2)) {
+                let (waited, delay) = (pair[1] - pair[0], expected.delay(attempt));
+                assert!(
+                    waited >= delay && waited < delay + Duration::from_millis(1),
+                    "{}: retry {} waited {:?}, backoff {:?}",
+                    name,
+                    attempt,
+                    waited,
+                    delay
+                );
+            }
+            if seed.is_none() {
+                let waits: Vec<u128> = arrivals.windows(2).map(|p| (p[1] - p[0]).as_millis()).collect();
+                assert_eq!(waits, [200, 400, 800, 1_000]);
+            }
+            fs::remove_dir_all(&dir).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn move_into_place_retries_a_failed_rename() {
+        let dir = scratch_dir("move-retry");
+        let (tmp, out) = (dir.join("tile.glb.part"), dir.join("tile.glb"));
+        fs::write(&tmp, b"new tile").unwrap();
+        // A non-empty directory in the way makes the first rename fail until
+        // it is cleared, as a scanner holding the file would.
+        fs::create_dir(&out).unwrap();
+        fs::write(out.join("held"), b"").unwrap();
+        let blocker = out.clone();
+        let clear = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            fs::remove_dir_all(blocker).unwrap();
+        });
+        move_into_place(&tmp, &out, 5).await.unwrap();
+        clear.await.unwrap();
+        assert_eq!(fs::read(&out).unwrap(), b"new tile");
+        assert!(!tmp.exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn tile_extent_splits_tiles_either_side_of_the_antimeridian() {
+        assert_eq!(tile_extent(&[(15, 0), (0, 2)], 4), [(15, 15, 0, 2), (0, 0, 0, 2)]);
+        assert_eq!(tile_extent(&[(7, 3), (9, 1)], 4), [(7, 9, 1, 3)]);
+        assert_eq!(tile_extent(&[], 4), []);
+    }
+}
@@ -0,0 +1,66 @@
+use std::error::Error as StdError;
+use std::io;
+use thiserror::Error;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Why [`run_download`](crate::download::run_download) stopped, for library
+/// callers that need to tell a rejected API key from a network or disk
+/// problem. Each variant displays the same message, with the same causes, as
+/// the CLI prints.
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    /// The first `requests` requests all returned `status`, e.g. 403 for an
+    /// invalid or over-quota API key
+    #[error("API key appears invalid or over quota: the first {requests} requests all returned HTTP {status}")]
+    Http { status: u16, requests: usize },
+    /// A request or connection timed out
+    #[error(transparent)]
+    Timeout(BoxError),
+    /// The region is missing, malformed or outside the Web Mercator range
+    #[error(transparent)]
+    InvalidBbox(BoxError),
+    /// A tile write ran out of disk space or quota, so the run stopped
+    /// rather than fail every remaining tile
+    #[error("Disk full, download aborted: {error}")]
+    DiskFull { error: String },
+    /// Reading or writing a file failed
+    #[error(transparent)]
+    Io(BoxError),
+    /// Anything else, such as an invalid option value
+    #[error(transparent)]
+    Other(BoxError),
+}
+
+impl DownloadError {
+    pub(crate) fn invalid_bbox(e: impl Into<BoxError>) -> Self {
+        DownloadError::InvalidBbox(e.into())
+    }
+
+    /// Whether the server refused the API key (HTTP 401 or 403).
+    pub fn is_key_problem(&self) -> bool {
+        matches!(self, DownloadError::Http { status: 401 | 403, .. })
+    }
+}
+
+impl From<anyhow::Error> for DownloadError {
+    /// Recovers a `DownloadError` raised inside the run, or classifies the
+    /// error by the first timeout or IO error in its cause chain.
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<DownloadError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        let timeout = e.chain().any(|cause| {
+            cause.downcast_ref::<reqwest::Error>().is_some_and(reqwest::Error::is_timeout)
+                || cause.downcast_ref::<io::Error>().is_some_and(|io| io.kind() == io::ErrorKind::TimedOut)
+        });
+        if timeout {
+            DownloadError::Timeout(e.into())
+        } else if e.chain().any(|cause| cause.is::<io::Error>()) {
+            DownloadError::Io(e.into())
+        } else {
+            DownloadError::Other(e.into())
+        }
+    }
+}
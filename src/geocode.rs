@@ -0,0 +1,47 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::time::Duration;
+
+/// Bing Maps Locations API, queried with the download's API key.
+const LOCATIONS_URL: &str = "https://dev.virtualearth.net/REST/v1/Locations";
+
+/// Best match for a `--place` query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    pub lat: f64,
+    pub lon: f64,
+    /// Name of the match as Bing formats it, e.g. "Eiffel Tower, France"
+    pub name: String,
+}
+
+/// Geocodes `query` to the best-matching location.
+pub async fn locate(query: &str, key: &str, user_agent: &str, timeout: Duration) -> Result<Place> {
+    let client = reqwest::Client::builder()
+        .user_agent(user_agent)
+        .timeout(timeout)
+        .build()?;
+    // Drop the URL from errors: it carries the API key.
+    let resp = client
+        .get(LOCATIONS_URL)
+        .query(&[("query", query), ("maxResults", "1"), ("key", key)])
+        .send()
+        .await
+        .map_err(reqwest::Error::without_url)
+        .context("Geocoding request failed")?;
+    let status = resp.status();
+    let body = resp.text().await.map_err(reqwest::Error::without_url)?;
+    if !status.is_success() {
+        return Err(anyhow!("Geocoding {:?} failed: HTTP {}", query, status));
+    }
+    let doc: Value = serde_json::from_str(&body).context("Parsing geocoding response")?;
+    parse_place(&doc).ok_or_else(|| anyhow!("No location found for {:?}", query))
+}
+
+/// Reads the first resource of a Locations API response.
+fn parse_place(doc: &Value) -> Option<Place> {
+    let resource = doc["resourceSets"].get(0)?["resources"].get(0)?;
+    let coords = resource["point"]["coordinates"].as_array()?;
+    let (lat, lon) = (coords.first()?.as_f64()?, coords.get(1)?.as_f64()?);
+    let name = resource["name"].as_str().unwrap_or_default().to_string();
+    Some(Place { lat, lon, name })
+}
@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+const GLB_MAGIC: u32 = 0x4654_6C67; // "glTF"
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A; // "JSON"
+const CHUNK_BIN: u32 = 0x004E_4942; // "BIN\0"
+
+/// A binary glTF container split into its JSON document and BIN chunk.
+#[derive(Debug, Clone)]
+pub struct Glb {
+    pub json: Value,
+    pub bin: Vec<u8>,
+}
+
+impl Glb {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 12 {
+            return Err(anyhow!("File too short for a GLB header ({} bytes)", bytes.len()));
+        }
+        if read_u32(bytes, 0) != GLB_MAGIC {
+            return Err(anyhow!("Missing glTF magic"));
+        }
+        let version = read_u32(bytes, 4);
+        if version != GLB_VERSION {
+            return Err(anyhow!("Unsupported glTF container version {}", version));
+        }
+        let length = read_u32(bytes, 8) as usize;
+        if length > bytes.len() {
+            return Err(anyhow!(
+                "Header declares {} bytes but only {} are present",
+                length,
+                bytes.len()
+            ));
+        }
+
+        let mut json = None;
+        let mut bin = Vec::new();
+        let mut offset = 12;
+        while offset + 8 <= length {
+            let chunk_len = read_u32(bytes, offset) as usize;
+            let chunk_type = read_u32(bytes, offset + 4);
+            let start = offset + 8;
+            let end = start
+                .checked_add(chunk_len)
+                .filter(|&end| end <= length)
+                .ok_or_else(|| anyhow!("Chunk at offset {} overruns the file", offset))?;
+            match chunk_type {
+                CHUNK_JSON => {
+                    json = Some(
+                        serde_json::from_slice(&bytes[start..end])
+                            .context("Invalid JSON chunk")?,
+                    );
+                }
+                CHUNK_BIN => bin = bytes[start..end].to_vec(),
+                _ => {} // unknown chunks must be ignored per spec
+            }
+            offset = end;
+        }
+
+        let json = json.ok_or_else(|| anyhow!("Missing JSON chunk"))?;
+        Ok(Self { json, bin })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("Reading {:?}", path))?;
+        Self::parse(&bytes).with_context(|| format!("Parsing {:?}", path))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut json = serde_json::to_vec(&self.json)?;
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+        let mut bin = self.bin.clone();
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+
+        let mut total = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            total += 8 + bin.len();
+        }
+
+        let mut out = Vec::with_capacity(total);
+        out.extend_from_slice(&GLB_MAGIC.to_le_bytes());
+        out.extend_from_slice(&GLB_VERSION.to_le_bytes());
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+        out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        out.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+        out.extend_from_slice(&json);
+        if !bin.is_empty() {
+            out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            out.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+            out.extend_from_slice(&bin);
+        }
+        Ok(out)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes()?).with_context(|| format!("Writing {:?}", path))
+    }
+
+    /// Removes every image, texture and sampler along with the materials'
+    /// texture references, keeping geometry and material factors. Image data
+    /// stored in the BIN chunk is dropped and the remaining buffer views are
+    /// packed together again.
+    pub fn strip_textures(&mut self) -> Result<()> {
+        let Some(doc) = self.json.as_object_mut() else {
+            return Err(anyhow!("glTF document is not an object"));
+        };
+        let images = doc.remove("images").unwrap_or_default();
+        doc.remove("textures");
+        doc.remove("samplers");
+        if let Some(materials) = doc.get_mut("materials") {
+            drop_texture_infos(materials);
+        }
+        for key in ["extensionsUsed", "extensionsRequired"] {
+            if let Some(Value::Array(list)) = doc.get_mut(key) {
+                list.retain(|e| !e.as_str().is_some_and(|e| TEXTURE_EXTENSIONS.contains(&e)));
+                if list.is_empty() {
+                    doc.remove(key);
+                }
+            }
+        }
+
+        // Only views used by nothing but images can go. Views in an external
+        // buffer, or behind meshopt's buffer indirection, are left in place.
+        let mut dropped: HashSet<u64> = HashSet::new();
+        collect_buffer_views(&images, &mut dropped);
+        let mut in_use = HashSet::new();
+        collect_buffer_views(&Value::Object(doc.clone()), &mut in_use);
+        dropped.retain(|v| !in_use.contains(v));
+        let embedded = doc
+            .get("buffers")
+            .and_then(|b| b.get(0))
+            .is_some_and(|b| b.get("uri").is_none());
+        let meshopt = doc
+            .get("extensionsUsed")
+            .and_then(Value::as_array)
+            .is_some_and(|l| l.iter().any(|e| e == "EXT_meshopt_compression"));
+        if dropped.is_empty() || !embedded || meshopt {
+            return Ok(());
+        }
+
+        let views = doc
+            .get_mut("bufferViews")
+            .and_then(Value::as_array_mut)
+            .ok_or_else(|| anyhow!("Images refer to buffer views, but there are none"))?;
+        let mut bin = Vec::with_capacity(self.bin.len());
+        let mut remap = Vec::with_capacity(views.len());
+        let mut kept = Vec::with_capacity(views.len());
+        for (i, view) in views.drain(..).enumerate() {
+            if dropped.contains(&(i as u64)) {
+                remap.push(None);
+                continue;
+            }
+            let mut view = view;
+            if view["buffer"].as_u64().unwrap_or(0) == 0 {
+                let start = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+                let end = start + view["byteLength"].as_u64().unwrap_or(0) as usize;
+                let data = self
+                    .bin
+                    .get(start..end)
+                    .ok_or_else(|| anyhow!("Buffer view {} overruns the BIN chunk", i))?;
+                // Accessor components are at most 4 bytes wide.
+                while !bin.len().is_multiple_of(4) {
+                    bin.push(0);
+                }
+                view["byteOffset"] = json!(bin.len());
+                bin.extend_from_slice(data);
+            }
+            remap.push(Some(kept.len() as u64));
+            kept.push(view);
+        }
+        *views = kept;
+        for (key, value) in doc.iter_mut() {
+            if key != "bufferViews" {
+                remap_buffer_views(value, &remap);
+            }
+        }
+        if let Some(buffer) = doc.get_mut("buffers").and_then(|b| b.get_mut(0)) {
+            buffer["byteLength"] = json!(bin.len());
+        }
+        self.bin = bin;
+        Ok(())
+    }
+}
+
+/// Extensions that only describe textures, dropped by [`Glb::strip_textures`].
+const TEXTURE_EXTENSIONS: [&str; 5] = [
+    "KHR_texture_basisu",
+    "KHR_texture_transform",
+    "EXT_texture_webp",
+    "EXT_texture_avif",
+    "MSFT_texture_dds",
+];
+
+/// Removes every textureInfo (`baseColorTexture`, `normalTexture`, extension
+/// textures, ...) found anywhere inside a material.
+fn drop_texture_infos(v: &mut Value) {
+    match v {
+        Value::Object(map) => {
+            map.retain(|k, child| !(k.ends_with("Texture") && child.is_object()));
+            map.values_mut().for_each(drop_texture_infos);
+        }
+        Value::Array(items) => items.iter_mut().for_each(drop_texture_infos),
+        _ => {}
+    }
+}
+
+fn collect_buffer_views(v: &Value, out: &mut HashSet<u64>) {
+    match v {
+        Value::Object(map) => {
+            if let Some(view) = map.get("bufferView").and_then(Value::as_u64) {
+                out.insert(view);
+            }
+            map.values().for_each(|child| collect_buffer_views(child, out));
+        }
+        Value::Array(items) => items.iter().for_each(|item| collect_buffer_views(item, out)),
+        _ => {}
+    }
+}
+
+fn remap_buffer_views(v: &mut Value, remap: &[Option<u64>]) {
+    match v {
+        Value::Object(map) => {
+            remap_view_key(map, remap);
+            map.values_mut().for_each(|child| remap_buffer_views(child, remap));
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| remap_buffer_views(item, remap)),
+        _ => {}
+    }
+}
+
+fn remap_view_key(map: &mut Map<String, Value>, remap: &[Option<u64>]) {
+    if let Some(view) = map.get("bufferView").and_then(Value::as_u64)
+        && let Some(Some(new)) = remap.get(view as usize)
+    {
+        map.insert("bufferView".to_string(), json!(new));
+    }
+}
+
+/// Checks the 12-byte GLB header: glTF magic, container version 2, and a
+/// declared length matching the actual byte count.
+pub fn check_header(bytes: &[u8]) -> Result<()> {
+    if bytes.is_empty() {
+        return Err(anyhow!("File is empty"));
+    }
+    if bytes.len() < 12 {
+        return Err(anyhow!("File too short for a GLB header ({} bytes)", bytes.len()));
+    }
+    if read_u32(bytes, 0) != GLB_MAGIC {
+        return Err(anyhow!("Missing glTF magic"));
+    }
+    let version = read_u32(bytes, 4);
+    if version != GLB_VERSION {
+        return Err(anyhow!("Unsupported glTF container version {}", version));
+    }
+    let length = read_u32(bytes, 8) as usize;
+    if length != bytes.len() {
+        return Err(anyhow!(
+            "Header declares {} bytes but file has {}",
+            length,
+            bytes.len()
+        ));
+    }
+    Ok(())
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
@@ -0,0 +1,33 @@
+// Long-lived gltf-transform worker used by `bing decompress --persistent`.
+//
+// Loads the gltf-transform CLI once, then reads one JSON request per line on
+// stdin ({"args": ["ktxdecompress", "in.glb", "out.glb"]}) and answers each
+// with a single stdout line starting with \u0001bing. Anything else the CLI
+// prints is ignored by the caller.
+import { createInterface } from 'node:readline';
+import { pathToFileURL } from 'node:url';
+
+const { program, programReady } = await import(
+    pathToFileURL(process.env.BING_GLTF_TRANSFORM_MODULE).href
+);
+await programReady;
+
+const reply = (msg) => process.stdout.write('\u0001bing ' + JSON.stringify(msg) + '\n');
+
+// The CLI exits the process on fatal errors; turn that into a failed request.
+process.exit = (code) => {
+    throw new Error(`gltf-transform exited with code ${code}`);
+};
+
+for await (const line of createInterface({ input: process.stdin })) {
+    if (!line.trim()) continue;
+    try {
+        const { args } = JSON.parse(line);
+        process.exitCode = 0;
+        await program.run(args);
+        if (process.exitCode) throw new Error(`gltf-transform exited with code ${process.exitCode}`);
+        reply({ ok: true });
+    } catch (err) {
+        reply({ ok: false, error: String((err && err.message) || err) });
+    }
+}
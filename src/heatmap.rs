@@ -0,0 +1,178 @@
+use crate::download::TileStatus;
+use anyhow::{anyhow, Context, Result};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Target width or height of a zoom level's panel; cells are scaled up to
+/// reach it, between 1 and [`MAX_CELL_PX`] pixels.
+const TARGET_PANEL_PX: usize = 512;
+const MAX_CELL_PX: usize = 16;
+/// Transparent rows between the panels of different zoom levels.
+const PANEL_GAP_PX: usize = 8;
+/// Largest image side written; bigger grids are refused rather than
+/// allocating gigabytes.
+const MAX_IMAGE_PX: usize = 32_768;
+
+/// RGBA color of a tile status in the heatmap. Cells outside the requested
+/// tiles are transparent.
+pub fn status_color(status: TileStatus) -> [u8; 4] {
+    match status {
+        TileStatus::Saved => [46, 160, 67, 255],
+        TileStatus::Kept | TileStatus::Unchanged => [66, 133, 244, 255],
+        TileStatus::Empty | TileStatus::Http(404) => [170, 170, 170, 255],
+        TileStatus::Http(_) => [230, 120, 20, 255],
+        TileStatus::Error => [200, 30, 30, 255],
+    }
+}
+
+/// Writes a PNG with one cell per tile colored by [`status_color`], one
+/// panel per zoom level from lowest to highest, stacked top to bottom. Each
+/// panel spans the `(x_min, x_max, y_min, y_max)` tile ranges of its zoom in
+/// `ranges`, north up, with the ranges' columns side by side, so a request
+/// split at the antimeridian stays as narrow as the request itself. Zooms
+/// without ranges span the extent of their tiles, and tiles outside the
+/// ranges are left out.
+#[allow(clippy::type_complexity)]
+pub fn write_heatmap(
+    tiles: &[(i32, i32, u32, TileStatus)],
+    ranges: &[(u32, Vec<(i32, i32, i32, i32)>)],
+    path: &Path,
+) -> Result<()> {
+    let mut by_zoom: BTreeMap<u32, Vec<(i32, i32, TileStatus)>> = BTreeMap::new();
+    for &(x, y, z, status) in tiles {
+        by_zoom.entry(z).or_default().push((x, y, status));
+    }
+
+    // (ranges, their first panel column, min_y, columns, rows, cell size) per zoom
+    let panels: Vec<_> = by_zoom
+        .iter()
+        .map(|(z, cells)| {
+            let ranges = match ranges.iter().find(|(rz, r)| rz == z && !r.is_empty()) {
+                Some((_, r)) => r.clone(),
+                None => vec![(
+                    cells.iter().map(|c| c.0).min().unwrap_or(0),
+                    cells.iter().map(|c| c.0).max().unwrap_or(0),
+                    cells.iter().map(|c| c.1).min().unwrap_or(0),
+                    cells.iter().map(|c| c.1).max().unwrap_or(0),
+                )],
+            };
+            let mut firsts = Vec::with_capacity(ranges.len());
+            let mut cols = 0;
+            for r in &ranges {
+                firsts.push(cols);
+                cols += (r.1 - r.0) as usize + 1;
+            }
+            let min_y = ranges.iter().map(|r| r.2).min().unwrap_or(0);
+            let rows = (ranges.iter().map(|r| r.3).max().unwrap_or(0) - min_y) as usize + 1;
+            let cell = (TARGET_PANEL_PX / cols.max(rows)).clamp(1, MAX_CELL_PX);
+            (ranges, firsts, min_y, cols, rows, cell)
+        })
+        .collect();
+    let width = panels.iter().map(|p| p.3 * p.5).max().unwrap_or(1).max(1);
+    let height = panels.iter().map(|p| p.4 * p.5).sum::<usize>()
+        + PANEL_GAP_PX * panels.len().saturating_sub(1);
+    if width > MAX_IMAGE_PX || height > MAX_IMAGE_PX {
+        return Err(anyhow!(
+            "Heatmap would be {}x{} pixels, over the {} pixel limit",
+            width,
+            height,
+            MAX_IMAGE_PX
+        ));
+    }
+
+    let mut pixels = vec![0u8; width * height.max(1) * 4];
+    let mut top = 0;
+    for (cells, (ranges, firsts, min_y, _, rows, cell)) in by_zoom.values().zip(&panels) {
+        let (min_y, rows, cell) = (*min_y, *rows, *cell);
+        for &(x, y, status) in cells {
+            let Some(column) = ranges
+                .iter()
+                .zip(firsts)
+                .find(|(r, _)| (r.0..=r.1).contains(&x) && (r.2..=r.3).contains(&y))
+                .map(|(r, first)| first + (x - r.0) as usize)
+            else {
+                continue;
+            };
+            let color = status_color(status);
+            let (px, py) = (column * cell, top + (y - min_y) as usize * cell);
+            for row in py..py + cell {
+                for col in px..px + cell {
+                    let at = (row * width + col) * 4;
+                    pixels[at..at + 4].copy_from_slice(&color);
+                }
+            }
+        }
+        top += rows * cell + PANEL_GAP_PX;
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    let png = encode_png(width as u32, height.max(1) as u32, &pixels)?;
+    fs::write(path, png).with_context(|| format!("Writing heatmap {:?}", path))
+}
+
+/// Encodes 8-bit RGBA `pixels` as a PNG without filtering.
+fn encode_png(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<u8>> {
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for line in pixels.chunks(width as usize * 4) {
+        raw.push(0); // filter type: none
+        raw.extend_from_slice(line);
+    }
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(&raw)?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]); // 8-bit RGBA, deflate, no interlace
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"IDAT", &zlib.finish()?);
+    chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Width and height from a PNG's IHDR chunk.
+    fn png_size(png: &[u8]) -> (u32, u32) {
+        (u32::from_be_bytes(png[16..20].try_into().unwrap()), u32::from_be_bytes(png[20..24].try_into().unwrap()))
+    }
+
+    #[test]
+    fn antimeridian_panel_spans_only_the_requested_columns() {
+        let dir = std::env::temp_dir().join(format!("bing-test-heatmap-{}", std::process::id()));
+        let path = dir.join("heatmap.png");
+        let n = 1 << 16;
+        // Six columns west of the antimeridian and four east of it, three rows.
+        let ranges = vec![(16, vec![(n - 6, n - 1, 10, 12), (0, 3, 10, 12)])];
+        let tiles = [
+            (n - 6, 10, 16, TileStatus::Saved),
+            (n - 1, 11, 16, TileStatus::Error),
+            (0, 11, 16, TileStatus::Saved),
+            (3, 12, 16, TileStatus::Http(404)),
+        ];
+        write_heatmap(&tiles, &ranges, &path).unwrap();
+        let cell = (TARGET_PANEL_PX / 10).clamp(1, MAX_CELL_PX);
+        assert_eq!(png_size(&fs::read(&path).unwrap()), (10 * cell as u32, 3 * cell as u32));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
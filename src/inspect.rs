@@ -0,0 +1,135 @@
+use crate::glb::Glb;
+use crate::stats::format_bytes;
+use anyhow::{Context, Result};
+use clap::Parser;
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Tile to inspect (.glb, or .glb.gz from `download --gzip`)
+    pub file: PathBuf,
+}
+
+/// Prints what a GLB tile holds: meshes and primitives, textures by image
+/// format, buffer sizes and the glTF extensions it uses, and whether it has
+/// KTX2 textures that `decompress` would convert.
+pub fn run_inspect(args: Args) -> Result<()> {
+    let mut bytes = fs::read(&args.file).with_context(|| format!("Reading {:?}", args.file))?;
+    let stored_len = bytes.len();
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut inflated = Vec::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut inflated)
+            .with_context(|| format!("Inflating {:?}", args.file))?;
+        bytes = inflated;
+    }
+    let glb = Glb::parse(&bytes).with_context(|| format!("Parsing {:?}", args.file))?;
+    let json = &glb.json;
+
+    println!("File: {}", args.file.display());
+    if bytes.len() == stored_len {
+        println!("Size: {}", format_bytes(bytes.len() as u64));
+    } else {
+        println!(
+            "Size: {} ({} gzip-compressed)",
+            format_bytes(bytes.len() as u64),
+            format_bytes(stored_len as u64)
+        );
+    }
+    let asset = &json["asset"];
+    println!(
+        "glTF: version {}{}",
+        asset["version"].as_str().unwrap_or("?"),
+        asset["generator"].as_str().map(|g| format!(", generator {:?}", g)).unwrap_or_default()
+    );
+
+    let meshes = array(json, "meshes");
+    let primitives: usize = meshes.iter().map(|m| array(m, "primitives").len()).sum();
+    println!("Meshes: {} ({} primitives)", meshes.len(), primitives);
+
+    let images = array(json, "images");
+    let textures = array(json, "textures");
+    let mut formats: BTreeMap<String, usize> = BTreeMap::new();
+    let mut ktx2 = 0;
+    for texture in textures {
+        let basisu = texture["extensions"]["KHR_texture_basisu"]["source"].as_u64();
+        let source = basisu.or(texture["source"].as_u64());
+        let format = match source.and_then(|i| images.get(i as usize)) {
+            Some(image) => image_format(image),
+            None => "no image".to_string(),
+        };
+        if basisu.is_some() || format == "image/ktx2" {
+            ktx2 += 1;
+        }
+        *formats.entry(format).or_default() += 1;
+    }
+    let by_format: Vec<String> = formats.iter().map(|(f, n)| format!("{} {}", n, f)).collect();
+    if by_format.is_empty() {
+        println!("Textures: {} ({} images)", textures.len(), images.len());
+    } else {
+        println!("Textures: {} ({} images): {}", textures.len(), images.len(), by_format.join(", "));
+    }
+
+    let buffers = array(json, "buffers");
+    for (i, buffer) in buffers.iter().enumerate() {
+        let length = buffer["byteLength"].as_u64().unwrap_or(0);
+        let location = match buffer["uri"].as_str() {
+            Some(uri) if uri.starts_with("data:") => "data URI".to_string(),
+            Some(uri) => format!("external {}", uri),
+            None => "BIN chunk".to_string(),
+        };
+        println!("Buffer {}: {} ({})", i, format_bytes(length), location);
+    }
+    println!(
+        "Buffer views: {}, accessors: {}, BIN chunk: {}",
+        array(json, "bufferViews").len(),
+        array(json, "accessors").len(),
+        format_bytes(glb.bin.len() as u64)
+    );
+
+    let used = strings(json, "extensionsUsed");
+    let required = strings(json, "extensionsRequired");
+    println!("Extensions used: {}", if used.is_empty() { "none".to_string() } else { used.join(", ") });
+    if !required.is_empty() {
+        println!("Extensions required: {}", required.join(", "));
+    }
+
+    if ktx2 > 0 || used.iter().any(|e| e == "KHR_texture_basisu") {
+        println!("KTX2: {} of {} textures are KTX2/basisu; run `decompress` to convert them", ktx2, textures.len());
+    } else {
+        println!("KTX2: none; `decompress` would leave this tile unchanged");
+    }
+    Ok(())
+}
+
+/// The JSON array `key` of `value`, empty when it is missing.
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value.get(key).and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default()
+}
+
+fn strings(value: &Value, key: &str) -> Vec<String> {
+    array(value, key).iter().filter_map(Value::as_str).map(str::to_string).collect()
+}
+
+/// MIME type of a glTF image, from `mimeType` or the extension of its URI.
+fn image_format(image: &Value) -> String {
+    if let Some(mime) = image["mimeType"].as_str() {
+        return mime.to_string();
+    }
+    let uri = image["uri"].as_str().unwrap_or_default();
+    if let Some(mime) = uri.strip_prefix("data:").and_then(|u| u.split([';', ',']).next()) {
+        return mime.to_string();
+    }
+    match uri.rsplit('.').next().map(str::to_ascii_lowercase).as_deref() {
+        Some("ktx2") => "image/ktx2".to_string(),
+        Some("png") => "image/png".to_string(),
+        Some("jpg" | "jpeg") => "image/jpeg".to_string(),
+        Some("webp") => "image/webp".to_string(),
+        _ => "unknown".to_string(),
+    }
+}
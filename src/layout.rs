@@ -0,0 +1,233 @@
+use crate::tile::tile_xy_to_quadkey;
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, ValueEnum};
+use rayon::prelude::*;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use walkdir::WalkDir;
+
+/// On-disk arrangement of a tile directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// `z_x_y.ext`, as written by `download` (subdirectories are ignored when reading)
+    Flat,
+    /// `z/x/y.ext`, XYZ rows counted from the north
+    Zxy,
+    /// `z/x/y.ext` with TMS rows counted from the south
+    Tms,
+    /// `<first N quadkey digits>/z_x_y.ext`, as written by `download --qk-prefix-depth`
+    QuadkeyPrefix,
+}
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Tile directory to read
+    pub input: PathBuf,
+
+    /// Directory to write the new layout to
+    pub output: PathBuf,
+
+    /// Layout of the input directory
+    #[arg(long, value_enum)]
+    pub from: Layout,
+
+    /// Layout to write
+    #[arg(long, value_enum)]
+    pub to: Layout,
+
+    /// Quadkey digits per directory name for `--to quadkey-prefix`
+    #[arg(long = "qk-prefix-depth", default_value_t = 2)]
+    pub qk_prefix_depth: usize,
+
+    /// Move files instead of copying them
+    #[arg(long = "move")]
+    pub move_files: bool,
+
+    /// Replace files that already exist in the output directory
+    #[arg(long)]
+    pub overwrite: bool,
+}
+
+/// `z_x_y` file stem of a tile in the flat layout, shared with `download`.
+pub(crate) fn tile_stem(z: u32, x: i32, y: i32) -> String {
+    format!("{}_{}_{}", z, x, y)
+}
+
+/// Directory name for the first `depth` digits of `quadkey`.
+pub(crate) fn quadkey_prefix(quadkey: &str, depth: usize) -> &str {
+    &quadkey[..depth.min(quadkey.len())]
+}
+
+/// Flips a row between XYZ (north origin) and TMS (south origin) numbering.
+pub(crate) fn flip_row(y: i32, z: u32) -> i32 {
+    (1i32 << z) - 1 - y
+}
+
+/// Splits `1_2_3.glb.gz` into `("1_2_3", "glb.gz")`.
+fn split_name(name: &str) -> (&str, &str) {
+    name.split_once('.').unwrap_or((name, ""))
+}
+
+impl Layout {
+    /// Reads `(z, x, y)` (XYZ rows) and the extension from a path relative to
+    /// the input directory.
+    fn parse(self, rel: &Path) -> Option<(u32, i32, i32, String)> {
+        let name = rel.file_name()?.to_str()?;
+        let (stem, ext) = split_name(name);
+        match self {
+            Layout::Flat | Layout::QuadkeyPrefix => {
+                let parts: Vec<&str> = stem.split('_').collect();
+                let [z, x, y] = parts[..] else { return None };
+                Some((z.parse().ok()?, x.parse().ok()?, y.parse().ok()?, ext.to_string()))
+            }
+            Layout::Zxy | Layout::Tms => {
+                let y: i32 = stem.parse().ok()?;
+                let mut dirs = rel.parent()?.components().rev();
+                let x: i32 = dirs.next()?.as_os_str().to_str()?.parse().ok()?;
+                let z: u32 = dirs.next()?.as_os_str().to_str()?.parse().ok()?;
+                if z > 30 {
+                    return None;
+                }
+                let y = if self == Layout::Tms { flip_row(y, z) } else { y };
+                Some((z, x, y, ext.to_string()))
+            }
+        }
+    }
+
+    /// Path of tile `(z, x, y)` (XYZ rows) relative to the output directory.
+    fn path(self, z: u32, x: i32, y: i32, extension: &str, qk_prefix_depth: usize) -> PathBuf {
+        let (dir, stem) = match self {
+            Layout::Flat => (PathBuf::new(), tile_stem(z, x, y)),
+            Layout::QuadkeyPrefix => {
+                let quadkey = tile_xy_to_quadkey(x, y, z);
+                let dir = PathBuf::from(quadkey_prefix(&quadkey, qk_prefix_depth));
+                (dir, tile_stem(z, x, y))
+            }
+            Layout::Zxy => (Path::new(&z.to_string()).join(x.to_string()), y.to_string()),
+            Layout::Tms => (
+                Path::new(&z.to_string()).join(x.to_string()),
+                flip_row(y, z).to_string(),
+            ),
+        };
+        if extension.is_empty() {
+            dir.join(stem)
+        } else {
+            dir.join(format!("{}.{}", stem, extension))
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Layout::Flat => "flat",
+            Layout::Zxy => "zxy",
+            Layout::Tms => "tms",
+            Layout::QuadkeyPrefix => "quadkey-prefix",
+        }
+    }
+}
+
+/// Whether `(x, y)` is a valid tile at zoom `z`. Guards against file names
+/// that happen to parse but are not tile coordinates.
+fn in_range(z: u32, x: i32, y: i32) -> bool {
+    let n = 1i64 << z;
+    (0..n).contains(&(x as i64)) && (0..n).contains(&(y as i64))
+}
+
+pub fn run_relayout(args: Args) -> Result<()> {
+    if !args.input.is_dir() {
+        return Err(anyhow!("Input directory {:?} does not exist", args.input));
+    }
+    if args.to == Layout::QuadkeyPrefix && args.qk_prefix_depth == 0 {
+        return Err(anyhow!("--qk-prefix-depth must be at least 1 for --to quadkey-prefix"));
+    }
+
+    let mut moves = Vec::new();
+    let mut unmatched = 0usize;
+    for entry in WalkDir::new(&args.input).follow_links(true) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(&args.input).unwrap_or(entry.path());
+        if rel.extension().is_some_and(|e| e == "part") {
+            continue;
+        }
+        match args.from.parse(rel) {
+            Some((z, x, y, ext)) if in_range(z, x, y) => {
+                let target = args.output.join(args.to.path(z, x, y, &ext, args.qk_prefix_depth));
+                moves.push((entry.into_path(), target));
+            }
+            _ => unmatched += 1,
+        }
+    }
+    moves.sort();
+
+    let verb = if args.move_files { "Moving" } else { "Copying" };
+    println!(
+        "{} {} files from {:?} ({}) to {:?} ({})",
+        verb,
+        moves.len(),
+        args.input,
+        args.from.name(),
+        args.output,
+        args.to.name()
+    );
+    if unmatched > 0 {
+        println!("Skipped {} files that do not match the {} layout", unmatched, args.from.name());
+    }
+
+    let existing = AtomicUsize::new(0);
+    let mut failures: Vec<(PathBuf, String)> = moves
+        .par_iter()
+        .filter_map(|(src, dst)| {
+            if !args.overwrite && dst.exists() {
+                existing.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            transfer(src, dst, args.move_files)
+                .err()
+                .map(|e| (src.clone(), format!("{:#}", e)))
+        })
+        .collect();
+    failures.sort();
+
+    for (path, reason) in &failures {
+        eprintln!("FAILED {}: {}", path.display(), reason);
+    }
+    let existing = existing.into_inner();
+    println!(
+        "Done: {} written, {} already present (use --overwrite to replace), {} failed",
+        moves.len() - existing - failures.len(),
+        existing,
+        failures.len()
+    );
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} files could not be relaid out. See errors above.", failures.len()))
+    }
+}
+
+/// Copies or moves one file, creating parent directories. Moves fall back to
+/// copy-and-delete across filesystems.
+fn transfer(src: &Path, dst: &Path, move_file: bool) -> Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Creating {:?}", parent))?;
+    }
+    if move_file {
+        match fs::rename(src, dst) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {}
+            Err(e) => return Err(e).with_context(|| format!("Moving to {:?}", dst)),
+        }
+    }
+    fs::copy(src, dst).with_context(|| format!("Copying to {:?}", dst))?;
+    if move_file {
+        fs::remove_file(src).with_context(|| format!("Removing {:?}", src))?;
+    }
+    Ok(())
+}
+
@@ -1,2 +1,27 @@
 pub mod download;
-pub mod decompress;
\ No newline at end of file
+pub mod decompress;
+pub mod glb;
+pub mod merge;
+pub mod tileset;
+pub mod verify;
+pub mod tile;
+pub mod preview;
+pub mod stats;
+pub mod manifest;
+pub mod progress;
+pub mod tms;
+pub mod mask;
+pub mod layout;
+pub mod config;
+pub mod probe;
+pub mod dedupe;
+pub mod error;
+pub mod summary;
+pub mod inspect;
+pub mod checksum;
+pub mod diff;
+pub mod mbtiles;
+#[cfg(feature = "geocode")]
+pub mod geocode;
+#[cfg(feature = "heatmap")]
+pub mod heatmap;
@@ -1,13 +1,40 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use bing::download::{run_download, Args as DownloadArgs};
+use std::process::ExitCode;
+use bing::download::{run_download, Args as DownloadArgs, RunOutcome};
+use bing::error::DownloadError;
 use bing::decompress::{run_decompress, Args as DecompressArgs};
+use bing::verify::{run_verify, Args as VerifyArgs};
+use bing::stats::{run_stats, Args as StatsArgs};
+use bing::layout::{run_relayout, Args as RelayoutArgs};
+use bing::probe::{run_probe, Args as ProbeArgs};
+use bing::inspect::{run_inspect, Args as InspectArgs};
+use bing::diff::{run_diff, Args as DiffArgs};
+use bing::progress::ColorMode;
+use bing::config::parse_with_config;
+use std::path::PathBuf;
+
+/// `download` exit status when some tiles failed but others succeeded.
+const EXIT_PARTIAL: u8 = 3;
+/// `download` exit status when no queued tile succeeded. Errors that stop a
+/// run early exit with 1 as well.
+const EXIT_FAILED: u8 = 1;
+/// `download` exit status when the run stopped because the disk filled up.
+const EXIT_DISK_FULL: u8 = 4;
 
 #[derive(Parser)]
 #[command(name = "bing")]
 #[command(about = "A CLI tool for downloading and processing Bing Maps tiles")]
 #[command(version = "0.1.0")]
 struct Cli {
+    /// Colored output: auto (only on a terminal), always or never
+    #[arg(long, global = true, value_enum, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+
+    /// Config file with option defaults (default: ./bing.toml, then ~/.config/bing/bing.toml)
+    #[arg(long, global = true, env = "BING_CONFIG")]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -15,23 +42,64 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Download Bing 3D 'mtx' GLB tiles for a lat/lon rectangle
-    Download(DownloadArgs),
+    Download(Box<DownloadArgs>),
     /// Parallel KTX2 texture decompression for .glb files using gltf-transform ktxdecompress
     Decompress(DecompressArgs),
+    /// Check a downloaded tile directory for corrupt or missing .glb files
+    Verify(VerifyArgs),
+    /// Summarize an existing tile directory: sizes, zoom levels and coverage
+    Stats(StatsArgs),
+    /// Copy or move a tile directory into another layout (flat, zxy, tms, quadkey-prefix)
+    Relayout(RelayoutArgs),
+    /// Request one known-good tile to check the API key, hosts and URL template
+    Probe(ProbeArgs),
+    /// Show the meshes, textures, buffers and extensions of one .glb tile
+    Inspect(InspectArgs),
+    /// Compare two tile directories: tiles only in one of them and tiles whose content changed
+    Diff(DiffArgs),
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+async fn main() -> Result<ExitCode> {
+    let cli: Cli = parse_with_config()?;
 
     match cli.command {
-        Commands::Download(args) => {
-            run_download(args).await?;
+        Commands::Download(mut args) => {
+            args.color = cli.color;
+            return Ok(match run_download(*args).await {
+                Ok(RunOutcome::Complete) => ExitCode::SUCCESS,
+                Ok(RunOutcome::Partial { .. }) => ExitCode::from(EXIT_PARTIAL),
+                Ok(RunOutcome::Failed) => ExitCode::from(EXIT_FAILED),
+                Err(e @ DownloadError::DiskFull { .. }) => {
+                    eprintln!("Error: {}", e);
+                    ExitCode::from(EXIT_DISK_FULL)
+                }
+                Err(e) => return Err(e.into()),
+            });
         }
-        Commands::Decompress(args) => {
+        Commands::Decompress(mut args) => {
+            args.color = cli.color;
             run_decompress(args)?;
         }
+        Commands::Verify(args) => {
+            run_verify(args)?;
+        }
+        Commands::Stats(args) => {
+            run_stats(args)?;
+        }
+        Commands::Relayout(args) => {
+            run_relayout(args)?;
+        }
+        Commands::Probe(args) => {
+            run_probe(args).await?;
+        }
+        Commands::Inspect(args) => {
+            run_inspect(args)?;
+        }
+        Commands::Diff(args) => {
+            run_diff(args)?;
+        }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
\ No newline at end of file
@@ -0,0 +1,309 @@
+use crate::tile::lon_span_union;
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// One saved tile as listed in a run manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestTile {
+    pub z: u32,
+    pub x: i32,
+    pub y: i32,
+    pub quadkey: String,
+    /// Path relative to the manifest's directory, with forward slashes
+    pub path: String,
+    /// Size of the tile itself, before any `--gzip`
+    pub bytes: u64,
+    /// Size of the `.gz` file on disk, for `--gzip` runs
+    pub compressed_bytes: Option<u64>,
+}
+
+/// One download run recorded in an appended manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestRun {
+    pub bbox: [f64; 4],
+    pub zooms: Vec<u32>,
+    pub created: String,
+    /// Tiles the run saved
+    pub tiles: usize,
+}
+
+/// JSON summary of a download run: what was asked for and what was saved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// `[west, south, east, north]`, GeoJSON order: west > east when the
+    /// box crosses the antimeridian
+    pub bbox: [f64; 4],
+    pub zooms: Vec<u32>,
+    pub form: String,
+    /// UTC time the manifest was written, `YYYY-MM-DDTHH:MM:SSZ`
+    pub created: String,
+    pub tiles: Vec<ManifestTile>,
+    /// Runs merged into this manifest by `--manifest-append`, oldest first;
+    /// empty for a single run
+    pub runs: Vec<ManifestRun>,
+}
+
+impl ManifestTile {
+    fn to_json(&self) -> Value {
+        let mut tile = json!({
+            "z": self.z,
+            "x": self.x,
+            "y": self.y,
+            "quadkey": self.quadkey,
+            "path": self.path,
+            "bytes": self.bytes,
+        });
+        if let Some(compressed) = self.compressed_bytes {
+            tile["compressed_bytes"] = compressed.into();
+        }
+        tile
+    }
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> Value {
+        let tiles: Vec<Value> = self.tiles.iter().map(ManifestTile::to_json).collect();
+        let mut doc = json!({
+            "bbox": self.bbox,
+            "zooms": self.zooms,
+            "form": self.form,
+            "created": self.created,
+            "tile_count": self.tiles.len(),
+            "tiles": tiles,
+        });
+        if !self.runs.is_empty() {
+            let runs: Vec<Value> = self
+                .runs
+                .iter()
+                .map(|r| json!({ "bbox": r.bbox, "zooms": r.zooms, "created": r.created, "tiles": r.tiles }))
+                .collect();
+            doc["runs"] = runs.into();
+        }
+        doc
+    }
+
+    pub fn from_json(doc: &Value) -> Result<Self> {
+        let bbox = parse_bbox(&doc["bbox"])?;
+        let zooms = parse_zooms(&doc["zooms"]);
+        let runs = match doc["runs"].as_array() {
+            Some(runs) => runs
+                .iter()
+                .map(|r| {
+                    Ok(ManifestRun {
+                        bbox: parse_bbox(&r["bbox"])?,
+                        zooms: parse_zooms(&r["zooms"]),
+                        created: r["created"].as_str().unwrap_or_default().to_string(),
+                        tiles: r["tiles"].as_u64().unwrap_or(0) as usize,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        let tiles = doc["tiles"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Manifest has no tiles array"))?
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let int = |key: &str| {
+                    t[key]
+                        .as_i64()
+                        .ok_or_else(|| anyhow!("Manifest tile {} is missing '{}'", i, key))
+                };
+                Ok(ManifestTile {
+                    z: int("z")? as u32,
+                    x: int("x")? as i32,
+                    y: int("y")? as i32,
+                    quadkey: t["quadkey"].as_str().unwrap_or_default().to_string(),
+                    path: t["path"].as_str().unwrap_or_default().to_string(),
+                    bytes: t["bytes"].as_u64().unwrap_or(0),
+                    compressed_bytes: t["compressed_bytes"].as_u64(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            bbox,
+            zooms,
+            form: doc["form"].as_str().unwrap_or("web3d").to_string(),
+            created: doc["created"].as_str().unwrap_or_default().to_string(),
+            tiles,
+            runs,
+        })
+    }
+
+    /// This manifest as a single entry of a `runs` history.
+    fn as_run(&self) -> ManifestRun {
+        ManifestRun {
+            bbox: self.bbox,
+            zooms: self.zooms.clone(),
+            created: self.created.clone(),
+            tiles: self.tiles.len(),
+        }
+    }
+
+    /// Merges this run into `previous` for `--manifest-append`: tiles are
+    /// matched by quadkey with this run's entry winning, the bbox (the short
+    /// way round the antimeridian) and zooms grow to cover both, and this run is added to the `runs` history. A
+    /// previous manifest without one counts as a single earlier run.
+    pub fn append_to(mut self, previous: Manifest) -> Self {
+        let mut runs = if previous.runs.is_empty() { vec![previous.as_run()] } else { previous.runs };
+        runs.push(self.as_run());
+
+        let fresh: HashSet<&str> = self.tiles.iter().map(|t| t.quadkey.as_str()).collect();
+        let kept: Vec<ManifestTile> =
+            previous.tiles.into_iter().filter(|t| !fresh.contains(t.quadkey.as_str())).collect();
+        self.tiles.extend(kept);
+        self.tiles.sort_by_key(|t| (t.z, t.x, t.y));
+
+        let [w, s, e, n] = previous.bbox;
+        let (west, east) = lon_span_union((self.bbox[0], self.bbox[2]), (w, e));
+        self.bbox = [west, self.bbox[1].min(s), east, self.bbox[3].max(n)];
+        self.zooms.extend(previous.zooms);
+        self.zooms.sort_unstable();
+        self.zooms.dedup();
+        self.runs = runs;
+        self
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Reading manifest {:?}", path))?;
+        let doc: Value =
+            serde_json::from_str(&text).with_context(|| format!("Parsing manifest {:?}", path))?;
+        Self::from_json(&doc).with_context(|| format!("Parsing manifest {:?}", path))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        fs::write(path, serde_json::to_vec_pretty(&self.to_json())?)
+            .with_context(|| format!("Writing manifest {:?}", path))
+    }
+}
+
+fn parse_bbox(value: &Value) -> Result<[f64; 4]> {
+    let bbox: Vec<f64> = value
+        .as_array()
+        .map(|a| a.iter().filter_map(Value::as_f64).collect())
+        .unwrap_or_default();
+    bbox.try_into().map_err(|_| anyhow!("Manifest bbox must be 4 numbers"))
+}
+
+fn parse_zooms(value: &Value) -> Vec<u32> {
+    value
+        .as_array()
+        .map(|a| a.iter().filter_map(Value::as_u64).map(|z| z as u32).collect())
+        .unwrap_or_default()
+}
+
+/// Writes the manifest of a `--batch-size` run as it goes: each batch's
+/// tiles are appended to the file in place, after the ones already there,
+/// so neither memory nor the rewriting grows with the tiles written so far.
+/// The file is a complete manifest after every batch.
+pub struct ManifestWriter {
+    file: File,
+    path: PathBuf,
+    tiles: usize,
+    /// Offset of the text after the last tile, rewritten by every batch
+    tail_at: u64,
+}
+
+impl ManifestWriter {
+    /// Starts a manifest with the bbox, zooms, form and time of `head`,
+    /// whose own tiles and runs are ignored.
+    pub fn create(path: &Path, head: &Manifest) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        let head = json!({
+            "bbox": head.bbox,
+            "zooms": head.zooms,
+            "form": head.form,
+            "created": head.created,
+        });
+        let head = serde_json::to_string_pretty(&head)?;
+        // Reopen the object to list the tiles last, then the count after them.
+        let head = format!("{},\n  \"tiles\": [", head.trim_end().trim_end_matches('}').trim_end());
+        let mut file = File::create(path).with_context(|| format!("Writing manifest {:?}", path))?;
+        file.write_all(head.as_bytes())
+            .with_context(|| format!("Writing manifest {:?}", path))?;
+        let mut writer = Self { file, path: path.to_path_buf(), tiles: 0, tail_at: head.len() as u64 };
+        writer.append(&[])?;
+        Ok(writer)
+    }
+
+    /// Adds `tiles` after those already written.
+    pub fn append(&mut self, tiles: &[ManifestTile]) -> Result<()> {
+        let mut text = String::new();
+        for tile in tiles {
+            text.push_str(if self.tiles == 0 && text.is_empty() { "\n    " } else { ",\n    " });
+            text.push_str(&serde_json::to_string(&tile.to_json())?);
+        }
+        self.tiles += tiles.len();
+        let written = (|| -> std::io::Result<()> {
+            self.file.seek(SeekFrom::Start(self.tail_at))?;
+            self.file.write_all(text.as_bytes())?;
+            self.tail_at += text.len() as u64;
+            let close = if self.tiles == 0 { "" } else { "\n  " };
+            write!(self.file, "{}],\n  \"tile_count\": {}\n}}\n", close, self.tiles)?;
+            let end = self.file.stream_position()?;
+            self.file.set_len(end)?;
+            self.file.flush()
+        })();
+        written.with_context(|| format!("Writing manifest {:?}", self.path))
+    }
+
+    /// Tiles written so far.
+    pub fn tiles(&self) -> usize {
+        self.tiles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(x: i32) -> ManifestTile {
+        ManifestTile {
+            z: 3,
+            x,
+            y: 1,
+            quadkey: format!("q{}", x),
+            path: format!("3_{}_1.glb", x),
+            bytes: 100 + x as u64,
+            compressed_bytes: (x % 2 == 0).then_some(50),
+        }
+    }
+
+    #[test]
+    fn batches_appended_in_place_read_back_as_one_manifest() {
+        let dir = std::env::temp_dir().join(format!("bing-test-manifest-writer-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let path = dir.join("manifest.json");
+        let expected = Manifest {
+            bbox: [170.0, -10.0, -170.0, 10.0],
+            zooms: vec![3],
+            form: "web3d".to_string(),
+            created: "2026-10-14T00:00:00Z".to_string(),
+            tiles: (0..5).map(tile).collect(),
+            runs: Vec::new(),
+        };
+
+        let mut writer = ManifestWriter::create(&path, &expected).unwrap();
+        assert_eq!(Manifest::read(&path).unwrap().tiles, []);
+        writer.append(&expected.tiles[..2]).unwrap();
+        writer.append(&[]).unwrap();
+        assert_eq!(Manifest::read(&path).unwrap().tiles, &expected.tiles[..2]);
+        writer.append(&expected.tiles[2..]).unwrap();
+        assert_eq!(writer.tiles(), 5);
+
+        assert_eq!(Manifest::read(&path).unwrap(), expected);
+        let doc: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(doc["tile_count"], 5);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
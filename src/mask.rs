@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// A `[lon, lat]` position.
+type Point = (f64, f64);
+
+/// One polygon: the outer ring followed by any holes. Rings may be closed
+/// (first point repeated) or not.
+struct Polygon {
+    rings: Vec<Vec<Point>>,
+}
+
+/// Polygons read from a GeoJSON file, tested against tile bounds.
+pub struct Mask {
+    polygons: Vec<Polygon>,
+}
+
+impl Mask {
+    /// Reads every Polygon and MultiPolygon from a GeoJSON geometry, Feature,
+    /// FeatureCollection or GeometryCollection. Other geometry types are
+    /// ignored; a file without any polygon is an error.
+    pub fn read_geojson(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Reading mask {:?}", path))?;
+        let doc: Value =
+            serde_json::from_str(&text).with_context(|| format!("Parsing mask {:?}", path))?;
+        let mut polygons = Vec::new();
+        collect_polygons(&doc, &mut polygons).with_context(|| format!("Parsing mask {:?}", path))?;
+        if polygons.is_empty() {
+            return Err(anyhow!("Mask {:?} contains no Polygon or MultiPolygon", path));
+        }
+        Ok(Self { polygons })
+    }
+
+    /// Whether the box `(west, south, east, north)` overlaps any polygon.
+    pub fn intersects(&self, (west, south, east, north): (f64, f64, f64, f64)) -> bool {
+        let corners = [(west, south), (east, south), (east, north), (west, north)];
+        let in_box = |&(x, y): &Point| x >= west && x <= east && y >= south && y <= north;
+        self.polygons.iter().any(|poly| {
+            corners.iter().any(|c| poly.contains(*c))
+                || poly.rings.iter().flatten().any(in_box)
+                || poly.rings.iter().any(|ring| {
+                    edges(ring).any(|(a, b)| {
+                        edges(&corners).any(|(c, d)| segments_cross(a, b, c, d))
+                    })
+                })
+        })
+    }
+}
+
+impl Polygon {
+    /// Even-odd test: inside the outer ring and outside every hole.
+    fn contains(&self, p: Point) -> bool {
+        let mut rings = self.rings.iter();
+        rings.next().is_some_and(|outer| ring_contains(outer, p))
+            && rings.all(|hole| !ring_contains(hole, p))
+    }
+}
+
+fn collect_polygons(value: &Value, out: &mut Vec<Polygon>) -> Result<()> {
+    match value["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in value["features"].as_array().into_iter().flatten() {
+                collect_polygons(feature, out)?;
+            }
+        }
+        Some("Feature") => collect_polygons(&value["geometry"], out)?,
+        Some("GeometryCollection") => {
+            for geometry in value["geometries"].as_array().into_iter().flatten() {
+                collect_polygons(geometry, out)?;
+            }
+        }
+        Some("Polygon") => out.push(parse_polygon(&value["coordinates"])?),
+        Some("MultiPolygon") => {
+            let polygons = value["coordinates"]
+                .as_array()
+                .ok_or_else(|| anyhow!("MultiPolygon without coordinates"))?;
+            for polygon in polygons {
+                out.push(parse_polygon(polygon)?);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn parse_polygon(coords: &Value) -> Result<Polygon> {
+    let rings = coords
+        .as_array()
+        .ok_or_else(|| anyhow!("Polygon without coordinates"))?
+        .iter()
+        .map(|ring| {
+            ring.as_array()
+                .ok_or_else(|| anyhow!("Polygon ring is not an array"))?
+                .iter()
+                .map(|pos| match (pos[0].as_f64(), pos[1].as_f64()) {
+                    (Some(lon), Some(lat)) => Ok((lon, lat)),
+                    _ => Err(anyhow!("Polygon position must be [lon, lat]")),
+                })
+                .collect::<Result<Vec<Point>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if rings.first().is_none_or(|outer| outer.len() < 3) {
+        return Err(anyhow!("Polygon outer ring needs at least 3 positions"));
+    }
+    Ok(Polygon { rings })
+}
+
+/// Consecutive point pairs of a ring, including the closing edge.
+fn edges(ring: &[Point]) -> impl Iterator<Item = (Point, Point)> + '_ {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| (*a, *b))
+}
+
+/// Ray-casting point-in-ring test.
+fn ring_contains(ring: &[Point], (x, y): Point) -> bool {
+    let mut inside = false;
+    for ((x1, y1), (x2, y2)) in edges(ring) {
+        if (y1 > y) != (y2 > y) && x < (x2 - x1) * (y - y1) / (y2 - y1) + x1 {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Whether segments `ab` and `cd` intersect, touching included.
+fn segments_cross(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let orient = |p: Point, q: Point, r: Point| (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0);
+    let d1 = orient(c, d, a);
+    let d2 = orient(c, d, b);
+    let d3 = orient(a, b, c);
+    let d4 = orient(a, b, d);
+    // The bounding-box check rules out collinear segments that don't overlap.
+    let overlap = |p: f64, q: f64, r: f64, s: f64| p.min(q) <= r.max(s) && r.min(s) <= p.max(q);
+    d1 * d2 <= 0.0 && d3 * d4 <= 0.0 && overlap(a.0, b.0, c.0, d.0) && overlap(a.1, b.1, c.1, d.1)
+}
@@ -0,0 +1,972 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// MBTiles files are SQLite databases. Only the parts of the file format
+// (https://www.sqlite.org/fileformat2.html) needed to read a tile set and to
+// write a fresh one are implemented here, rather than linking SQLite.
+
+const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+/// Page size of the files [`MbtilesWriter`] writes.
+const PAGE_SIZE: usize = 4096;
+/// `application_id` of MBTiles 1.3 files ("MPBX").
+const MBTILES_APPLICATION_ID: u32 = 0x4D50_4258;
+/// Nominal SQLite version recorded in written files (3.46.0).
+const SQLITE_VERSION_NUMBER: u32 = 3_046_000;
+
+const INTERIOR_INDEX: u8 = 0x02;
+const INTERIOR_TABLE: u8 = 0x05;
+const LEAF_INDEX: u8 = 0x0A;
+const LEAF_TABLE: u8 = 0x0D;
+
+const TILES_SQL: &str = "CREATE TABLE tiles (zoom_level integer, tile_column integer, tile_row integer, tile_data blob)";
+const METADATA_SQL: &str = "CREATE TABLE metadata (name text, value text)";
+const TILE_INDEX_SQL: &str = "CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row)";
+
+/// One tile of an MBTiles set. `row` is `tile_row` as stored, a TMS row
+/// counted from the south.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MbTile {
+    pub z: u32,
+    pub column: i32,
+    pub row: i32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SqlValue {
+    Null,
+    Int(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl SqlValue {
+    fn as_int(&self) -> Option<i64> {
+        match *self {
+            SqlValue::Int(i) => Some(i),
+            SqlValue::Real(f) if f.fract() == 0.0 => Some(f as i64),
+            _ => None,
+        }
+    }
+
+    fn into_text(self) -> String {
+        match self {
+            SqlValue::Null => String::new(),
+            SqlValue::Int(i) => i.to_string(),
+            SqlValue::Real(f) => f.to_string(),
+            SqlValue::Text(s) => s,
+            SqlValue::Blob(b) => String::from_utf8_lossy(&b).into_owned(),
+        }
+    }
+}
+
+/// A row of `sqlite_master`.
+struct SchemaEntry {
+    kind: String,
+    name: String,
+    root: u32,
+    sql: String,
+}
+
+/// Read-only access to the tiles and metadata of an MBTiles file.
+pub struct MbtilesReader {
+    file: File,
+    path: PathBuf,
+    page_size: usize,
+    usable: usize,
+    pages: u32,
+    schema: Vec<SchemaEntry>,
+}
+
+impl MbtilesReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path).with_context(|| format!("Opening MBTiles {:?}", path))?;
+        let mut header = [0u8; 100];
+        file.read_exact(&mut header)
+            .with_context(|| format!("{:?} is too short to be an MBTiles file", path))?;
+        if &header[..16] != SQLITE_MAGIC {
+            return Err(anyhow!("{:?} is not an MBTiles (SQLite) file", path));
+        }
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65_536,
+            n => n as usize,
+        };
+        if !page_size.is_power_of_two() || !(512..=65_536).contains(&page_size) {
+            return Err(anyhow!("{:?} has an invalid SQLite page size {}", path, page_size));
+        }
+        let encoding = u32::from_be_bytes(header[56..60].try_into().unwrap());
+        if encoding > 1 {
+            return Err(anyhow!("{:?} uses a UTF-16 text encoding, which is not supported", path));
+        }
+        let wal = path.with_file_name(format!(
+            "{}-wal",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        if header[18] == 2 && fs::metadata(&wal).is_ok_and(|m| m.len() > 0) {
+            return Err(anyhow!(
+                "{:?} has uncheckpointed changes in {:?}; checkpoint it (e.g. `sqlite3 {} 'PRAGMA wal_checkpoint'`) first",
+                path,
+                wal,
+                path.display()
+            ));
+        }
+        let len = file.metadata()?.len();
+        let mut reader = Self {
+            file,
+            path: path.to_path_buf(),
+            page_size,
+            usable: page_size - header[20] as usize,
+            pages: (len / page_size as u64) as u32,
+            schema: Vec::new(),
+        };
+        let mut schema = Vec::new();
+        reader.scan_table(1, &mut |_, values| {
+            let mut values = values.into_iter();
+            let mut next = || values.next().unwrap_or(SqlValue::Null);
+            let (kind, name, _table, root, sql) = (next(), next(), next(), next(), next());
+            schema.push(SchemaEntry {
+                kind: kind.into_text(),
+                name: name.into_text(),
+                root: root.as_int().unwrap_or(0) as u32,
+                sql: sql.into_text(),
+            });
+            Ok(())
+        })?;
+        reader.schema = schema;
+        Ok(reader)
+    }
+
+    fn entry(&self, kind: &str, name: &str) -> Option<&SchemaEntry> {
+        self.schema
+            .iter()
+            .find(|e| e.kind == kind && e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Rows of the `metadata` table as `(name, value)`; empty without one.
+    pub fn metadata(&mut self) -> Result<Vec<(String, String)>> {
+        let Some(table) = self.entry("table", "metadata") else {
+            return Ok(Vec::new());
+        };
+        let (root, columns) = (table.root, TableColumns::parse(&table.sql));
+        let (name, value) = (columns.find("name")?, columns.find("value")?);
+        let mut rows = Vec::new();
+        self.scan_table(root, &mut |rowid, values| {
+            let name = columns.get(&values, name, rowid).into_text();
+            let value = columns.get(&values, value, rowid).into_text();
+            rows.push((name, value));
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    /// Calls `visit` with every tile, from the `tiles` table or, for files
+    /// that deduplicate tile data, the `map` and `images` tables behind a
+    /// `tiles` view.
+    pub fn tiles(&mut self, visit: &mut dyn FnMut(MbTile) -> Result<()>) -> Result<()> {
+        if let Some(table) = self.entry("table", "tiles") {
+            let (root, columns) = (table.root, TableColumns::parse(&table.sql));
+            let keys = [columns.find("zoom_level")?, columns.find("tile_column")?, columns.find("tile_row")?];
+            let data = columns.find("tile_data")?;
+            return self.scan_table(root, &mut |rowid, mut values| {
+                let [z, column, row] = keys.map(|k| columns.get(&values, k, rowid).as_int());
+                let data = columns.take(&mut values, data);
+                visit(tile(z, column, row, data)?)
+            });
+        }
+
+        let (Some(map), Some(images)) = (self.entry("table", "map"), self.entry("table", "images")) else {
+            return Err(anyhow!("{:?} has no tiles table (nor map and images tables)", self.path));
+        };
+        let (map_root, map_columns) = (map.root, TableColumns::parse(&map.sql));
+        let (images_root, image_columns) = (images.root, TableColumns::parse(&images.sql));
+        let keys = [
+            map_columns.find("zoom_level")?,
+            map_columns.find("tile_column")?,
+            map_columns.find("tile_row")?,
+        ];
+        let map_id = map_columns.find("tile_id")?;
+        let (image_id, data) = (image_columns.find("tile_id")?, image_columns.find("tile_data")?);
+
+        // Keys are small, so the map is held while the images stream past.
+        let mut by_id: HashMap<String, Vec<[Option<i64>; 3]>> = HashMap::new();
+        self.scan_table(map_root, &mut |rowid, values| {
+            let id = map_columns.get(&values, map_id, rowid).into_text();
+            by_id.entry(id).or_default().push(keys.map(|k| map_columns.get(&values, k, rowid).as_int()));
+            Ok(())
+        })?;
+        self.scan_table(images_root, &mut |rowid, mut values| {
+            let id = image_columns.get(&values, image_id, rowid).into_text();
+            let Some(keys) = by_id.remove(&id) else {
+                return Ok(());
+            };
+            let data = image_columns.take(&mut values, data);
+            for [z, column, row] in keys {
+                visit(tile(z, column, row, data.clone())?)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn page(&mut self, number: u32) -> Result<Vec<u8>> {
+        if number == 0 || number > self.pages {
+            return Err(anyhow!("{:?} is corrupt: page {} is out of range", self.path, number));
+        }
+        let mut page = vec![0u8; self.page_size];
+        self.file.seek(SeekFrom::Start((number as u64 - 1) * self.page_size as u64))?;
+        self.file
+            .read_exact(&mut page)
+            .with_context(|| format!("Reading page {} of {:?}", number, self.path))?;
+        Ok(page)
+    }
+
+    /// Walks the table b-tree rooted at `root` in rowid order, calling
+    /// `visit` with each row's rowid and decoded record.
+    fn scan_table(&mut self, root: u32, visit: &mut dyn FnMut(i64, Vec<SqlValue>) -> Result<()>) -> Result<()> {
+        let path = self.path.clone();
+        let corrupt = |what: &str| anyhow!("{:?} is corrupt: {}", path, what);
+        let mut stack = vec![root];
+        let mut visited = 0u32;
+        while let Some(number) = stack.pop() {
+            visited += 1;
+            if visited > self.pages {
+                return Err(corrupt("b-tree pages form a loop"));
+            }
+            let page = self.page(number)?;
+            let base = if number == 1 { 100 } else { 0 };
+            let kind = page[base];
+            let cells = u16::from_be_bytes([page[base + 3], page[base + 4]]) as usize;
+            let header_len = if kind == INTERIOR_TABLE { 12 } else { 8 };
+            let pointer = |i: usize| -> Result<usize> {
+                let at = base + header_len + 2 * i;
+                let offset = page.get(at..at + 2).ok_or_else(|| corrupt("cell pointer past the page"))?;
+                Ok(u16::from_be_bytes([offset[0], offset[1]]) as usize)
+            };
+            match kind {
+                INTERIOR_TABLE => {
+                    // Pushed right-most first so the leftmost child pops first.
+                    stack.push(u32::from_be_bytes(page[base + 8..base + 12].try_into().unwrap()));
+                    for i in (0..cells).rev() {
+                        let at = pointer(i)?;
+                        let child = page.get(at..at + 4).ok_or_else(|| corrupt("cell past the page"))?;
+                        stack.push(u32::from_be_bytes(child.try_into().unwrap()));
+                    }
+                }
+                LEAF_TABLE => {
+                    for i in 0..cells {
+                        let at = pointer(i)?;
+                        let (len, n) = get_varint(&page, at).ok_or_else(|| corrupt("truncated cell"))?;
+                        let (rowid, m) = get_varint(&page, at + n).ok_or_else(|| corrupt("truncated cell"))?;
+                        let payload = self.payload(&page, at + n + m, len as usize)?;
+                        let values = decode_record(&payload).ok_or_else(|| corrupt("malformed record"))?;
+                        visit(rowid as i64, values)?;
+                    }
+                }
+                other => return Err(corrupt(&format!("page {} has unexpected type {:#04x}", number, other))),
+            }
+        }
+        Ok(())
+    }
+
+    /// The `len`-byte payload of a table leaf cell starting at `at`,
+    /// followed through its overflow pages.
+    fn payload(&mut self, page: &[u8], at: usize, len: usize) -> Result<Vec<u8>> {
+        let local = local_payload_len(len, self.usable);
+        let corrupt = || anyhow!("{:?} is corrupt: cell payload past the page", self.path);
+        let mut payload = page.get(at..at + local).ok_or_else(corrupt)?.to_vec();
+        if local < len {
+            let next = page.get(at + local..at + local + 4).ok_or_else(corrupt)?;
+            let mut next = u32::from_be_bytes(next.try_into().unwrap());
+            while payload.len() < len {
+                if next == 0 {
+                    return Err(anyhow!("{:?} is corrupt: overflow chain ends early", self.path));
+                }
+                let overflow = self.page(next)?;
+                let take = (len - payload.len()).min(self.usable - 4);
+                payload.extend_from_slice(&overflow[4..4 + take]);
+                next = u32::from_be_bytes(overflow[..4].try_into().unwrap());
+            }
+        }
+        Ok(payload)
+    }
+}
+
+fn tile(z: Option<i64>, column: Option<i64>, row: Option<i64>, data: SqlValue) -> Result<MbTile> {
+    let (Some(z), Some(column), Some(row)) = (z, column, row) else {
+        return Err(anyhow!("Tile with a non-integer zoom_level, tile_column or tile_row"));
+    };
+    let data = match data {
+        SqlValue::Blob(b) => b,
+        SqlValue::Text(s) => s.into_bytes(),
+        SqlValue::Null => Vec::new(),
+        _ => return Err(anyhow!("Tile {}/{}/{} has numeric tile_data", z, column, row)),
+    };
+    let (Ok(z), Ok(column), Ok(row)) = (u32::try_from(z), i32::try_from(column), i32::try_from(row)) else {
+        return Err(anyhow!("Tile {}/{}/{} is out of range", z, column, row));
+    };
+    Ok(MbTile { z, column, row, data })
+}
+
+/// Column positions of a table, read from its `CREATE TABLE` statement.
+struct TableColumns {
+    names: Vec<String>,
+    /// The `INTEGER PRIMARY KEY` column, stored as the rowid
+    rowid_alias: Option<usize>,
+}
+
+impl TableColumns {
+    fn parse(sql: &str) -> Self {
+        let body = match (sql.find('('), sql.rfind(')')) {
+            (Some(start), Some(end)) if start < end => &sql[start + 1..end],
+            _ => "",
+        };
+        let mut definitions = Vec::new();
+        let (mut depth, mut start) = (0, 0);
+        for (i, c) in body.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    definitions.push(&body[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        definitions.push(&body[start..]);
+
+        let mut names = Vec::new();
+        let mut rowid_alias = None;
+        for definition in definitions {
+            let definition = definition.trim();
+            let upper = definition.to_ascii_uppercase();
+            let first = upper.split_whitespace().next().unwrap_or_default();
+            if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"].contains(&first) {
+                continue;
+            }
+            let name = definition.split_whitespace().next().unwrap_or_default();
+            let name = name.trim_matches(|c| matches!(c, '"' | '`' | '[' | ']' | '\''));
+            let words: Vec<&str> = upper.split_whitespace().collect();
+            if words.get(1) == Some(&"INTEGER") && words.windows(2).any(|w| w == ["PRIMARY", "KEY"]) {
+                rowid_alias = Some(names.len());
+            }
+            names.push(name.to_string());
+        }
+        Self { names, rowid_alias }
+    }
+
+    fn find(&self, name: &str) -> Result<usize> {
+        self.names
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("MBTiles table has no {} column", name))
+    }
+
+    fn get(&self, values: &[SqlValue], column: usize, rowid: i64) -> SqlValue {
+        if self.rowid_alias == Some(column) {
+            return SqlValue::Int(rowid);
+        }
+        values.get(column).cloned().unwrap_or(SqlValue::Null)
+    }
+
+    /// Like [`Self::get`] for a blob column, without copying it.
+    fn take(&self, values: &mut [SqlValue], column: usize) -> SqlValue {
+        values.get_mut(column).map(|v| std::mem::replace(v, SqlValue::Null)).unwrap_or(SqlValue::Null)
+    }
+}
+
+/// Bytes of a `len`-byte table leaf payload kept on the b-tree page, the
+/// rest going to overflow pages.
+fn local_payload_len(len: usize, usable: usize) -> usize {
+    let max_local = usable - 35;
+    if len <= max_local {
+        return len;
+    }
+    let min_local = (usable - 12) * 32 / 255 - 23;
+    let local = min_local + (len - min_local) % (usable - 4);
+    if local <= max_local { local } else { min_local }
+}
+
+fn get_varint(bytes: &[u8], at: usize) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for i in 0..8 {
+        let byte = *bytes.get(at + i)?;
+        value = (value << 7) | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    Some(((value << 8) | *bytes.get(at + 8)? as u64, 9))
+}
+
+fn put_varint(out: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        let mut bytes = [0u8; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest & 0x7F) as u8 | 0x80;
+            rest >>= 7;
+        }
+        out.extend_from_slice(&bytes);
+        return;
+    }
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest != 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.extend(groups.iter().rev());
+}
+
+fn varint_len(value: u64) -> usize {
+    let mut out = Vec::new();
+    put_varint(&mut out, value);
+    out.len()
+}
+
+fn decode_record(payload: &[u8]) -> Option<Vec<SqlValue>> {
+    let (header_len, mut at) = get_varint(payload, 0)?;
+    let header_len = header_len as usize;
+    let mut body = header_len;
+    let mut values = Vec::new();
+    while at < header_len {
+        let (serial, n) = get_varint(payload, at)?;
+        at += n;
+        let int = |len: usize| -> Option<i64> {
+            let bytes = payload.get(body..body + len)?;
+            let mut value = if bytes.first()? & 0x80 != 0 { -1i64 } else { 0 };
+            for &b in bytes {
+                value = (value << 8) | b as i64;
+            }
+            Some(value)
+        };
+        let (value, len) = match serial {
+            0 => (SqlValue::Null, 0),
+            1..=6 => {
+                let len = [1, 2, 3, 4, 6, 8][serial as usize - 1];
+                (SqlValue::Int(int(len)?), len)
+            }
+            7 => (SqlValue::Real(f64::from_bits(int(8)? as u64)), 8),
+            8 => (SqlValue::Int(0), 0),
+            9 => (SqlValue::Int(1), 0),
+            n if n >= 12 => {
+                let len = ((n - 12) / 2) as usize;
+                let bytes = payload.get(body..body + len)?.to_vec();
+                if n % 2 == 0 {
+                    (SqlValue::Blob(bytes), len)
+                } else {
+                    (SqlValue::Text(String::from_utf8_lossy(&bytes).into_owned()), len)
+                }
+            }
+            _ => return None,
+        };
+        values.push(value);
+        body += len;
+    }
+    Some(values)
+}
+
+fn encode_record(values: &[SqlValue]) -> Vec<u8> {
+    let mut serials = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial = match value {
+            SqlValue::Null => 0,
+            SqlValue::Int(0) => 8,
+            SqlValue::Int(1) => 9,
+            &SqlValue::Int(i) => {
+                let (serial, len) = match i {
+                    -0x80..=0x7F => (1, 1),
+                    -0x8000..=0x7FFF => (2, 2),
+                    -0x80_0000..=0x7F_FFFF => (3, 3),
+                    -0x8000_0000..=0x7FFF_FFFF => (4, 4),
+                    -0x8000_0000_0000..=0x7FFF_FFFF_FFFF => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&i.to_be_bytes()[8 - len..]);
+                serial
+            }
+            SqlValue::Real(f) => {
+                body.extend_from_slice(&f.to_bits().to_be_bytes());
+                7
+            }
+            SqlValue::Text(s) => {
+                body.extend_from_slice(s.as_bytes());
+                13 + 2 * s.len() as u64
+            }
+            SqlValue::Blob(b) => {
+                body.extend_from_slice(b);
+                12 + 2 * b.len() as u64
+            }
+        };
+        put_varint(&mut serials, serial);
+    }
+    // The header length counts its own varint.
+    let mut header_len = serials.len() + 1;
+    while varint_len(header_len as u64) + serials.len() != header_len {
+        header_len += 1;
+    }
+    let mut record = Vec::with_capacity(header_len + body.len());
+    put_varint(&mut record, header_len as u64);
+    record.extend_from_slice(&serials);
+    record.extend_from_slice(&body);
+    record
+}
+
+/// Lays `cells` out on a b-tree page whose header starts at `header_at`
+/// (100 on page 1, after the file header).
+fn btree_page(kind: u8, cells: &[Vec<u8>], right_child: Option<u32>, header_at: usize) -> Vec<u8> {
+    let mut page = vec![0u8; PAGE_SIZE];
+    let header_len = if right_child.is_some() { 12 } else { 8 };
+    let mut content = PAGE_SIZE;
+    for (i, cell) in cells.iter().enumerate() {
+        content -= cell.len();
+        page[content..content + cell.len()].copy_from_slice(cell);
+        let at = header_at + header_len + 2 * i;
+        page[at..at + 2].copy_from_slice(&(content as u16).to_be_bytes());
+    }
+    page[header_at] = kind;
+    page[header_at + 3..header_at + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    page[header_at + 5..header_at + 7].copy_from_slice(&(content as u16).to_be_bytes());
+    if let Some(right) = right_child {
+        page[header_at + 8..header_at + 12].copy_from_slice(&right.to_be_bytes());
+    }
+    page
+}
+
+fn fits(header_at: usize, header_len: usize, cells: &[Vec<u8>], extra: usize) -> bool {
+    header_at + header_len + cells.iter().map(|c| c.len() + 2).sum::<usize>() + extra + 2 <= PAGE_SIZE
+}
+
+/// Pages of the file being written, numbered in the order they are claimed.
+struct Pager {
+    file: File,
+    next: u32,
+}
+
+impl Pager {
+    fn claim(&mut self) -> u32 {
+        let number = self.next;
+        self.next += 1;
+        number
+    }
+
+    fn write(&mut self, number: u32, page: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start((number as u64 - 1) * PAGE_SIZE as u64))?;
+        self.file.write_all(page)?;
+        Ok(())
+    }
+
+    /// A table leaf cell for `payload`, spilling what doesn't fit on the
+    /// page into freshly written overflow pages.
+    fn table_cell(&mut self, rowid: i64, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut cell = Vec::new();
+        put_varint(&mut cell, payload.len() as u64);
+        put_varint(&mut cell, rowid as u64);
+        let local = local_payload_len(payload.len(), PAGE_SIZE);
+        cell.extend_from_slice(&payload[..local]);
+        if local < payload.len() {
+            let chunks: Vec<&[u8]> = payload[local..].chunks(PAGE_SIZE - 4).collect();
+            let first = self.next;
+            for (i, chunk) in chunks.iter().enumerate() {
+                let number = self.claim();
+                let next = if i + 1 < chunks.len() { number + 1 } else { 0 };
+                let mut page = vec![0u8; PAGE_SIZE];
+                page[..4].copy_from_slice(&next.to_be_bytes());
+                page[4..4 + chunk.len()].copy_from_slice(chunk);
+                self.write(number, &page)?;
+            }
+            cell.extend_from_slice(&first.to_be_bytes());
+        }
+        Ok(cell)
+    }
+
+    /// Writes the interior levels above `children` (`(page, largest rowid)`
+    /// of each leaf, in rowid order) and returns the root page.
+    fn table_tree(&mut self, mut children: Vec<(u32, i64)>) -> Result<u32> {
+        while children.len() > 1 {
+            let mut groups: Vec<Vec<(u32, i64)>> = vec![Vec::new()];
+            let mut used = 12;
+            for child in children {
+                let size = 4 + varint_len(child.1 as u64) + 2;
+                let group = groups.last_mut().unwrap();
+                if !group.is_empty() && used + size > PAGE_SIZE {
+                    groups.push(Vec::new());
+                    used = 12;
+                }
+                used += size;
+                groups.last_mut().unwrap().push(child);
+            }
+            // An interior page needs a cell besides its right-most child.
+            let n = groups.len();
+            if n > 1 && groups[n - 1].len() < 2 {
+                let moved = groups[n - 2].pop().unwrap();
+                groups[n - 1].insert(0, moved);
+            }
+            children = Vec::with_capacity(n);
+            for group in groups {
+                let (&(right, largest), left) = group.split_last().unwrap();
+                let cells: Vec<Vec<u8>> = left
+                    .iter()
+                    .map(|&(page, key)| {
+                        let mut cell = page.to_be_bytes().to_vec();
+                        put_varint(&mut cell, key as u64);
+                        cell
+                    })
+                    .collect();
+                let number = self.claim();
+                self.write(number, &btree_page(INTERIOR_TABLE, &cells, Some(right), 0))?;
+                children.push((number, largest));
+            }
+        }
+        Ok(children[0].0)
+    }
+
+    /// Writes an index b-tree over `keys` (encoded records, sorted) and
+    /// returns its root page. Keys are small, so none overflow.
+    fn index_tree(&mut self, keys: Vec<Vec<u8>>) -> Result<u32> {
+        let cell_of = |key: &[u8]| {
+            let mut cell = Vec::with_capacity(key.len() + 2);
+            put_varint(&mut cell, key.len() as u64);
+            cell.extend_from_slice(key);
+            cell
+        };
+
+        // Leaves, with the key between each pair moved up as a divider.
+        let (mut children, mut dividers) = (Vec::new(), Vec::new());
+        let mut cells: Vec<Vec<u8>> = Vec::new();
+        let mut i = 0;
+        while i < keys.len() {
+            let cell = cell_of(&keys[i]);
+            if cells.is_empty() || fits(0, 8, &cells, cell.len()) {
+                cells.push(cell);
+                i += 1;
+                continue;
+            }
+            // Never leave the last leaf empty: give it the last key instead.
+            let divider = if i + 1 == keys.len() {
+                cells.pop();
+                i - 1
+            } else {
+                i
+            };
+            let number = self.claim();
+            self.write(number, &btree_page(LEAF_INDEX, &cells, None, 0))?;
+            children.push(number);
+            dividers.push(keys[divider].clone());
+            cells.clear();
+            i = divider + 1;
+        }
+        let number = self.claim();
+        self.write(number, &btree_page(LEAF_INDEX, &cells, None, 0))?;
+        children.push(number);
+
+        while children.len() > 1 {
+            let (mut upper_children, mut upper_dividers) = (Vec::new(), Vec::new());
+            let mut cells: Vec<Vec<u8>> = Vec::new();
+            let mut j = 0;
+            while j < dividers.len() {
+                let mut cell = children[j].to_be_bytes().to_vec();
+                cell.extend(cell_of(&dividers[j]));
+                if cells.is_empty() || fits(0, 12, &cells, cell.len()) {
+                    cells.push(cell);
+                    j += 1;
+                    continue;
+                }
+                let up = if j + 1 == dividers.len() {
+                    cells.pop();
+                    j - 1
+                } else {
+                    j
+                };
+                let number = self.claim();
+                self.write(number, &btree_page(INTERIOR_INDEX, &cells, Some(children[up]), 0))?;
+                upper_children.push(number);
+                upper_dividers.push(dividers[up].clone());
+                cells.clear();
+                j = up + 1;
+            }
+            let number = self.claim();
+            let right = *children.last().unwrap();
+            self.write(number, &btree_page(INTERIOR_INDEX, &cells, Some(right), 0))?;
+            upper_children.push(number);
+            children = upper_children;
+            dividers = upper_dividers;
+        }
+        Ok(children[0])
+    }
+}
+
+/// Leaf level of a table b-tree being written: rows fill the current leaf,
+/// which is written out once the next row no longer fits.
+#[derive(Default)]
+struct TableLeaves {
+    cells: Vec<Vec<u8>>,
+    last_rowid: i64,
+    /// `(page, largest rowid)` of each written leaf
+    written: Vec<(u32, i64)>,
+}
+
+impl TableLeaves {
+    fn push(&mut self, pager: &mut Pager, record: &[u8]) -> Result<()> {
+        let rowid = self.last_rowid + 1;
+        let cell = pager.table_cell(rowid, record)?;
+        if !self.cells.is_empty() && !fits(0, 8, &self.cells, cell.len()) {
+            self.flush(pager)?;
+        }
+        self.cells.push(cell);
+        self.last_rowid = rowid;
+        Ok(())
+    }
+
+    fn flush(&mut self, pager: &mut Pager) -> Result<()> {
+        let number = pager.claim();
+        pager.write(number, &btree_page(LEAF_TABLE, &self.cells, None, 0))?;
+        self.written.push((number, self.last_rowid));
+        self.cells.clear();
+        Ok(())
+    }
+
+    /// Writes the last leaf and the levels above; returns the root page.
+    fn finish(mut self, pager: &mut Pager) -> Result<u32> {
+        if !self.cells.is_empty() || self.written.is_empty() {
+            self.flush(pager)?;
+        }
+        pager.table_tree(self.written)
+    }
+}
+
+/// Writes a new MBTiles file with `metadata` and `tiles` tables and the
+/// unique `tile_index` that MBTiles readers look tiles up by. Tiles go
+/// straight to disk as they are added; the file appears under its final
+/// name once [`Self::finish`] succeeds.
+pub struct MbtilesWriter {
+    path: PathBuf,
+    part: PathBuf,
+    pager: Pager,
+    tiles: TableLeaves,
+    /// `(zoom_level, tile_column, tile_row)` and rowid of every tile
+    keys: Vec<((u32, i32, i32), i64)>,
+    seen: HashSet<(u32, i32, i32)>,
+}
+
+impl MbtilesWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".part");
+        let part = path.with_file_name(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        let file = File::create(&part).with_context(|| format!("Creating {:?}", part))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            part,
+            // Page 1 holds the schema and is written last.
+            pager: Pager { file, next: 2 },
+            tiles: TableLeaves::default(),
+            keys: Vec::new(),
+            seen: HashSet::new(),
+        })
+    }
+
+    pub fn add(&mut self, tile: &MbTile) -> Result<()> {
+        let key = (tile.z, tile.column, tile.row);
+        if !self.seen.insert(key) {
+            return Err(anyhow!("Tile {}/{}/{} added twice", tile.z, tile.column, tile.row));
+        }
+        let record = encode_record(&[
+            SqlValue::Int(tile.z as i64),
+            SqlValue::Int(tile.column as i64),
+            SqlValue::Int(tile.row as i64),
+            SqlValue::Blob(tile.data.clone()),
+        ]);
+        self.tiles
+            .push(&mut self.pager, &record)
+            .with_context(|| format!("Writing {:?}", self.part))?;
+        self.keys.push((key, self.tiles.last_rowid));
+        Ok(())
+    }
+
+    /// Writes the metadata rows, the index and the schema, then moves the
+    /// file into place. Returns the number of tiles written.
+    pub fn finish(mut self, metadata: &[(String, String)]) -> Result<usize> {
+        let count = self.keys.len();
+        let written = (|| -> Result<()> {
+            let pager = &mut self.pager;
+            let tiles_root = std::mem::take(&mut self.tiles).finish(pager)?;
+
+            let mut rows = TableLeaves::default();
+            for (name, value) in metadata {
+                rows.push(pager, &encode_record(&[SqlValue::Text(name.clone()), SqlValue::Text(value.clone())]))?;
+            }
+            let metadata_root = rows.finish(pager)?;
+
+            self.keys.sort_unstable();
+            let keys = self
+                .keys
+                .iter()
+                .map(|&((z, column, row), rowid)| {
+                    encode_record(&[
+                        SqlValue::Int(z as i64),
+                        SqlValue::Int(column as i64),
+                        SqlValue::Int(row as i64),
+                        SqlValue::Int(rowid),
+                    ])
+                })
+                .collect();
+            let index_root = pager.index_tree(keys)?;
+
+            let schema = [
+                ("table", "metadata", "metadata", metadata_root, METADATA_SQL),
+                ("table", "tiles", "tiles", tiles_root, TILES_SQL),
+                ("index", "tile_index", "tiles", index_root, TILE_INDEX_SQL),
+            ];
+            let mut cells = Vec::new();
+            for (rowid, (kind, name, table, root, sql)) in schema.into_iter().enumerate() {
+                let record = encode_record(&[
+                    SqlValue::Text(kind.to_string()),
+                    SqlValue::Text(name.to_string()),
+                    SqlValue::Text(table.to_string()),
+                    SqlValue::Int(root as i64),
+                    SqlValue::Text(sql.to_string()),
+                ]);
+                cells.push(pager.table_cell(rowid as i64 + 1, &record)?);
+            }
+            let mut page = btree_page(LEAF_TABLE, &cells, None, 100);
+            page[..100].copy_from_slice(&file_header(pager.next - 1));
+            pager.write(1, &page)?;
+            pager.file.flush()?;
+            pager.file.sync_all()?;
+            Ok(())
+        })();
+        written.with_context(|| format!("Writing {:?}", self.part))?;
+        fs::rename(&self.part, &self.path)
+            .with_context(|| format!("Moving {:?} into place", self.part))?;
+        Ok(count)
+    }
+}
+
+/// The 100-byte SQLite header of a written file of `pages` pages.
+fn file_header(pages: u32) -> [u8; 100] {
+    let mut header = [0u8; 100];
+    header[..16].copy_from_slice(SQLITE_MAGIC);
+    header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    // Rollback journal for both the write and read format versions.
+    header[18] = 1;
+    header[19] = 1;
+    // Maximum, minimum and leaf payload fractions, fixed by the format.
+    header[21] = 64;
+    header[22] = 32;
+    header[23] = 32;
+    let mut put = |at: usize, value: u32| header[at..at + 4].copy_from_slice(&value.to_be_bytes());
+    put(24, 1); // file change counter
+    put(28, pages);
+    put(40, 1); // schema cookie
+    put(44, 4); // schema format
+    put(56, 1); // UTF-8
+    put(68, MBTILES_APPLICATION_ID);
+    put(92, 1); // version-valid-for, matching the change counter
+    put(96, SQLITE_VERSION_NUMBER);
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bing-test-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn tile_data(z: u32, column: i32, row: i32) -> Vec<u8> {
+        // Every 50th tile is several pages long, to exercise overflow chains.
+        let len = if column % 50 == 0 { 3 * PAGE_SIZE + 123 } else { 40 + (row as usize % 7) * 300 };
+        (0..len).map(|i| (i as u32 ^ z ^ column as u32 ^ (row as u32 * 7)) as u8).collect()
+    }
+
+    #[test]
+    fn written_tiles_and_metadata_read_back() {
+        let dir = scratch_dir("mbtiles-round-trip");
+        let path = dir.join("tiles.mbtiles");
+        let mut tiles = Vec::new();
+        // Enough tiles for interior table and index pages.
+        for column in 0..300 {
+            for row in 0..12 {
+                tiles.push(MbTile { z: 9, column, row, data: tile_data(9, column, row) });
+            }
+        }
+        tiles.push(MbTile { z: 0, column: 0, row: 0, data: Vec::new() });
+        let metadata = vec![
+            ("name".to_string(), "round trip".to_string()),
+            ("format".to_string(), "glb".to_string()),
+        ];
+
+        let mut writer = MbtilesWriter::create(&path).unwrap();
+        for tile in &tiles {
+            writer.add(tile).unwrap();
+        }
+        assert_eq!(writer.finish(&metadata).unwrap(), tiles.len());
+        assert!(!dir.join("tiles.mbtiles.part").exists());
+
+        let mut reader = MbtilesReader::open(&path).unwrap();
+        assert_eq!(reader.metadata().unwrap(), metadata);
+        let mut read = Vec::new();
+        reader.tiles(&mut |tile| {
+            read.push(tile);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(read, tiles);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn duplicate_tiles_are_rejected() {
+        let dir = scratch_dir("mbtiles-duplicate");
+        let mut writer = MbtilesWriter::create(&dir.join("tiles.mbtiles")).unwrap();
+        let tile = MbTile { z: 3, column: 1, row: 2, data: vec![1, 2, 3] };
+        writer.add(&tile).unwrap();
+        assert!(writer.add(&tile).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn columns_come_from_the_create_statement() {
+        let columns = TableColumns::parse(
+            "CREATE TABLE images (tile_id INTEGER PRIMARY KEY, tile_data BLOB, UNIQUE (tile_id))",
+        );
+        assert_eq!(columns.names, ["tile_id", "tile_data"]);
+        assert_eq!(columns.rowid_alias, Some(0));
+        assert_eq!(columns.get(&[SqlValue::Null, SqlValue::Blob(vec![7])], 0, 42), SqlValue::Int(42));
+    }
+
+    #[test]
+    fn varints_and_records_round_trip() {
+        for value in [0, 1, 127, 128, 16_383, 16_384, 1 << 40, u64::MAX >> 1, u64::MAX] {
+            let mut bytes = Vec::new();
+            put_varint(&mut bytes, value);
+            assert_eq!(get_varint(&bytes, 0), Some((value, bytes.len())), "{}", value);
+        }
+        let values = vec![
+            SqlValue::Null,
+            SqlValue::Int(0),
+            SqlValue::Int(1),
+            SqlValue::Int(-200),
+            SqlValue::Int(1 << 40),
+            SqlValue::Int(i64::MIN),
+            SqlValue::Real(0.5),
+            SqlValue::Text("zoom".to_string()),
+            SqlValue::Blob(vec![0; 200]),
+        ];
+        assert_eq!(decode_record(&encode_record(&values)), Some(values));
+    }
+}
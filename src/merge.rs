@@ -0,0 +1,258 @@
+use crate::glb::Glb;
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+/// A tile to merge plus the translation (in glTF units) of its root node.
+pub struct MergeInput {
+    pub path: PathBuf,
+    pub name: String,
+    pub translation: [f64; 3],
+}
+
+/// Offsets of each top-level glTF array before a tile is appended.
+#[derive(Clone, Copy)]
+struct Offsets {
+    accessors: u64,
+    buffer_views: u64,
+    images: u64,
+    samplers: u64,
+    textures: u64,
+    materials: u64,
+    meshes: u64,
+    nodes: u64,
+}
+
+const ARRAYS: [&str; 8] = [
+    "accessors",
+    "bufferViews",
+    "images",
+    "samplers",
+    "textures",
+    "materials",
+    "meshes",
+    "nodes",
+];
+
+/// Merges every input GLB into a single GLB with one shared buffer. Each tile's
+/// scene roots are parented under a new node carrying the tile's translation.
+///
+/// Cameras, skins and animations are dropped; tiles with more than one buffer
+/// or an external buffer URI are rejected.
+pub fn merge_glbs(inputs: &[MergeInput]) -> Result<Glb> {
+    let mut out: Map<String, Value> = Map::new();
+    for key in ARRAYS {
+        out.insert(key.to_string(), Value::Array(Vec::new()));
+    }
+    let mut bin: Vec<u8> = Vec::new();
+    let mut roots = Vec::new();
+    let mut used = BTreeSet::new();
+    let mut required = BTreeSet::new();
+
+    for input in inputs {
+        let glb = Glb::read(&input.path)?;
+        let mut doc = glb.json;
+
+        let buffers = doc.get("buffers").and_then(Value::as_array).map_or(0, Vec::len);
+        if buffers > 1 {
+            return Err(anyhow!(
+                "{:?} has {} buffers; only single-buffer tiles can be merged",
+                input.path,
+                buffers
+            ));
+        }
+        if doc.pointer("/buffers/0/uri").is_some() {
+            return Err(anyhow!("{:?} references an external buffer", input.path));
+        }
+
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+        let bin_base = bin.len() as u64;
+        bin.extend_from_slice(&glb.bin);
+
+        let off = Offsets {
+            accessors: array_len(&out, "accessors"),
+            buffer_views: array_len(&out, "bufferViews"),
+            images: array_len(&out, "images"),
+            samplers: array_len(&out, "samplers"),
+            textures: array_len(&out, "textures"),
+            materials: array_len(&out, "materials"),
+            meshes: array_len(&out, "meshes"),
+            nodes: array_len(&out, "nodes"),
+        };
+
+        for ext in ["extensionsUsed", "extensionsRequired"] {
+            let target = if ext == "extensionsUsed" { &mut used } else { &mut required };
+            if let Some(list) = doc.get(ext).and_then(Value::as_array) {
+                target.extend(list.iter().filter_map(Value::as_str).map(str::to_string));
+            }
+        }
+
+        // Roots of the tile's default scene.
+        let scene_idx = doc.get("scene").and_then(Value::as_u64).unwrap_or(0) as usize;
+        let tile_roots: Vec<u64> = match doc.pointer(&format!("/scenes/{}/nodes", scene_idx)) {
+            Some(Value::Array(nodes)) => nodes.iter().filter_map(Value::as_u64).collect(),
+            _ => Vec::new(),
+        };
+
+        for key in ARRAYS {
+            let Some(Value::Array(items)) = doc.get_mut(key).map(Value::take) else {
+                continue;
+            };
+            for mut item in items {
+                remap(key, &mut item, off, bin_base);
+                out.get_mut(key).unwrap().as_array_mut().unwrap().push(item);
+            }
+        }
+
+        let wrapper = array_len(&out, "nodes");
+        let children: Vec<u64> = tile_roots.iter().map(|n| n + off.nodes).collect();
+        out.get_mut("nodes").unwrap().as_array_mut().unwrap().push(json!({
+            "name": input.name,
+            "translation": input.translation,
+            "children": children,
+        }));
+        roots.push(wrapper);
+    }
+
+    // Empty arrays are not allowed by the glTF schema.
+    out.retain(|_, v| v.as_array().is_none_or(|a| !a.is_empty()));
+    out.insert("asset".into(), json!({ "version": "2.0", "generator": "bing merge" }));
+    out.insert("scene".into(), json!(0));
+    out.insert("scenes".into(), json!([{ "nodes": roots }]));
+    if !bin.is_empty() {
+        out.insert("buffers".into(), json!([{ "byteLength": bin.len() }]));
+    }
+    if !used.is_empty() {
+        out.insert("extensionsUsed".into(), json!(used));
+    }
+    if !required.is_empty() {
+        out.insert("extensionsRequired".into(), json!(required));
+    }
+
+    Ok(Glb {
+        json: Value::Object(out),
+        bin,
+    })
+}
+
+/// Merges `inputs` and writes the result to `out_path`.
+pub fn write_merged(inputs: &[MergeInput], out_path: &std::path::Path) -> Result<()> {
+    let merged = merge_glbs(inputs)?;
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+    merged
+        .write(out_path)
+        .with_context(|| format!("Writing merged GLB {:?}", out_path))
+}
+
+fn array_len(doc: &Map<String, Value>, key: &str) -> u64 {
+    doc.get(key).and_then(Value::as_array).map_or(0, |a| a.len() as u64)
+}
+
+fn shift(v: &mut Value, key: &str, by: u64) {
+    if let Some(n) = v.get(key).and_then(Value::as_u64) {
+        v[key] = json!(n + by);
+    }
+}
+
+/// Rewrites the cross-references of one item of the `key` array.
+fn remap(key: &str, item: &mut Value, off: Offsets, bin_base: u64) {
+    match key {
+        "accessors" => {
+            shift(item, "bufferView", off.buffer_views);
+            if let Some(sparse) = item.get_mut("sparse") {
+                for part in ["indices", "values"] {
+                    if let Some(p) = sparse.get_mut(part) {
+                        shift(p, "bufferView", off.buffer_views);
+                    }
+                }
+            }
+        }
+        "bufferViews" => {
+            item["buffer"] = json!(0);
+            let base = item.get("byteOffset").and_then(Value::as_u64).unwrap_or(0);
+            item["byteOffset"] = json!(base + bin_base);
+            if let Some(meshopt) = item.pointer_mut("/extensions/EXT_meshopt_compression") {
+                meshopt["buffer"] = json!(0);
+                let base = meshopt.get("byteOffset").and_then(Value::as_u64).unwrap_or(0);
+                meshopt["byteOffset"] = json!(base + bin_base);
+            }
+        }
+        "images" => shift(item, "bufferView", off.buffer_views),
+        "textures" => {
+            shift(item, "source", off.images);
+            shift(item, "sampler", off.samplers);
+            if let Some(Value::Object(exts)) = item.get_mut("extensions") {
+                for ext in exts.values_mut() {
+                    shift(ext, "source", off.images);
+                }
+            }
+        }
+        "materials" => remap_texture_infos(item, off.textures),
+        "meshes" => {
+            if let Some(Value::Array(prims)) = item.get_mut("primitives") {
+                for prim in prims {
+                    shift(prim, "indices", off.accessors);
+                    shift(prim, "material", off.materials);
+                    if let Some(Value::Object(attrs)) = prim.get_mut("attributes") {
+                        shift_all(attrs, off.accessors);
+                    }
+                    if let Some(Value::Array(targets)) = prim.get_mut("targets") {
+                        for target in targets {
+                            if let Value::Object(attrs) = target {
+                                shift_all(attrs, off.accessors);
+                            }
+                        }
+                    }
+                    if let Some(draco) = prim.pointer_mut("/extensions/KHR_draco_mesh_compression") {
+                        shift(draco, "bufferView", off.buffer_views);
+                    }
+                }
+            }
+        }
+        "nodes" => {
+            shift(item, "mesh", off.meshes);
+            if let Some(Value::Array(children)) = item.get_mut("children") {
+                for child in children {
+                    if let Some(n) = child.as_u64() {
+                        *child = json!(n + off.nodes);
+                    }
+                }
+            }
+            if let Value::Object(node) = item {
+                node.remove("camera");
+                node.remove("skin");
+            }
+        }
+        _ => {}
+    }
+}
+
+fn shift_all(map: &mut Map<String, Value>, by: u64) {
+    for v in map.values_mut() {
+        if let Some(n) = v.as_u64() {
+            *v = json!(n + by);
+        }
+    }
+}
+
+/// Shifts the `index` of every textureInfo (`baseColorTexture`, `normalTexture`,
+/// extension textures, ...) found anywhere inside a material.
+fn remap_texture_infos(v: &mut Value, by: u64) {
+    if let Value::Object(map) = v {
+        for (k, child) in map.iter_mut() {
+            if k.ends_with("Texture") && child.is_object() {
+                shift(child, "index", by);
+            }
+            remap_texture_infos(child, by);
+        }
+    } else if let Value::Array(items) = v {
+        for item in items {
+            remap_texture_infos(item, by);
+        }
+    }
+}
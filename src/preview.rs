@@ -0,0 +1,80 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const MODEL_VIEWER_URL: &str =
+    "https://ajax.googleapis.com/ajax/libs/model-viewer/3.5.0/model-viewer.min.js";
+
+/// A downloaded tile to show in the preview page.
+pub struct PreviewEntry {
+    pub name: String,
+    pub quadkey: String,
+    /// GLB path relative to the HTML file.
+    pub uri: String,
+    /// `(west, south, east, north)` in degrees.
+    pub bounds: (f64, f64, f64, f64),
+}
+
+/// Renders a self-contained HTML page with one lazily loaded `<model-viewer>`
+/// per tile, captioned with its geographic bounds and linked to the GLB.
+pub fn render_preview(entries: &[PreviewEntry]) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Tile preview ({count} tiles)</title>
+<script type="module" src="{viewer}"></script>
+<style>
+  body {{ font-family: sans-serif; margin: 1rem; background: #f4f4f4; }}
+  .grid {{ display: grid; grid-template-columns: repeat(auto-fill, minmax(260px, 1fr)); gap: 1rem; }}
+  figure {{ margin: 0; background: #fff; border-radius: 6px; overflow: hidden; box-shadow: 0 1px 3px rgba(0,0,0,.2); }}
+  model-viewer {{ width: 100%; height: 220px; background: #ddd; }}
+  figcaption {{ padding: .5rem; font-size: .8rem; line-height: 1.4; }}
+  code {{ font-size: .75rem; }}
+</style>
+</head>
+<body>
+<h1>Tile preview</h1>
+<p>{count} tiles. Models load as they scroll into view; drag to orbit.</p>
+<div class="grid">
+"#,
+        count = entries.len(),
+        viewer = MODEL_VIEWER_URL,
+    );
+
+    for e in entries {
+        let (west, south, east, north) = e.bounds;
+        let uri = escape(&e.uri);
+        let _ = writeln!(
+            html,
+            r#"<figure>
+  <model-viewer src="{uri}" alt="{name}" loading="lazy" reveal="interaction" camera-controls></model-viewer>
+  <figcaption><a href="{uri}">{name}</a><br><code>{quadkey}</code><br>{south:.6}, {west:.6} → {north:.6}, {east:.6}</figcaption>
+</figure>"#,
+            uri = uri,
+            name = escape(&e.name),
+            quadkey = escape(&e.quadkey),
+        );
+    }
+
+    html.push_str("</div>\n</body>\n</html>\n");
+    html
+}
+
+pub fn write_preview(entries: &[PreviewEntry], path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    fs::write(path, render_preview(entries)).with_context(|| format!("Writing preview {:?}", path))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,135 @@
+use crate::download::{
+    decode_body, parse_headers, validate_timeout, HostPool, KeyPool, TileForm, UrlTemplate, DEFAULT_API_KEY,
+    DEFAULT_HOSTS, DEFAULT_URL_TEMPLATE, USER_AGENT,
+};
+use crate::glb::check_header;
+use crate::stats::format_bytes;
+use crate::tile::quadkey_to_tile_xy;
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::time::Instant;
+
+/// Zoom-17 tile over the Sydney Opera House, which has 3D coverage.
+const PROBE_QUADKEY: &str = "31123013300223131";
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Tile to request; the default lies over a well-covered city
+    #[arg(long, default_value = PROBE_QUADKEY)]
+    pub quadkey: String,
+
+    /// Bing API key (repeat or comma-separate to probe several keys)
+    #[arg(long = "api-key", visible_alias = "api-keys", value_delimiter = ',', default_value = DEFAULT_API_KEY)]
+    pub api_key: Vec<String>,
+
+    /// Tile hosts to probe (repeatable or comma-separated)
+    #[arg(long = "hosts", visible_alias = "host", value_delimiter = ',', default_values = DEFAULT_HOSTS)]
+    pub hosts: Vec<String>,
+
+    /// Request URL template, as for `download --url-template`
+    #[arg(long = "url-template", default_value = DEFAULT_URL_TEMPLATE)]
+    pub url_template: String,
+
+    /// Payload to request: web3d (.glb meshes), jpeg or png imagery
+    #[arg(long = "form", value_enum, default_value_t = TileForm::Web3d)]
+    pub form: TileForm,
+
+    /// Per-request timeout in seconds
+    #[arg(long = "request-timeout", default_value_t = 30)]
+    pub request_timeout: u64,
+
+    /// User-Agent sent with every request
+    #[arg(long = "user-agent", default_value = USER_AGENT)]
+    pub user_agent: String,
+
+    /// Extra HTTP header sent with every request ("Name: Value", repeatable)
+    #[arg(long = "header")]
+    pub header: Vec<String>,
+}
+
+/// Requests one tile from every host with every key and reports the status,
+/// size and GLB header check of each response. Fails unless every probe
+/// returned a valid tile.
+pub async fn run_probe(args: Args) -> Result<()> {
+    let tile = quadkey_to_tile_xy(&args.quadkey)
+        .filter(|_| !args.quadkey.is_empty())
+        .ok_or_else(|| anyhow!("--quadkey must be a non-empty string of digits 0-3"))?;
+    let keys = KeyPool::new(args.api_key)?;
+    let hosts = HostPool::new(args.hosts)?;
+    let urls = UrlTemplate::new(&args.url_template, args.form)?;
+    let timeout = validate_timeout("--request-timeout", args.request_timeout)?;
+    let client = reqwest::Client::builder()
+        .user_agent(args.user_agent.as_str())
+        .default_headers(parse_headers(&args.header)?)
+        .timeout(timeout)
+        .build()?;
+
+    println!(
+        "Probing quadkey {} (z{} x{} y{}) on {} host(s) with {} key(s)",
+        args.quadkey,
+        tile.2,
+        tile.0,
+        tile.1,
+        hosts.len(),
+        keys.len()
+    );
+    let mut failed = 0;
+    let mut forbidden = 0;
+    for k in 0..keys.len() {
+        for h in 0..hosts.len() {
+            let url = urls.render(hosts.get(h), tile, &args.quadkey, keys.key(k));
+            let started = Instant::now();
+            let result = match client.get(&url).send().await {
+                Ok(resp) => {
+                    let status = resp.status();
+                    match resp.bytes().await {
+                        Ok(body) => Ok((status, body)),
+                        Err(e) => Err(format!("HTTP {}, reading body: {}", status, e)),
+                    }
+                }
+                Err(e) => Err(format!("{:#}", anyhow::Error::from(e))),
+            };
+            let ms = started.elapsed().as_millis();
+            let label = format!("{} key #{}", hosts.get(h), k + 1);
+            let verdict = match result {
+                Err(e) => Err(keys.mask(&e)),
+                Ok((status, _)) if matches!(status.as_u16(), 401 | 403) => {
+                    forbidden += 1;
+                    Err(format!("HTTP {}: key rejected", status))
+                }
+                Ok((status, _)) if !status.is_success() => Err(format!("HTTP {}", status)),
+                Ok((status, body)) if body.is_empty() => {
+                    Err(format!("HTTP {}, empty body: no tile here, try another --quadkey", status))
+                }
+                Ok((status, body)) => {
+                    let size = format_bytes(body.len() as u64);
+                    if args.form != TileForm::Web3d {
+                        Ok(format!("HTTP {}, {}", status, size))
+                    } else {
+                        match decode_body(&body).and_then(|b| check_header(&b)) {
+                            Ok(()) => Ok(format!("HTTP {}, {}, valid GLB", status, size)),
+                            Err(e) => Err(format!("HTTP {}, {}, invalid GLB: {:#}", status, size, e)),
+                        }
+                    }
+                }
+            };
+            match verdict {
+                Ok(text) => println!("OK   {}: {} ({} ms)", label, text, ms),
+                Err(text) => {
+                    failed += 1;
+                    println!("FAIL {}: {} ({} ms)", label, text, ms);
+                }
+            }
+        }
+    }
+
+    let total = keys.len() * hosts.len();
+    if failed == 0 {
+        println!("Probe passed: {}/{} requests returned a valid tile", total, total);
+        return Ok(());
+    }
+    if forbidden == total {
+        return Err(anyhow!("Probe failed: every request was rejected; check --api-key"));
+    }
+    Err(anyhow!("Probe failed: {} of {} requests did not return a valid tile", failed, total))
+}
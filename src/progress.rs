@@ -0,0 +1,124 @@
+use clap::ValueEnum;
+use anyhow::{anyhow, Result};
+use indicatif::{ProgressBar, ProgressDrawTarget};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often plain progress lines are printed.
+pub const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Checks a `--progress-interval` in milliseconds.
+pub fn interval(ms: Option<u64>) -> Result<Option<Duration>> {
+    match ms {
+        Some(0) => Err(anyhow!("--progress-interval must be greater than 0 ms")),
+        ms => Ok(ms.map(Duration::from_millis)),
+    }
+}
+
+/// Terminal draw target redrawing at most once per `interval`, or at
+/// indicatif's default rate without one.
+pub fn draw_target(interval: Option<Duration>) -> ProgressDrawTarget {
+    match interval {
+        Some(interval) => {
+            let hz = (1000 / interval.as_millis().max(1)).clamp(1, u8::MAX as u128);
+            ProgressDrawTarget::stderr_with_hz(hz as u8)
+        }
+        None => ProgressDrawTarget::stderr(),
+    }
+}
+
+/// Whether animated progress bars make sense: both stdout and stderr are
+/// terminals. Redirected output or CI logs get plain lines instead.
+pub fn interactive() -> bool {
+    std::io::stdout().is_terminal() && std::io::stderr().is_terminal()
+}
+
+/// `--color` setting shared by every subcommand.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    /// Color when writing to a terminal that supports it
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Switches ANSI styling in progress bars on or off for the whole
+    /// process. `Auto` keeps the terminal detection (which also honours
+    /// `NO_COLOR`/`CLICOLOR`).
+    pub fn apply(self) {
+        let enabled = match self {
+            ColorMode::Auto => return,
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        };
+        console::set_colors_enabled(enabled);
+        console::set_colors_enabled_stderr(enabled);
+    }
+}
+
+/// Prints `"<verb> 500/2000 (25%)"` to stdout every `interval`, summed over
+/// `bars`, until finished. Stands in for hidden progress bars when the output
+/// is not a terminal.
+pub struct PlainProgress {
+    verb: &'static str,
+    bars: Vec<ProgressBar>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PlainProgress {
+    pub fn start(verb: &'static str, bars: Vec<ProgressBar>, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let bars = bars.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut next = Instant::now() + interval;
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(100).min(interval));
+                    if Instant::now() >= next {
+                        println!("{}", line(verb, &bars));
+                        next += interval;
+                    }
+                }
+            })
+        };
+        Self {
+            verb,
+            bars,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Stops the reporter and prints a final line.
+    pub fn finish(mut self) {
+        self.stop_thread();
+        println!("{}", line(self.verb, &self.bars));
+    }
+
+    fn stop_thread(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+impl Drop for PlainProgress {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+fn line(verb: &str, bars: &[ProgressBar]) -> String {
+    let pos: u64 = bars.iter().map(ProgressBar::position).sum();
+    let len: u64 = bars.iter().filter_map(ProgressBar::length).sum();
+    let pct = if len == 0 { 100.0 } else { pos as f64 / len as f64 * 100.0 };
+    format!("{} {}/{} ({:.0}%)", verb, pos, len, pct)
+}
@@ -0,0 +1,236 @@
+use crate::decompress::has_glb_ext;
+use crate::tile::{lon_span_union, span_columns, tile_bounds};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Tile directory to analyze
+    #[arg(default_value = ".")]
+    pub dir: PathBuf,
+}
+
+/// Per-zoom tile extent.
+struct ZoomStats {
+    count: usize,
+    /// `(west, east)` of the tiles, merged with [`lon_span_union`] so a dump
+    /// straddling the antimeridian has west > east instead of spanning the
+    /// world
+    lon_span: (f64, f64),
+    min_y: i32,
+    max_y: i32,
+}
+
+impl ZoomStats {
+    fn new(x: i32, y: i32, z: u32) -> Self {
+        Self {
+            count: 1,
+            lon_span: lon_edges(x, z),
+            min_y: y,
+            max_y: y,
+        }
+    }
+
+    fn add(&mut self, x: i32, y: i32, z: u32) {
+        self.count += 1;
+        self.lon_span = lon_span_union(self.lon_span, lon_edges(x, z));
+        self.min_y = self.min_y.min(y);
+        self.max_y = self.max_y.max(y);
+    }
+
+    /// `x 14..=15 + 0..=1, y 5..=6 (4x2, 50.0% filled)`: the column ranges
+    /// (two across the antimeridian), the rows, and how much of that extent
+    /// is on disk.
+    fn describe(&self, z: u32) -> String {
+        let columns = span_columns(self.lon_span, z);
+        let width: i32 = columns.iter().map(|(first, last)| last - first + 1).sum();
+        let height = self.max_y - self.min_y + 1;
+        let fill = self.count as f64 / (width as f64 * height as f64) * 100.0;
+        let x: Vec<String> = columns.iter().map(|(first, last)| format!("{}..={}", first, last)).collect();
+        format!(
+            "x {}, y {}..={} ({}x{}, {:.1}% filled)",
+            x.join(" + "),
+            self.min_y,
+            self.max_y,
+            width,
+            height,
+            fill
+        )
+    }
+}
+
+fn lon_edges(x: i32, z: u32) -> (f64, f64) {
+    let (west, _, east, _) = tile_bounds(x, 0, z);
+    (west, east)
+}
+
+/// `(west, south, east, north)` covered by every zoom level, west-first
+/// across the antimeridian.
+fn coverage(zooms: &BTreeMap<u32, ZoomStats>) -> Option<(f64, f64, f64, f64)> {
+    let (west, east) = zooms.values().map(|s| s.lon_span).reduce(lon_span_union)?;
+    let south = zooms.iter().map(|(z, s)| tile_bounds(0, s.max_y, *z).1).fold(f64::INFINITY, f64::min);
+    let north = zooms.iter().map(|(z, s)| tile_bounds(0, s.min_y, *z).3).fold(f64::NEG_INFINITY, f64::max);
+    Some((west, south, east, north))
+}
+
+pub fn run_stats(args: Args) -> Result<()> {
+    if !args.dir.is_dir() {
+        return Err(anyhow!("{} is not a directory", args.dir.display()));
+    }
+
+    let mut sizes = Vec::new();
+    let mut zooms: BTreeMap<u32, ZoomStats> = BTreeMap::new();
+    let mut unrecognized = 0usize;
+
+    for entry in WalkDir::new(&args.dir).follow_links(true) {
+        let entry = entry?;
+        if !entry.file_type().is_file() || !has_glb_ext(entry.path()) {
+            continue;
+        }
+        sizes.push(entry.metadata()?.len());
+
+        let rel = entry.path().strip_prefix(&args.dir).unwrap_or(entry.path());
+        let Some((z, x, y)) = parse_tile_path(rel) else {
+            unrecognized += 1;
+            continue;
+        };
+        zooms
+            .entry(z)
+            .and_modify(|s| s.add(x, y, z))
+            .or_insert_with(|| ZoomStats::new(x, y, z));
+    }
+
+    println!("Directory: {}", args.dir.display());
+    println!("GLB files: {}", sizes.len());
+    if sizes.is_empty() {
+        return Ok(());
+    }
+
+    let total: u64 = sizes.iter().sum();
+    println!(
+        "Size: total {}, average {}, min {}, max {}",
+        format_bytes(total),
+        format_bytes(total / sizes.len() as u64),
+        format_bytes(*sizes.iter().min().unwrap()),
+        format_bytes(*sizes.iter().max().unwrap())
+    );
+
+    if zooms.is_empty() {
+        println!("No filenames matched the z_x_y.glb or z/x/y.glb layouts; tile coverage unknown");
+        return Ok(());
+    }
+    if unrecognized > 0 {
+        println!("Unrecognized filenames: {}", unrecognized);
+    }
+
+    let levels: Vec<String> = zooms.keys().map(u32::to_string).collect();
+    println!("Zoom levels: {}", levels.join(", "));
+
+    for (z, s) in &zooms {
+        println!("  z{}: {} tiles, {}", z, s.count, s.describe(*z));
+    }
+    if let Some((west, south, east, north)) = coverage(&zooms) {
+        println!(
+            "Coverage: SW {:.6},{:.6}  NE {:.6},{:.6}",
+            south, west, north, east
+        );
+    }
+
+    Ok(())
+}
+
+/// Extracts `(z, x, y)` from a tile path relative to the root, accepting the
+/// downloader's flat `z_x_y.glb` names (in any subfolder) and `z/x/y.glb`.
+/// Names with a zoom above 30 or a negative column or row are not tiles.
+pub(crate) fn parse_tile_path(rel: &Path) -> Option<(u32, i32, i32)> {
+    let stem = rel.file_stem()?.to_str()?;
+
+    let parts: Vec<&str> = stem.split('_').collect();
+    let (z, x, y): (u32, i32, i32) = if let [z, x, y] = parts[..] {
+        (z.parse().ok()?, x.parse().ok()?, y.parse().ok()?)
+    } else {
+        let y = stem.parse().ok()?;
+        let mut dirs = rel.parent()?.components().rev();
+        let x = dirs.next()?.as_os_str().to_str()?.parse().ok()?;
+        let z = dirs.next()?.as_os_str().to_str()?.parse().ok()?;
+        (z, x, y)
+    };
+    if z > 30 || x < 0 || y < 0 {
+        return None;
+    }
+    Some((z, x, y))
+}
+
+/// `12.3s`, `4m 05s` or `1h 02m` rendering of a duration in seconds.
+pub(crate) fn format_duration(secs: f64) -> String {
+    if secs < 60.0 {
+        return format!("{:.1}s", secs);
+    }
+    let secs = secs.round() as u64;
+    if secs < 3_600 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {:02}m", secs / 3_600, secs % 3_600 / 60)
+    }
+}
+
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tile_path_accepts_both_layouts() {
+        assert_eq!(parse_tile_path(Path::new("sub/12_345_678.glb")), Some((12, 345, 678)));
+        assert_eq!(parse_tile_path(Path::new("12/345/678.glb")), Some((12, 345, 678)));
+        assert_eq!(parse_tile_path(Path::new("30/0/0.glb")), Some((30, 0, 0)));
+    }
+
+    #[test]
+    fn parse_tile_path_rejects_out_of_range_names() {
+        for name in ["31_0_0.glb", "64_1_1.glb", "5_-1_0.glb", "5_0_-3.glb", "31/0/0.glb", "5/-1/0.glb", "5/0/-1.glb"] {
+            assert_eq!(parse_tile_path(Path::new(name)), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn a_dump_across_the_antimeridian_is_measured_west_first() {
+        // Columns 15 and 0 at z4 are neighbours across the antimeridian, not
+        // the two ends of a 16-column grid.
+        let mut zooms = BTreeMap::new();
+        let mut z4 = ZoomStats::new(15, 5, 4);
+        for (x, y) in [(0, 5), (15, 6), (0, 6)] {
+            z4.add(x, y, 4);
+        }
+        assert_eq!(z4.describe(4), "x 15..=15 + 0..=0, y 5..=6 (2x2, 100.0% filled)");
+        zooms.insert(4, z4);
+
+        let (west, south, east, north) = coverage(&zooms).unwrap();
+        assert_eq!((west, east), (157.5, -157.5));
+        assert_eq!(south, tile_bounds(0, 6, 4).1);
+        assert_eq!(north, tile_bounds(0, 5, 4).3);
+
+        // A level on one side keeps the union on that side of the line.
+        zooms.insert(5, ZoomStats::new(30, 10, 5));
+        assert_eq!(zooms[&5].describe(5), "x 30..=30, y 10..=10 (1x1, 100.0% filled)");
+        let (west, _, east, _) = coverage(&zooms).unwrap();
+        assert_eq!((west, east), (157.5, -157.5));
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Run-level record written by `download --summary-json`: the inputs, timing
+/// and totals of one run, without the per-tile list of the manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    /// `complete`, `partial`, `failed` or `aborted`
+    pub result: String,
+    /// UTC start and end times, `YYYY-MM-DDTHH:MM:SSZ`
+    pub started: String,
+    pub finished: String,
+    pub duration_secs: f64,
+    /// `[west, south, east, north]`, GeoJSON order: west > east when the
+    /// box crosses the antimeridian
+    pub bbox: [f64; 4],
+    pub zooms: Vec<u32>,
+    pub form: String,
+    pub concurrency: usize,
+    pub hosts: Vec<String>,
+    /// Keys passed through [`redact_key`]
+    pub api_keys: Vec<String>,
+    pub out: String,
+    /// Tiles queued for the run
+    pub tiles: usize,
+    pub saved: usize,
+    /// Tiles left alone: kept by the overwrite policy or unchanged (HTTP 304)
+    pub skipped: usize,
+    pub empty: usize,
+    pub failed: usize,
+    /// Tile count per status label, as in the printed histogram
+    pub statuses: BTreeMap<String, usize>,
+    /// Bytes of tile bodies downloaded
+    pub bytes: u64,
+}
+
+impl RunSummary {
+    pub fn to_json(&self) -> Value {
+        let secs = self.duration_secs.max(1e-3);
+        json!({
+            "result": self.result,
+            "started": self.started,
+            "finished": self.finished,
+            "duration_secs": self.duration_secs,
+            "args": {
+                "bbox": self.bbox,
+                "zooms": self.zooms,
+                "form": self.form,
+                "concurrency": self.concurrency,
+                "hosts": self.hosts,
+                "api_keys": self.api_keys,
+                "out": self.out,
+            },
+            "totals": {
+                "tiles": self.tiles,
+                "ok": self.saved,
+                "skipped": self.skipped,
+                "empty": self.empty,
+                "failed": self.failed,
+            },
+            "statuses": self.statuses,
+            "bytes": self.bytes,
+            "throughput": {
+                "tiles_per_sec": self.tiles as f64 / secs,
+                "bytes_per_sec": self.bytes as f64 / secs,
+            },
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        fs::write(path, serde_json::to_vec_pretty(&self.to_json())?)
+            .with_context(|| format!("Writing summary {:?}", path))
+    }
+}
+
+/// Hides all but the last four characters of an API key, so summaries can be
+/// archived without leaking it.
+pub fn redact_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("****{}", tail)
+}
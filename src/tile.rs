@@ -0,0 +1,470 @@
+use anyhow::{anyhow, Result};
+use std::f64::consts::PI;
+
+pub const EARTH_LAT_MAX: f64 = 85.05112878;
+pub const EARTH_CIRCUMFERENCE_M: f64 = 40_075_016.686;
+
+#[inline]
+pub fn meters_to_degrees(meters: f64, lat_deg: f64) -> (f64, f64) {
+    // Spherical approximations consistent with Web Mercator usage.
+    let r = EARTH_RADIUS_M;
+    let lat = meters / (r * PI / 180.0);
+    let lon = meters / (r * (lat_deg.to_radians().cos()) * PI / 180.0);
+    (lat, lon)
+}
+
+/// Mean Earth radius in metres, as used by [`haversine_distance`].
+pub const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Great-circle distance in metres between two points given in degrees, on a
+/// sphere of radius [`EARTH_RADIUS_M`]. Accurate to within about 0.5% of the
+/// ellipsoidal distance.
+pub fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = (lat2 - lat1).to_radians();
+    let dlambda = (lon2 - lon1).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+}
+
+#[inline]
+pub fn create_square_bbox(center_lat: f64, center_lon: f64, size_m: f64) -> (f64, f64, f64, f64) {
+    let half = size_m / 2.0;
+    let (dlat, dlon) = meters_to_degrees(half, center_lat);
+    (
+        center_lat - dlat,
+        center_lon - dlon,
+        center_lat + dlat,
+        center_lon + dlon,
+    )
+}
+
+#[inline]
+pub fn clamp_lat(lat: f64) -> f64 {
+    lat.clamp(-EARTH_LAT_MAX, EARTH_LAT_MAX)
+}
+
+/// Strict counterpart of [`clamp_lat`]: errors instead of clamping when `lat`
+/// lies outside the Web Mercator range of ±85.05112878°.
+pub fn checked_lat(lat: f64) -> Result<f64> {
+    if lat.abs() > EARTH_LAT_MAX {
+        return Err(anyhow!(
+            "Latitude {} is outside the Web Mercator range of ±{}",
+            lat,
+            EARTH_LAT_MAX
+        ));
+    }
+    Ok(lat)
+}
+
+#[inline]
+pub fn wrap_lon(lon: f64) -> f64 {
+    // Safe wrap into [-180, 180)
+    let mut l = lon % 360.0;
+    if l >= 180.0 {
+        l -= 360.0;
+    }
+    if l < -180.0 {
+        l += 360.0;
+    }
+    l
+}
+
+#[inline]
+pub fn lonlat_to_tile_xy(lon: f64, lat: f64, z: u32) -> (i32, i32) {
+    let lat = clamp_lat(lat);
+    let lon = wrap_lon(lon);
+    let n = (1u32 << z) as f64;
+    let lat_rad = lat.to_radians();
+
+    let xf = ((lon + 180.0) / 360.0) * n;
+    let yf = (0.5 - ( ( (PI / 4.0) + (lat_rad / 2.0) ).tan().ln() / (2.0 * PI) )) * n;
+
+    // Python's int() floors for positive values; ensure we floor.
+    (xf.floor() as i32, yf.floor() as i32)
+}
+
+/// Pixel size of a tile in Bing's Web Mercator tiling.
+pub const TILE_SIZE_PX: u32 = 256;
+
+/// Ground resolution in metres per pixel at latitude `lat` (degrees) and zoom
+/// `z`, for 256 px Web Mercator tiles. Multiply by [`TILE_SIZE_PX`] for the
+/// width of one tile on the ground.
+#[inline]
+pub fn ground_resolution(lat: f64, z: u32) -> f64 {
+    clamp_lat(lat).to_radians().cos() * EARTH_CIRCUMFERENCE_M
+        / (TILE_SIZE_PX as f64 * (1u64 << z) as f64)
+}
+
+/// Zoom in `1..=23` whose [`ground_resolution`] at `lat` is nearest to
+/// `meters_per_px`, compared on a log scale since each zoom halves it.
+pub fn zoom_for_resolution(lat: f64, meters_per_px: f64) -> u32 {
+    let z0 = ground_resolution(lat, 0);
+    (z0 / meters_per_px).log2().round().clamp(1.0, 23.0) as u32
+}
+
+/// Inverse of `lonlat_to_tile_xy` for fractional tile coordinates.
+#[inline]
+pub fn fractional_tile_to_lonlat(xf: f64, yf: f64, z: u32) -> (f64, f64) {
+    let n = (1u32 << z) as f64;
+    let lon = xf / n * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * yf / n)).sinh().atan().to_degrees();
+    (lon, lat)
+}
+
+/// Longitude/latitude of the NW corner of tile `(x, y)` at zoom `z`.
+#[inline]
+pub fn tile_xy_to_lonlat(x: i32, y: i32, z: u32) -> (f64, f64) {
+    fractional_tile_to_lonlat(x as f64, y as f64, z)
+}
+
+/// Longitude/latitude of the centre of tile `(x, y)` at zoom `z`.
+#[inline]
+pub fn tile_center(x: i32, y: i32, z: u32) -> (f64, f64) {
+    fractional_tile_to_lonlat(x as f64 + 0.5, y as f64 + 0.5, z)
+}
+
+/// Geographic bounds of tile `(x, y)` at zoom `z` as `(west, south, east, north)`.
+#[inline]
+pub fn tile_bounds(x: i32, y: i32, z: u32) -> (f64, f64, f64, f64) {
+    let (west, north) = tile_xy_to_lonlat(x, y, z);
+    let (east, south) = tile_xy_to_lonlat(x + 1, y + 1, z);
+    (west, south, east, north)
+}
+
+/// Decodes a quadkey into `(x, y, z)`. Returns `None` for any digit outside 0-3.
+pub fn quadkey_to_tile_xy(quadkey: &str) -> Option<(i32, i32, u32)> {
+    let mut x = 0i32;
+    let mut y = 0i32;
+    for digit in quadkey.bytes() {
+        let d = match digit {
+            b'0'..=b'3' => (digit - b'0') as i32,
+            _ => return None,
+        };
+        x = (x << 1) | (d & 1);
+        y = (y << 1) | (d >> 1);
+    }
+    Some((x, y, quadkey.len() as u32))
+}
+
+/// Geographic bounds of a quadkey's tile as `(west, south, east, north)`.
+pub fn quadkey_bounds(quadkey: &str) -> (f64, f64, f64, f64) {
+    let (x, y, z) = quadkey_to_tile_xy(quadkey).unwrap_or((0, 0, 0));
+    tile_bounds(x, y, z)
+}
+
+#[inline]
+pub fn tile_xy_to_quadkey(x: i32, y: i32, z: u32) -> String {
+    let mut q = String::with_capacity(z as usize);
+    let x_temp = x;
+    let y_temp = y;
+    for i in (1..=z).rev() {
+        let mask = 1 << (i - 1);
+        let mut digit = 0;
+        if (x_temp & mask) != 0 { digit += 1; }
+        if (y_temp & mask) != 0 { digit += 2; }
+        q.push(char::from(b'0' + digit));
+    }
+    q
+}
+
+/// Longest prefix shared by all `quadkeys`: the quadkey of the smallest
+/// single tile containing every one of them. Empty when they fall in
+/// different zoom-1 tiles, or when there are none.
+pub fn common_quadkey_prefix<S: AsRef<str>>(quadkeys: &[S]) -> String {
+    let Some((first, rest)) = quadkeys.split_first() else {
+        return String::new();
+    };
+    let first = first.as_ref();
+    let len = rest.iter().fold(first.len(), |len, q| {
+        first
+            .bytes()
+            .zip(q.as_ref().bytes())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+    first[..len].to_string()
+}
+
+/// Wraps a tile column into `0..2^z`, so columns east of the antimeridian
+/// continue from 0 again.
+#[inline]
+pub fn wrap_tile_x(x: i64, z: u32) -> i32 {
+    x.rem_euclid(1i64 << z) as i32
+}
+
+/// Inclusive `(x_min, x_max, y_min, y_max)` tile ranges covering a bbox at zoom
+/// `z`. `lon1` is taken as the west edge and `lon2` as the east edge when the
+/// box crosses the antimeridian (`lon1` east of `lon2`), giving two ranges.
+/// Columns are wrapped modulo `2^z`; a box 360° or more wide covers every
+/// column once.
+pub fn bbox_tile_ranges(lat1: f64, lon1: f64, lat2: f64, lon2: f64, z: u32) -> Vec<(i32, i32, i32, i32)> {
+    let n = 1i64 << z;
+    let a_lat = clamp_lat(lat1);
+    let b_lat = clamp_lat(lat2);
+    let (lat_min, lat_max) = if a_lat <= b_lat { (a_lat, b_lat) } else { (b_lat, a_lat) };
+
+    // Rows of the north and south edges, kept inside the grid where the
+    // latitude clamp lands exactly on the last row's boundary.
+    let y_of = |lat: f64| lonlat_to_tile_xy(0.0, lat, z).1.clamp(0, (n - 1) as i32);
+    let (y_min, y_max) = (y_of(lat_max), y_of(lat_min));
+
+    let (x_min, x_max) = if (lon2 - lon1).abs() >= 360.0 {
+        (0, n - 1)
+    } else {
+        let west = wrap_lon(lon1);
+        let mut east = wrap_lon(lon2);
+        if east < west {
+            east += 360.0;
+        }
+        let x_of = |lon: f64| ((lon + 180.0) / 360.0 * n as f64).floor() as i64;
+        // An east edge exactly on the antimeridian ends with the last column
+        // instead of spilling into column 0.
+        let x_east = if east == 180.0 { n - 1 } else { x_of(east) };
+        (x_of(west), x_east)
+    };
+
+    if x_max - x_min + 1 >= n {
+        return vec![(0, (n - 1) as i32, y_min, y_max)];
+    }
+    let start = wrap_tile_x(x_min, z);
+    let end = start as i64 + (x_max - x_min);
+    if end < n {
+        vec![(start, end as i32, y_min, y_max)]
+    } else {
+        vec![(start, (n - 1) as i32, y_min, y_max), (0, wrap_tile_x(end, z), y_min, y_max)]
+    }
+}
+
+/// `[west, south, east, north]` of a bbox given by two corners. As in
+/// [`bbox_tile_ranges`], `lon1` is the west edge and `lon2` the east edge, so
+/// a box crossing the antimeridian keeps west > east (the GeoJSON
+/// convention) instead of flipping to the other side of the globe.
+pub fn bbox_wsen(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> [f64; 4] {
+    [lon1, lat1.min(lat2), lon2, lat1.max(lat2)]
+}
+
+/// Smallest `(west, east)` longitude span covering both spans, each read
+/// eastwards from its west edge so that west > east crosses the
+/// antimeridian. Spans that together wrap the globe give `(-180, 180)`.
+pub fn lon_span_union(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let width = |(w, e): (f64, f64)| if e >= w { e - w } else { e - w + 360.0 };
+    let covers = |outer: (f64, f64), inner: (f64, f64)| {
+        width(outer) >= 360.0 || (inner.0 - outer.0).rem_euclid(360.0) + width(inner) <= width(outer)
+    };
+    [a, b, (a.0, b.1), (b.0, a.1)]
+        .into_iter()
+        .filter(|&span| covers(span, a) && covers(span, b))
+        .min_by(|&x, &y| width(x).total_cmp(&width(y)))
+        .unwrap_or((-180.0, 180.0))
+}
+
+/// `(west, east)` longitude span of tiles given as `(x, z)`, merged with
+/// [`lon_span_union`] so tiles either side of the antimeridian give a narrow
+/// span with west > east rather than the whole world. `None` without tiles.
+pub fn tiles_lon_span(tiles: impl IntoIterator<Item = (i32, u32)>) -> Option<(f64, f64)> {
+    tiles
+        .into_iter()
+        .map(|(x, z)| {
+            let (west, _, east, _) = tile_bounds(x, 0, z);
+            (west, east)
+        })
+        .reduce(lon_span_union)
+}
+
+/// Inclusive column ranges at zoom `z` of a span whose edges lie on tile
+/// edges there, as from [`tiles_lon_span`]: two when it crosses the
+/// antimeridian.
+pub fn span_columns((west, east): (f64, f64), z: u32) -> Vec<(i32, i32)> {
+    let n = 1i32 << z;
+    let edge = |lon: f64| ((lon + 180.0) / 360.0 * n as f64).round() as i32;
+    let (first, end) = (edge(west), edge(east));
+    if west < east {
+        vec![(first, end - 1)]
+    } else {
+        vec![(first, n - 1), (0, end - 1)]
+    }
+}
+
+/// Expands tile ranges into `(x, y)` pairs, dropping duplicates where the two
+/// antimeridian ranges overlap. Order of first appearance is preserved.
+pub fn iter_tiles_in_ranges(ranges: &[(i32, i32, i32, i32)]) -> Vec<(i32, i32)> {
+    tiles_in_ranges(ranges).collect()
+}
+
+/// Lazy form of [`iter_tiles_in_ranges`]: yields the same tiles in the same
+/// order without holding them. A tile is skipped when an earlier range
+/// already covered it.
+pub fn tiles_in_ranges(ranges: &[(i32, i32, i32, i32)]) -> impl Iterator<Item = (i32, i32)> + Send + '_ {
+    ranges.iter().enumerate().flat_map(move |(i, &(x_min, x_max, y_min, y_max))| {
+        (y_min..=y_max)
+            .flat_map(move |y| (x_min..=x_max).map(move |x| (x, y)))
+            .filter(move |&(x, y)| {
+                !ranges[..i]
+                    .iter()
+                    .any(|&(a, b, c, d)| (a..=b).contains(&x) && (c..=d).contains(&y))
+            })
+    })
+}
+
+pub fn range_tile_count(ranges: &[(i32, i32, i32, i32)]) -> usize {
+    ranges
+        .iter()
+        .map(|&(x_min, x_max, y_min, y_max)| {
+            ((x_max - x_min + 1).max(0) as usize) * ((y_max - y_min + 1).max(0) as usize)
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn checked_lat_accepts_the_mercator_limit_and_rejects_beyond() {
+        assert_eq!(checked_lat(85.05112878).unwrap(), 85.05112878);
+        assert_eq!(checked_lat(-85.05112878).unwrap(), -85.05112878);
+        assert_eq!(checked_lat(0.0).unwrap(), 0.0);
+        for lat in [85.05112879, -85.05112879, 90.0, -90.0, f64::INFINITY] {
+            assert!(checked_lat(lat).is_err(), "{}", lat);
+        }
+    }
+
+    #[test]
+    fn haversine_distance_matches_known_city_pairs() {
+        // (lat1, lon1, lat2, lon2, published distance in km)
+        let pairs = [
+            (51.5074, -0.1278, 48.8566, 2.3522, 344.0),     // London - Paris
+            (40.7128, -74.0060, 34.0522, -118.2437, 3944.0), // New York - Los Angeles
+            (-33.8688, 151.2093, -37.8136, 144.9631, 714.0), // Sydney - Melbourne
+        ];
+        for (lat1, lon1, lat2, lon2, km) in pairs {
+            let d = haversine_distance(lat1, lon1, lat2, lon2) / 1000.0;
+            assert!((d - km).abs() < km * 0.005, "{} km, expected about {} km", d, km);
+            assert_eq!(d, haversine_distance(lat2, lon2, lat1, lon1) / 1000.0);
+        }
+        assert_eq!(haversine_distance(12.0, 34.0, 12.0, 34.0), 0.0);
+        let half_way = haversine_distance(0.0, 0.0, 0.0, 180.0);
+        assert!((half_way - PI * EARTH_RADIUS_M).abs() < 1e-6);
+    }
+
+    #[test]
+    fn wide_antimeridian_bbox_yields_each_tile_once() {
+        // West edge at 10°E and east edge at 5°E: 355° of longitude, wrapping
+        // round through the antimeridian.
+        for z in 1..=6 {
+            let ranges = bbox_tile_ranges(-60.0, 10.0, 60.0, 5.0, z);
+            let tiles: Vec<_> = tiles_in_ranges(&ranges).collect();
+            let unique: HashSet<_> = tiles.iter().copied().collect();
+            assert_eq!(unique.len(), tiles.len(), "duplicate tiles at z{}", z);
+            let n = 1i32 << z;
+            assert!(tiles.iter().all(|&(x, _)| (0..n).contains(&x)));
+        }
+
+        // Narrower crossing box: two ranges meeting at the antimeridian.
+        let ranges = bbox_tile_ranges(-10.0, 100.0, 10.0, -100.0, 4);
+        assert_eq!(ranges.len(), 2);
+        let tiles: Vec<_> = tiles_in_ranges(&ranges).collect();
+        let unique: HashSet<_> = tiles.iter().copied().collect();
+        assert_eq!(unique.len(), tiles.len());
+        assert_eq!(tiles.len(), range_tile_count(&ranges));
+    }
+
+    #[test]
+    fn tile_corners_round_trip_through_lonlat_to_tile_xy() {
+        for z in [0, 1, 5, 12, 20] {
+            let n = 1i32 << z;
+            for &(x, y) in &[(0, 0), (n - 1, n - 1), (n / 2, n / 3), (n / 7, n - 1)] {
+                // The NW corner lies on the tile's own edges.
+                let (lon, lat) = tile_xy_to_lonlat(x, y, z);
+                assert_eq!(lonlat_to_tile_xy(lon + 1e-9, lat - 1e-9, z), (x, y), "NW corner z{} {},{}", z, x, y);
+
+                // The centre and every point just inside the bounds map back.
+                let (lon, lat) = tile_center(x, y, z);
+                assert_eq!(lonlat_to_tile_xy(lon, lat, z), (x, y));
+                let (west, south, east, north) = tile_bounds(x, y, z);
+                assert!(west < east && south < north);
+                let eps_lon = (east - west) * 1e-6;
+                let eps_lat = (north - south) * 1e-6;
+                for (lon, lat) in [
+                    (west + eps_lon, north - eps_lat),
+                    (east - eps_lon, north - eps_lat),
+                    (west + eps_lon, south + eps_lat),
+                    (east - eps_lon, south + eps_lat),
+                ] {
+                    assert_eq!(lonlat_to_tile_xy(lon, lat, z), (x, y), "bounds z{} {},{}", z, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbouring_tiles_share_edges() {
+        let (west, south, east, north) = tile_bounds(3, 5, 4);
+        assert_eq!(tile_bounds(4, 5, 4).0, east);
+        assert_eq!(tile_bounds(2, 5, 4).2, west);
+        assert_eq!(tile_bounds(3, 4, 4).1, north);
+        assert_eq!(tile_bounds(3, 6, 4).3, south);
+        let (west, south, east, north) = tile_bounds(0, 0, 0);
+        assert_eq!((west, east), (-180.0, 180.0));
+        assert!((north - EARTH_LAT_MAX).abs() < 1e-6 && (south + EARTH_LAT_MAX).abs() < 1e-6);
+    }
+
+    #[test]
+    fn bbox_wsen_keeps_antimeridian_boxes_unflipped() {
+        assert_eq!(bbox_wsen(-10.0, 170.0, 10.0, -170.0), [170.0, -10.0, -170.0, 10.0]);
+        assert_eq!(bbox_wsen(10.0, 1.0, -10.0, 2.0), [1.0, -10.0, 2.0, 10.0]);
+    }
+
+    #[test]
+    fn lon_span_union_takes_the_short_way_round() {
+        assert_eq!(lon_span_union((0.0, 10.0), (20.0, 30.0)), (0.0, 30.0));
+        assert_eq!(lon_span_union((0.0, 10.0), (2.0, 5.0)), (0.0, 10.0));
+        // Both sides of the antimeridian: joined across it, not across 0°.
+        assert_eq!(lon_span_union((170.0, 175.0), (-175.0, -170.0)), (170.0, -170.0));
+        assert_eq!(lon_span_union((170.0, -170.0), (160.0, 165.0)), (160.0, -170.0));
+        assert_eq!(lon_span_union((170.0, -170.0), (-175.0, -160.0)), (170.0, -160.0));
+        // Crossing spans that meet on both sides cover the globe.
+        assert_eq!(lon_span_union((0.0, -90.0), (-100.0, 10.0)), (-180.0, 180.0));
+    }
+
+    #[test]
+    fn global_bbox_at_zoom_2_covers_all_sixteen_quadkeys() {
+        let ranges = bbox_tile_ranges(-EARTH_LAT_MAX, -180.0, EARTH_LAT_MAX, 180.0, 2);
+        assert_eq!(ranges, vec![(0, 3, 0, 3)]);
+        let tiles: Vec<_> = tiles_in_ranges(&ranges).collect();
+        assert_eq!(tiles.len(), 16);
+        let mut quadkeys: Vec<String> = tiles.iter().map(|&(x, y)| tile_xy_to_quadkey(x, y, 2)).collect();
+        quadkeys.sort();
+        let expected: Vec<String> = (0..16).map(|i| format!("{}{}", i / 4, i % 4)).collect();
+        assert_eq!(quadkeys, expected);
+        assert_eq!(tile_xy_to_quadkey(0, 0, 2), "00");
+        assert_eq!(tile_xy_to_quadkey(3, 0, 2), "11");
+        assert_eq!(tile_xy_to_quadkey(1, 2, 2), "21");
+        assert_eq!(tile_xy_to_quadkey(3, 3, 2), "33");
+    }
+
+    #[test]
+    fn overlapping_ranges_are_deduplicated_in_order() {
+        let ranges = [(0, 2, 0, 0), (1, 3, 0, 0)];
+        let tiles: Vec<_> = tiles_in_ranges(&ranges).collect();
+        assert_eq!(tiles, vec![(0, 0), (1, 0), (2, 0), (3, 0)]);
+        assert_eq!(iter_tiles_in_ranges(&ranges), tiles);
+    }
+
+    #[test]
+    fn tiles_either_side_of_the_antimeridian_span_it_west_first() {
+        // Quadkeys 1111 and 0000: the top corners of z4, x 15 and x 0.
+        let span = tiles_lon_span([(15, 4), (0, 4)]).unwrap();
+        assert_eq!(span, (157.5, -157.5));
+        assert_eq!(span_columns(span, 4), [(15, 15), (0, 0)]);
+
+        let span = tiles_lon_span([(3, 4), (5, 4), (4, 4)]).unwrap();
+        assert_eq!(span, (-112.5, -45.0));
+        assert_eq!(span_columns(span, 4), [(3, 5)]);
+        assert_eq!(span_columns(tiles_lon_span([(0, 0)]).unwrap(), 0), [(0, 0)]);
+        assert_eq!(tiles_lon_span([]), None);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::tile::{common_quadkey_prefix, EARTH_CIRCUMFERENCE_M};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// Bing does not report tile heights, so regions use a generous fixed range.
+const MIN_HEIGHT_M: f64 = -500.0;
+const MAX_HEIGHT_M: f64 = 9_000.0;
+
+/// A downloaded tile to expose in the tileset.
+pub struct TilesetEntry {
+    pub quadkey: String,
+    pub uri: String,
+}
+
+/// Builds a Cesium 3D Tiles 1.1 `tileset.json` document. Tiles are arranged as
+/// a quadtree following their quadkeys: every prefix shared by at least one
+/// tile becomes an interior node and each tile is a leaf with GLB content.
+///
+/// `bounds` maps a quadkey to its `(west, south, east, north)` in degrees.
+pub fn build_tileset<F>(entries: &[TilesetEntry], bounds: F) -> Value
+where
+    F: Fn(&str) -> (f64, f64, f64, f64),
+{
+    let uris: BTreeMap<&str, &str> = entries
+        .iter()
+        .map(|e| (e.quadkey.as_str(), e.uri.as_str()))
+        .collect();
+    let quadkeys: Vec<&str> = uris.keys().copied().collect();
+    let root_prefix = common_quadkey_prefix(&quadkeys);
+    let root = build_node(&root_prefix, &uris, &bounds);
+    json!({
+        "asset": { "version": "1.1", "generator": "bing" },
+        "geometricError": geometric_error(root_prefix.len()),
+        "root": root,
+    })
+}
+
+pub fn write_tileset<F>(entries: &[TilesetEntry], bounds: F, path: &Path) -> Result<()>
+where
+    F: Fn(&str) -> (f64, f64, f64, f64),
+{
+    let doc = build_tileset(entries, bounds);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    fs::write(path, serde_json::to_vec_pretty(&doc)?)
+        .with_context(|| format!("Writing tileset {:?}", path))
+}
+
+fn build_node<F>(prefix: &str, uris: &BTreeMap<&str, &str>, bounds: &F) -> Value
+where
+    F: Fn(&str) -> (f64, f64, f64, f64),
+{
+    let (west, south, east, north) = bounds(prefix);
+    let mut node = json!({
+        "boundingVolume": {
+            "region": [
+                west.to_radians(),
+                south.to_radians(),
+                east.to_radians(),
+                north.to_radians(),
+                MIN_HEIGHT_M,
+                MAX_HEIGHT_M,
+            ]
+        },
+        "geometricError": 0.0,
+        "refine": "ADD",
+    });
+
+    if let Some(uri) = uris.get(prefix) {
+        node["content"] = json!({ "uri": uri });
+    }
+
+    let children: Vec<Value> = ['0', '1', '2', '3']
+        .iter()
+        .map(|digit| format!("{}{}", prefix, digit))
+        .filter(|child| {
+            uris.range(child.as_str()..)
+                .next()
+                .is_some_and(|(qk, _)| qk.starts_with(child.as_str()))
+        })
+        .map(|child| build_node(&child, uris, bounds))
+        .collect();
+
+    if !children.is_empty() {
+        node["geometricError"] = json!(geometric_error(prefix.len()));
+        node["children"] = Value::Array(children);
+        // A tile with content and finer children (multi-zoom downloads) is a
+        // lower level of detail that its children should replace.
+        if uris.contains_key(prefix) {
+            node["refine"] = json!("REPLACE");
+        }
+    }
+    node
+}
+
+/// Error for an interior node at `level`: roughly 16 pixels of a 256 px tile.
+fn geometric_error(level: usize) -> f64 {
+    EARTH_CIRCUMFERENCE_M / (1u64 << level) as f64 / 16.0
+}
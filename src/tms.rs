@@ -0,0 +1,82 @@
+use crate::tile::TILE_SIZE_PX;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+/// Half the width of the Web Mercator plane in metres (EPSG:3857).
+const ORIGIN_SHIFT_M: f64 = 20_037_508.342_789_244;
+/// Standardized rendering pixel size used by OGC scale denominators.
+const PIXEL_SIZE_M: f64 = 0.000_28;
+
+/// Tile column/row extent of one zoom level, inclusive.
+pub struct MatrixLimits {
+    pub z: u32,
+    pub min_col: i32,
+    pub max_col: i32,
+    pub min_row: i32,
+    pub max_row: i32,
+}
+
+/// Builds an OGC TileMatrixSet 2.0 JSON document for the WebMercatorQuad set,
+/// restricted to the zooms in `limits`, with `tileMatrixSetLimits` and a CRS84
+/// bounding box describing the area covered.
+///
+/// `bbox` is `[west, south, east, north]`; west > east for a box crossing
+/// the antimeridian, as in GeoJSON.
+pub fn build_tms_metadata(bbox: [f64; 4], limits: &[MatrixLimits]) -> Value {
+    let matrices: Vec<Value> = limits
+        .iter()
+        .map(|l| {
+            let cell_size = 2.0 * ORIGIN_SHIFT_M / (TILE_SIZE_PX as f64 * (1u64 << l.z) as f64);
+            json!({
+                "id": l.z.to_string(),
+                "scaleDenominator": cell_size / PIXEL_SIZE_M,
+                "cellSize": cell_size,
+                "cornerOfOrigin": "topLeft",
+                "pointOfOrigin": [-ORIGIN_SHIFT_M, ORIGIN_SHIFT_M],
+                "tileWidth": TILE_SIZE_PX,
+                "tileHeight": TILE_SIZE_PX,
+                "matrixWidth": 1u64 << l.z,
+                "matrixHeight": 1u64 << l.z,
+            })
+        })
+        .collect();
+    let set_limits: Vec<Value> = limits
+        .iter()
+        .map(|l| {
+            json!({
+                "tileMatrix": l.z.to_string(),
+                "minTileRow": l.min_row,
+                "maxTileRow": l.max_row,
+                "minTileCol": l.min_col,
+                "maxTileCol": l.max_col,
+            })
+        })
+        .collect();
+    json!({
+        "id": "WebMercatorQuad",
+        "title": "Google Maps Compatible for the World",
+        "uri": "http://www.opengis.net/def/tilematrixset/OGC/1.0/WebMercatorQuad",
+        "crs": "http://www.opengis.net/def/crs/EPSG/0/3857",
+        "orderedAxes": ["X", "Y"],
+        "wellKnownScaleSet": "http://www.opengis.net/def/wkss/OGC/1.0/GoogleMapsCompatible",
+        "boundingBox": {
+            "lowerLeft": [bbox[0], bbox[1]],
+            "upperRight": [bbox[2], bbox[3]],
+            "crs": "http://www.opengis.net/def/crs/OGC/1.3/CRS84",
+            "orderedAxes": ["Lon", "Lat"],
+        },
+        "tileMatrices": matrices,
+        "tileMatrixSetLimits": set_limits,
+    })
+}
+
+pub fn write_tms_metadata(bbox: [f64; 4], limits: &[MatrixLimits], path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    let doc = build_tms_metadata(bbox, limits);
+    fs::write(path, serde_json::to_vec_pretty(&doc)?)
+        .with_context(|| format!("Writing TileMatrixSet metadata {:?}", path))
+}
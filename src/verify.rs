@@ -0,0 +1,174 @@
+use crate::checksum::ChecksumAlgorithm;
+use crate::decompress::has_glb_ext;
+use crate::glb::check_header;
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use flate2::read::MultiGzDecoder;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub(crate) const CHECKSUMS_FILE: &str = "checksums.txt";
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Tile directory to verify
+    #[arg(default_value = ".")]
+    pub dir: PathBuf,
+}
+
+enum Status {
+    Valid,
+    Corrupt(String),
+}
+
+pub fn run_verify(args: Args) -> Result<()> {
+    let checksums_path = args.dir.join(CHECKSUMS_FILE);
+    let checksums = if checksums_path.is_file() {
+        Some(read_checksums(&checksums_path)?)
+    } else {
+        None
+    };
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&args.dir).follow_links(true) {
+        let entry = entry?;
+        if entry.file_type().is_file() && (has_glb_ext(entry.path()) || is_gzipped_glb(entry.path())) {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+
+    println!("GLB files: {}", files.len());
+    if let Some((algorithm, c)) = &checksums {
+        println!("Checksums: {} {} entries from {}", c.len(), algorithm.as_str(), checksums_path.display());
+    }
+
+    let results: Vec<(String, Status)> = files
+        .par_iter()
+        .map(|path| {
+            let rel = relative_key(path, &args.dir);
+            let expected = checksums.as_ref().and_then(|(algorithm, c)| Some((*algorithm, c.get(&rel)?)));
+            let status = match verify_file(path, expected) {
+                Ok(()) => Status::Valid,
+                Err(e) => Status::Corrupt(format!("{:#}", e)),
+            };
+            (rel, status)
+        })
+        .collect();
+
+    let present: BTreeSet<&str> = results.iter().map(|(rel, _)| rel.as_str()).collect();
+    let missing: Vec<&String> = checksums
+        .as_ref()
+        .map(|(_, c)| c.keys().filter(|k| !present.contains(k.as_str())).collect())
+        .unwrap_or_default();
+
+    let valid = results.iter().filter(|(_, s)| matches!(s, Status::Valid)).count();
+    let corrupt: Vec<(&String, &String)> = results
+        .iter()
+        .filter_map(|(rel, s)| match s {
+            Status::Corrupt(reason) => Some((rel, reason)),
+            Status::Valid => None,
+        })
+        .collect();
+
+    for (rel, reason) in &corrupt {
+        eprintln!("CORRUPT {}: {}", rel, reason);
+    }
+    for rel in &missing {
+        eprintln!("MISSING {}", rel);
+    }
+    println!(
+        "Valid: {}, corrupt: {}, missing: {}",
+        valid,
+        corrupt.len(),
+        missing.len()
+    );
+
+    if corrupt.is_empty() && missing.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Verification failed. See errors above."))
+    }
+}
+
+/// `*.glb.gz`, as written by `download --gzip`.
+fn is_gzipped_glb(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.to_ascii_lowercase().ends_with(".glb.gz"))
+}
+
+/// Checks the GLB header, inside the gzip stream for `.glb.gz` files. The
+/// checksum is of the file as stored.
+fn verify_file(path: &Path, expected: Option<(ChecksumAlgorithm, &String)>) -> Result<()> {
+    let bytes = fs::read(path).with_context(|| format!("Reading {:?}", path))?;
+    if is_gzipped_glb(path) {
+        let mut glb = Vec::new();
+        MultiGzDecoder::new(&bytes[..])
+            .read_to_end(&mut glb)
+            .context("invalid gzip stream")?;
+        check_header(&glb)?;
+    } else {
+        check_header(&bytes)?;
+    }
+    if let Some((algorithm, expected)) = expected {
+        let actual = algorithm.hex(&bytes);
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(anyhow!("checksum mismatch (expected {}, got {})", expected, actual));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Path of `path` relative to `root` with forward slashes, as written in
+/// `checksums.txt`.
+pub(crate) fn relative_key(path: &Path, root: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Reads a `sha256sum`-style file: `<hex>  <path>` (or `<hex> *<path>`) per
+/// line. Blank lines and `#` comments are ignored. The algorithm comes from
+/// a `# algorithm: <name>` header, as `decompress --checksum-outputs`
+/// writes, or else is guessed from the digest length.
+pub(crate) fn read_checksums(path: &Path) -> Result<(ChecksumAlgorithm, BTreeMap<String, String>)> {
+    let text = fs::read_to_string(path).with_context(|| format!("Reading {:?}", path))?;
+    let mut algorithm = None;
+    let mut out = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('#').and_then(|c| c.trim().strip_prefix("algorithm:")) {
+            let parsed = ChecksumAlgorithm::parse(name)
+                .ok_or_else(|| anyhow!("{}:{}: unknown checksum algorithm {:?}", path.display(), i + 1, name.trim()))?;
+            algorithm = Some(parsed);
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (hash, file) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow!("{}:{}: expected '<hash>  <path>'", path.display(), i + 1))?;
+        let file = file.trim_start().trim_start_matches('*');
+        out.insert(file.replace('\\', "/"), hash.to_string());
+    }
+    let algorithm = algorithm
+        .or_else(|| out.values().next().and_then(|hash| ChecksumAlgorithm::from_hex_len(hash.len())))
+        .unwrap_or_default();
+    Ok((algorithm, out))
+}